@@ -0,0 +1,550 @@
+//! Typed calls to the server's API, one function per route. The routes are
+//! documented in `server/src/main.rs` and `server/src/rest.rs`, and served
+//! as an OpenAPI document at `/openapi.json`.
+//!
+//! A `Call` is a request and how to read its response, without sending it,
+//! so the same calls work natively and from the wasm frontend. Natively,
+//! `native::Http` sends them:
+//!
+//! ```no_run
+//! use model::SpaceId;
+//! let server = client::native::Http::new("127.0.0.1:8000");
+//! let info = server.send(client::space(SpaceId(0)).info()).unwrap();
+//! ```
+//!
+//! In the frontend, send `method`, `path` and `body` with any HTTP client
+//! and hand the status and body to `Call::response`. Pushed updates from
+//! `/tasks/events` are server-sent events, read them with an `EventSource`.
+
+use model::api::{
+    AccountInfo, Credentials, Invite, Member, Operation, PasswordChange, Role, SaveStatus,
+    SnapshotInfo, SpaceInfo,
+};
+use model::interchange::{Format, Imported};
+use model::{OrderedTasks, RankPoint, SimpleAthensSpace, SpaceId, Task, TaskId, Timestamp};
+use model::{User, UserId};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt;
+
+#[cfg(feature = "native")]
+pub mod native;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Body {
+    pub content_type: &'static str,
+    pub text: String,
+}
+
+/// A request to the server, and how to read its response as a `T`.
+pub struct Call<T> {
+    pub method: Method,
+    /// The path and query, like `/space/0/task?from=10`.
+    pub path: String,
+    pub body: Option<Body>,
+    read: fn(&str) -> Result<T, String>,
+}
+
+impl<T> Call<T> {
+    /// What the server's answer means for this call.
+    pub fn response(&self, status: u16, body: &str) -> Result<T, Error> {
+        match status {
+            200..=299 => (self.read)(body).map_err(Error::Decode),
+            _ => Err(Error::Status(status, body.to_string())),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Call<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method.as_str(), self.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The server refused the call with this status and body. A rejected
+    /// save carries the current space, for example.
+    Status(u16, String),
+    /// The response is not what the call returns.
+    Decode(String),
+    /// The call did not reach the server, or its answer got lost.
+    Transport(String),
+}
+
+impl Error {
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Status(status, _) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Status(status, body) => write!(f, "Server answered {}: {}", status, body),
+            Error::Decode(e) => write!(f, "Bad response: {}", e),
+            Error::Transport(e) => write!(f, "Failed to reach the server: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+fn json<T: DeserializeOwned>(body: &str) -> Result<T, String> {
+    serde_json::from_str(body).map_err(|e| e.to_string())
+}
+
+fn text(body: &str) -> Result<String, String> {
+    Ok(body.to_string())
+}
+
+fn nothing(_: &str) -> Result<(), String> {
+    Ok(())
+}
+
+fn get<T: DeserializeOwned>(path: String) -> Call<T> {
+    Call {
+        method: Method::Get,
+        path,
+        body: None,
+        read: json::<T>,
+    }
+}
+
+fn post<T: DeserializeOwned>(path: String) -> Call<T> {
+    Call {
+        method: Method::Post,
+        ..get(path)
+    }
+}
+
+fn post_json<T: DeserializeOwned>(path: String, body: &impl Serialize) -> Call<T> {
+    let text = serde_json::to_string(body).expect("Failed to serialize request");
+    Call {
+        body: Some(Body {
+            content_type: "application/json",
+            text,
+        }),
+        ..post(path)
+    }
+}
+
+fn post_text<T: DeserializeOwned>(path: String, text: &str) -> Call<T> {
+    Call {
+        body: Some(Body {
+            content_type: "text/plain",
+            text: text.to_string(),
+        }),
+        ..post(path)
+    }
+}
+
+fn download(path: String) -> Call<String> {
+    Call {
+        read: text,
+        ..get(path)
+    }
+}
+
+/// Appends the query parameters that are given to the path.
+fn query(path: String, params: &[(&str, Option<String>)]) -> String {
+    let params: Vec<String> = params
+        .iter()
+        .filter_map(|(name, value)| Some(format!("{}={}", name, encode(value.as_ref()?))))
+        .collect();
+    match params.is_empty() {
+        true => path,
+        false => format!("{}?{}", path, params.join("&")),
+    }
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn time(time: Option<Timestamp>) -> Option<String> {
+    time.map(|t| t.0.to_string())
+}
+
+/// A role as the server reads it in queries, like `voter`.
+fn role(role: Role) -> String {
+    let role = serde_json::to_value(role).expect("Failed to serialize role");
+    role.as_str().unwrap_or_default().to_string()
+}
+
+/// The whole default space, `None` before anything was saved.
+pub fn load_tasks() -> Call<Option<SimpleAthensSpace>> {
+    fn read(body: &str) -> Result<Option<SimpleAthensSpace>, String> {
+        match body {
+            "" => Ok(None),
+            body => json(body).map(Some),
+        }
+    }
+    Call {
+        read,
+        ..get("/tasks".to_string())
+    }
+}
+
+/// Replaces the default space. It has to be based on the latest version,
+/// otherwise the server answers 409 with the latest.
+pub fn save_tasks(space: &SimpleAthensSpace) -> Call<SaveStatus> {
+    post_json("/tasks".to_string(), space)
+}
+
+/// Applies an edit made against version `base`. The server answers 409 if
+/// what it refers to changed since, see `Operation::overtaken_by`.
+pub fn apply(op: &Operation, base: u64) -> Call<SaveStatus> {
+    let base = Some(base.to_string());
+    post_json(query("/tasks/op".to_string(), &[("base", base)]), op)
+}
+
+/// The default space's versions, waiting for `wait_for` to be written to
+/// disk if given.
+pub fn save_status(wait_for: Option<u64>) -> Call<SaveStatus> {
+    let wait_for = wait_for.map(|v| v.to_string());
+    get(query(
+        "/tasks/status".to_string(),
+        &[("wait_for", wait_for)],
+    ))
+}
+
+pub fn importance_at(at: Option<Timestamp>) -> Call<OrderedTasks> {
+    get(query(
+        "/history/importance".to_string(),
+        &[("at", time(at))],
+    ))
+}
+
+pub fn task_history(
+    task: TaskId,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+) -> Call<Vec<RankPoint>> {
+    let path = format!("/history/task/{}", task.0);
+    get(query(path, &[("from", time(from)), ("to", time(to))]))
+}
+
+pub fn export(format: Format, user: Option<UserId>) -> Call<String> {
+    let path = format!("/export/{}", format.extension());
+    download(query(path, &[("user", user.map(|u| u.0.to_string()))]))
+}
+
+pub fn import(format: Format, input: &str) -> Call<Imported> {
+    post_text(format!("/import/{}", format.extension()), input)
+}
+
+/// Imports an issue tracker's JSON export.
+pub fn import_issues(input: &str) -> Call<Imported> {
+    post_text("/import/issues".to_string(), input)
+}
+
+pub fn task_feed() -> Call<String> {
+    download("/tasks.ics".to_string())
+}
+
+/// Registers and logs in, the session is kept in a cookie.
+pub fn register(credentials: &Credentials) -> Call<AccountInfo> {
+    post_json("/account/register".to_string(), credentials)
+}
+
+pub fn login(credentials: &Credentials) -> Call<AccountInfo> {
+    post_json("/account/login".to_string(), credentials)
+}
+
+pub fn logout() -> Call<()> {
+    Call {
+        read: nothing,
+        ..post("/account/logout".to_string())
+    }
+}
+
+/// Changes the password, the account's other sessions are logged out.
+pub fn change_password(change: &PasswordChange) -> Call<()> {
+    Call {
+        read: nothing,
+        ..post_json("/account/password".to_string(), change)
+    }
+}
+
+pub fn account() -> Call<AccountInfo> {
+    get("/account".to_string())
+}
+
+/// Joins a space with an invite, as a new user named `alias`.
+pub fn join(token: &str, alias: Option<&str>) -> Call<User> {
+    let path = format!("/invite/{}", encode(token));
+    post(query(path, &[("alias", alias.map(str::to_string))]))
+}
+
+/// Makes the account an owner of the space, for the server's admins.
+pub fn make_owner(space: SpaceId, account: &str) -> Call<Member> {
+    post(format!(
+        "/admin/space/{}/owner/{}",
+        space.0,
+        encode(account)
+    ))
+}
+
+pub fn snapshots(space: SpaceId) -> Call<Vec<SnapshotInfo>> {
+    get(format!("/admin/space/{}/snapshots", space.0))
+}
+
+pub fn take_snapshot(space: SpaceId) -> Call<SnapshotInfo> {
+    post(format!("/admin/space/{}/snapshots", space.0))
+}
+
+/// The snapshot as it is stored.
+pub fn download_snapshot(space: SpaceId, at: Timestamp) -> Call<String> {
+    download(format!("/admin/space/{}/snapshots/{}", space.0, at.0))
+}
+
+pub fn restore_snapshot(space: SpaceId, at: Timestamp) -> Call<SaveStatus> {
+    post(format!(
+        "/admin/space/{}/snapshots/{}/restore",
+        space.0, at.0
+    ))
+}
+
+pub fn list_spaces() -> Call<Vec<SpaceId>> {
+    get("/space".to_string())
+}
+
+/// The calls under `/space/{id}`.
+pub fn space(id: SpaceId) -> Space {
+    Space(id)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Space(pub SpaceId);
+
+impl Space {
+    fn path(&self, rest: &str) -> String {
+        format!("/space/{}{}", self.0 .0, rest)
+    }
+
+    pub fn info(&self) -> Call<SpaceInfo> {
+        get(self.path(""))
+    }
+
+    pub fn create(&self, alias: Option<&str>) -> Call<SpaceInfo> {
+        post(query(
+            self.path(""),
+            &[("alias", alias.map(str::to_string))],
+        ))
+    }
+
+    /// What the caller may do in the space.
+    pub fn role(&self) -> Call<Role> {
+        get(self.path("/role"))
+    }
+
+    pub fn create_invite(
+        &self,
+        expires: Option<Timestamp>,
+        uses: Option<u32>,
+        invited: Option<Role>,
+    ) -> Call<Invite> {
+        let params = [
+            ("expires", time(expires)),
+            ("uses", uses.map(|u| u.to_string())),
+            ("role", invited.map(role)),
+        ];
+        post(query(self.path("/invite"), &params))
+    }
+
+    pub fn invites(&self) -> Call<Vec<Invite>> {
+        get(self.path("/invite"))
+    }
+
+    pub fn revoke_invite(&self, token: &str) -> Call<Invite> {
+        post(self.path(&format!("/invite/{}/revoke", encode(token))))
+    }
+
+    pub fn members(&self) -> Call<Vec<Member>> {
+        get(self.path("/member"))
+    }
+
+    pub fn set_member_role(&self, account: &str, to: Role) -> Call<Member> {
+        let path = self.path(&format!("/member/{}", encode(account)));
+        post(query(path, &[("role", Some(role(to)))]))
+    }
+
+    /// Makes the member the user, for owners.
+    pub fn assign_user(&self, account: &str, user: UserId) -> Call<Member> {
+        post(self.path(&format!("/member/{}/user/{}", encode(account), user.0)))
+    }
+
+    pub fn users(&self) -> Call<Vec<UserId>> {
+        get(self.path("/user"))
+    }
+
+    pub fn create_user(&self) -> Call<User> {
+        post(self.path("/user"))
+    }
+
+    pub fn user(&self, user: UserId) -> Call<User> {
+        get(self.path(&format!("/user/{}", user.0)))
+    }
+
+    pub fn set_user(&self, user: &User) -> Call<User> {
+        post_json(self.path(&format!("/user/{}", user.id.0)), user)
+    }
+
+    /// Makes the logged in account the user, if it owns the space.
+    pub fn claim_user(&self, user: UserId) -> Call<AccountInfo> {
+        post(self.path(&format!("/user/{}/claim", user.0)))
+    }
+
+    pub fn tasks(&self) -> Call<Vec<TaskId>> {
+        get(self.path("/task"))
+    }
+
+    pub fn create_task(&self) -> Call<Task> {
+        post(self.path("/task"))
+    }
+
+    pub fn task(&self, task: TaskId) -> Call<Task> {
+        get(self.path(&format!("/task/{}", task.0)))
+    }
+
+    pub fn set_task(&self, task: &Task) -> Call<Task> {
+        post_json(self.path(&format!("/task/{}", task.id.0)), task)
+    }
+
+    pub fn task_history(
+        &self,
+        task: TaskId,
+        from: Option<Timestamp>,
+        to: Option<Timestamp>,
+    ) -> Call<Vec<RankPoint>> {
+        let path = self.path(&format!("/task/{}/history", task.0));
+        get(query(path, &[("from", time(from)), ("to", time(to))]))
+    }
+
+    pub fn user_importance(&self, user: UserId) -> Call<OrderedTasks> {
+        get(self.path(&format!("/importance/{}", user.0)))
+    }
+
+    /// Tasks missing from the ballot are put in front, newest first.
+    pub fn set_user_importance(&self, user: UserId, ballot: &[TaskId]) -> Call<OrderedTasks> {
+        post_json(self.path(&format!("/importance/{}", user.0)), &ballot)
+    }
+
+    pub fn user_easiness(&self, user: UserId) -> Call<OrderedTasks> {
+        get(self.path(&format!("/easiness/{}", user.0)))
+    }
+
+    pub fn set_user_easiness(&self, user: UserId, ballot: &[TaskId]) -> Call<OrderedTasks> {
+        post_json(self.path(&format!("/easiness/{}", user.0)), &ballot)
+    }
+
+    /// Like the top level `apply`, returns the version the operation made.
+    pub fn apply(&self, op: &Operation, base: u64) -> Call<u64> {
+        let base = Some(base.to_string());
+        post_json(query(self.path("/op"), &[("base", base)]), op)
+    }
+
+    /// Waits up to 30 seconds for `wait_for` to be on disk, if given.
+    pub fn status(&self, wait_for: Option<u64>) -> Call<SaveStatus> {
+        let wait_for = wait_for.map(|v| v.to_string());
+        get(query(self.path("/status"), &[("wait_for", wait_for)]))
+    }
+
+    /// The consensus importance, now or at `at`.
+    pub fn importance(&self, at: Option<Timestamp>) -> Call<OrderedTasks> {
+        get(query(self.path("/importance"), &[("at", time(at))]))
+    }
+
+    pub fn easiness(&self) -> Call<OrderedTasks> {
+        get(self.path("/easiness"))
+    }
+
+    pub fn final_ordering(&self) -> Call<OrderedTasks> {
+        get(self.path("/final"))
+    }
+
+    pub fn export(&self, format: Format, user: Option<UserId>) -> Call<String> {
+        let path = self.path(&format!("/export/{}", format.extension()));
+        download(query(path, &[("user", user.map(|u| u.0.to_string()))]))
+    }
+
+    pub fn import(&self, format: Format, input: &str) -> Call<Imported> {
+        post_text(self.path(&format!("/import/{}", format.extension())), input)
+    }
+
+    pub fn import_issues(&self, input: &str) -> Call<Imported> {
+        post_text(self.path("/import/issues"), input)
+    }
+
+    pub fn task_feed(&self) -> Call<String> {
+        download(self.path("/tasks.ics"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn calls_build_paths_and_read_responses() {
+        let invite = space(SpaceId(3)).create_invite(Some(Timestamp(9)), None, Some(Role::Voter));
+        assert_eq!(invite.path, "/space/3/invite?expires=9&role=voter");
+        assert_eq!(
+            join("a b/c", Some("Ada")).path,
+            "/invite/a%20b%2Fc?alias=Ada"
+        );
+        assert_eq!(save_status(None).path, "/tasks/status");
+
+        let op = apply(
+            &Operation::CreateTask {
+                text: "first".to_string(),
+            },
+            1,
+        );
+        assert_eq!(
+            (op.method, op.path.as_str()),
+            (Method::Post, "/tasks/op?base=1")
+        );
+        let body = op.body.as_ref().unwrap();
+        assert_eq!(body.text, r#"{"op":"create_task","text":"first"}"#);
+        let status = op.response(200, r#"{"version": 2, "persisted_version": 1}"#);
+        assert_eq!(status.unwrap().version, 2);
+        assert_eq!(
+            op.response(403, "Forbidden"),
+            Err(Error::Status(403, "Forbidden".to_string()))
+        );
+        assert!(matches!(op.response(200, "nope"), Err(Error::Decode(_))));
+
+        assert_eq!(load_tasks().response(200, ""), Ok(None));
+        assert_eq!(logout().response(204, ""), Ok(()));
+    }
+}
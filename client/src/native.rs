@@ -0,0 +1,180 @@
+//! Sends calls over plain HTTP/1.1, one connection per call. Meant for tools
+//! talking to a server on the same machine or behind a TLS terminating proxy.
+
+use crate::{Call, Error};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// A server, and the cookies it set, so a login lasts until `logout`.
+pub struct Http {
+    address: String,
+    timeout: Duration,
+    cookies: Mutex<BTreeMap<String, String>>,
+}
+
+impl Http {
+    /// `address` is a `host:port`.
+    pub fn new(address: &str) -> Self {
+        Http {
+            address: address.to_string(),
+            timeout: Duration::from_secs(60),
+            cookies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// How long to wait for the server, a minute by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn send<T>(&self, call: Call<T>) -> Result<T, Error> {
+        let transport = |e: std::io::Error| Error::Transport(e.to_string());
+        let mut stream = TcpStream::connect(&self.address).map_err(transport)?;
+        stream
+            .set_read_timeout(Some(self.timeout))
+            .map_err(transport)?;
+        stream.write_all(&self.request(&call)).map_err(transport)?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).map_err(transport)?;
+        let response = Response::parse(&response).map_err(Error::Transport)?;
+        self.keep_cookies(&response);
+        call.response(response.status, &response.body)
+    }
+
+    fn request<T>(&self, call: &Call<T>) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+            call.method.as_str(),
+            call.path,
+            self.address
+        );
+        let cookies = self.cookies.lock().unwrap();
+        if !cookies.is_empty() {
+            let cookies: Vec<String> = cookies
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect();
+            head.push_str(&format!("Cookie: {}\r\n", cookies.join("; ")));
+        }
+        let body = call.body.as_ref().map_or("", |b| &b.text);
+        if let Some(b) = &call.body {
+            head.push_str(&format!("Content-Type: {}\r\n", b.content_type));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        let mut request = head.into_bytes();
+        request.extend_from_slice(body.as_bytes());
+        request
+    }
+
+    fn keep_cookies(&self, response: &Response) {
+        let mut cookies = self.cookies.lock().unwrap();
+        for cookie in response.headers("set-cookie") {
+            let pair = cookie.split(';').next().unwrap_or_default();
+            let (name, value) = match pair.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => continue,
+            };
+            // Removed cookies are set to nothing.
+            match value {
+                "" => cookies.remove(name),
+                value => cookies.insert(name.to_string(), value.to_string()),
+            };
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Response {
+    fn parse(raw: &[u8]) -> Result<Self, String> {
+        let split = raw
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or("Response ended in its head")?;
+        let head = std::str::from_utf8(&raw[..split]).map_err(|e| e.to_string())?;
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .and_then(|line| line.split(' ').nth(1))
+            .and_then(|status| status.parse().ok())
+            .ok_or("Bad status line")?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+            .collect();
+        let mut response = Response {
+            status,
+            headers,
+            body: String::new(),
+        };
+        let body = &raw[split + 4..];
+        let body = match response.headers("transfer-encoding").next() {
+            Some(encoding) if encoding.eq_ignore_ascii_case("chunked") => dechunk(body)?,
+            _ => body.to_vec(),
+        };
+        response.body = String::from_utf8(body).map_err(|e| e.to_string())?;
+        Ok(response)
+    }
+
+    fn headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let matching = self.headers.iter().filter(move |(n, _)| n == name);
+        matching.map(|(_, value)| value.as_str())
+    }
+}
+
+/// Joins the chunks of a `Transfer-Encoding: chunked` body.
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut joined = Vec::new();
+    loop {
+        let line = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("Chunk without a size")?;
+        let size = std::str::from_utf8(&body[..line]).map_err(|e| e.to_string())?;
+        // Chunk extensions follow a `;`.
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|e| e.to_string())?;
+        if size == 0 {
+            return Ok(joined);
+        }
+        let chunk = body
+            .get(line + 2..line + 2 + size)
+            .ok_or("Response ended in a chunk")?;
+        joined.extend_from_slice(chunk);
+        body = body.get(line + 4 + size..).unwrap_or_default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_chunked_responses_and_cookies() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+            Set-Cookie: athens_session=abc; Path=/; Secure\r\n\r\n\
+            4\r\n[1, \r\n2\r\n2]\r\n0\r\n\r\n";
+        let response = Response::parse(raw).unwrap();
+        assert_eq!((response.status, response.body.as_str()), (200, "[1, 2]"));
+
+        let http = Http::new("127.0.0.1:1");
+        http.keep_cookies(&response);
+        let request = http.request(&crate::account());
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("GET /account HTTP/1.1\r\n"));
+        assert!(request.contains("Cookie: athens_session=abc\r\n"));
+
+        let removed = b"HTTP/1.1 204 No Content\r\nSet-Cookie: athens_session=; Max-Age=0\r\n\r\n";
+        http.keep_cookies(&Response::parse(removed).unwrap());
+        assert!(http.cookies.lock().unwrap().is_empty());
+    }
+}
@@ -1,12 +1,15 @@
 #![feature(async_closure)]
-use gloo_net::http::Request;
+use gloo_net::http::{Method, Request};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
-use web_sys::HtmlTextAreaElement;
+use web_sys::{EventSource, HtmlInputElement, HtmlTextAreaElement, MessageEvent};
 use yew::context::ContextHandle;
 use yew::prelude::*;
 
-use model::{AthensSpace, SimpleAthensSpace, TaskId, UserId};
+use model::api::{AccountInfo, Credentials, Invite, Operation, Role, SaveStatus, Update};
+use model::{AthensSpace, ParallelSimpleAthensSpace, SimpleAthensSpace, SpaceId, TaskId, UserId};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 
 // TODO: Should this be Box<dyn Athens> or Rc<dyn Athens>
 // or something? How do I make this more dynamic/substitutable?
@@ -78,6 +81,9 @@ fn editable_input(props: &EditableInputP) -> Html {
 #[derive(PartialEq, Properties)]
 struct TaskInputP {
     id: usize,
+    // Whether the caller may edit tasks.
+    editable: bool,
+    apply: Callback<Operation>,
 }
 #[function_component(TaskInput)]
 fn task_input(props: &TaskInputP) -> Html {
@@ -86,18 +92,18 @@ fn task_input(props: &TaskInputP) -> Html {
     let athens = binding.get();
     let text = athens.get_task(TaskId(props.id)).unwrap().text;
     let set_text = {
-        let a = binding.clone();
-        let id = TaskId(props.id);
-        Callback::from(move |text| {
-            a.get().set_task(model::Task { id, text });
-        })
+        let task = TaskId(props.id);
+        props
+            .apply
+            .reform(move |text| Operation::SetTaskText { task, text })
     };
+    let editable = props.editable;
     html! {
         <EditableInput
             editable={*editing}
             size=80
             text={text}
-            set_editable={Callback::from(move |b| editing.set(b))}
+            set_editable={Callback::from(move |b| editing.set(b && editable))}
             set_text={set_text}
         />
     }
@@ -142,7 +148,11 @@ fn draggable_entry(props: &DraggableEntryP) -> Html {
 struct UserSelectP {
     active: Option<UserId>,
     set_active: Callback<Option<UserId>>,
-    add_user: Callback<()>,
+    apply: Callback<Operation>,
+    // Who the caller may rename: themselves, or everyone if they own the
+    // space and so may also add users.
+    own_user: Option<UserId>,
+    owner: bool,
 }
 #[function_component(UserSelect)]
 fn user_select(props: &UserSelectP) -> Html {
@@ -185,18 +195,12 @@ fn user_select(props: &UserSelectP) -> Html {
             let editing = use_state(|| false);
             let start_editing = {
                 let editing = editing.clone();
-                Callback::from(move |_| editing.set(true))
-            };
-            let set_user_alias = {
-                let a = binding.clone();
-                Callback::from(move |alias| {
-                    a.get().set_user(model::User {
-                        id: user,
-                        alias,
-                        weight: 1,
-                    });
-                })
+                let renamable = props.owner || props.own_user == Some(user);
+                Callback::from(move |_| editing.set(renamable))
             };
+            let set_user_alias = props
+                .apply
+                .reform(move |alias| Operation::RenameUser { user, alias });
             if *editing {
                 // When the user clicks on the main user button, we set
                 // `editing` to true and render a new input element to edit the
@@ -245,10 +249,14 @@ fn user_select(props: &UserSelectP) -> Html {
     }
     let main_button = main_button.expect("Active user not found");
 
-    let add_user = props.add_user.reform(|_| ());
-    hidden.push(html! {
-        <button onclick={add_user}>{"New user"}</button>
-    });
+    if props.owner {
+        let add_user = props.apply.reform(|_| Operation::CreateUser {
+            alias: String::new(),
+        });
+        hidden.push(html! {
+            <button onclick={add_user}>{"New user"}</button>
+        });
+    }
 
     html! {
         <div class="dropdown">
@@ -260,6 +268,57 @@ fn user_select(props: &UserSelectP) -> Html {
     }
 }
 
+#[derive(PartialEq, Properties)]
+struct LoginP {
+    account: Option<AccountInfo>,
+    error: Option<&'static str>,
+    log_in: Callback<Credentials>,
+    register: Callback<Credentials>,
+    log_out: Callback<()>,
+}
+
+#[function_component(Login)]
+fn login(props: &LoginP) -> Html {
+    let name = use_state(String::new);
+    let password = use_state(String::new);
+    if let Some(account) = &props.account {
+        return html! {
+            <div class="login">
+                {format!("Logged in as {} ", account.name)}
+                <button onclick={props.log_out.reform(|_| ())}>{"Log out"}</button>
+            </div>
+        };
+    }
+    let set = |state: &UseStateHandle<String>| {
+        let state = state.clone();
+        Callback::from(move |e: InputEvent| {
+            state.set(e.target_unchecked_into::<HtmlInputElement>().value())
+        })
+    };
+    let credentials = {
+        let (name, password) = (name.clone(), password.clone());
+        move |_: MouseEvent| Credentials {
+            name: (*name).clone(),
+            password: (*password).clone(),
+        }
+    };
+    html! {
+        <div class="login">
+            <input placeholder="Name" value={(*name).clone()} oninput={set(&name)}/>
+            <input type="password" placeholder="Password"
+                value={(*password).clone()} oninput={set(&password)}/>
+            <button onclick={props.log_in.reform(credentials.clone())}>{"Log in"}</button>
+            <button onclick={props.register.reform(credentials)}>{"Register"}</button>
+            if let Some(error) = props.error {
+                <span class="login-error">{error}</span>
+            }
+        </div>
+    }
+}
+
+/// The space the list shows, as served by `/tasks`.
+const SPACE: SpaceId = SpaceId(0);
+
 struct List {
     dragged: Option<usize>,
     dragged_over: Option<usize>,
@@ -267,8 +326,87 @@ struct List {
     athens: Athens,
     _handle: ContextHandle<Athens>,
     selected_user: Option<UserId>,
+    // Edits already applied locally, sent to the server one at a time so
+    // they are applied there in the same order.
+    unsent: VecDeque<Operation>,
+    // The space as it was before the unsent edits, to rebase them from.
+    unsent_base: Option<ParallelSimpleAthensSpace>,
+    // The server's latest acknowledgement of our edits.
+    save_status: Option<SaveStatus>,
+    // Others changed the space while our edits were on their way, so it is
+    // reloaded once they are all applied.
+    resync: bool,
+    // The server's latest, when someone else changed what our edits refer
+    // to first. Until we reload or merge our edits are not sent.
+    stale: Option<SimpleAthensSpace>,
+    // What merging would change in the list, for review.
+    review: Vec<String>,
+    // What others changed since this browser last loaded the space.
+    since_last_look: Vec<String>,
+    _events: Option<Events>,
+    // Who is logged in, they can only reorder their own ballots.
+    account: Option<AccountInfo>,
+    // What the caller may do in the space, controls they cannot use are
+    // hidden.
+    role: Role,
+    account_error: Option<&'static str>,
+    // Whether to show the account's ballots once its user is loaded.
+    select_own: bool,
+    // The invite this page was opened with, and what became of it.
+    invite: Option<String>,
+    invite_error: Option<&'static str>,
+    // The latest invite made here, to pass on.
+    invite_link: Option<String>,
+}
+
+/// The server's pushed updates to the space, see `/tasks/events`. The
+/// browser reconnects by itself, and the server resends what was missed.
+struct Events {
+    source: EventSource,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onerror: Closure<dyn FnMut(web_sys::Event)>,
 }
 
+impl Events {
+    fn open(ctx: &Context<List>) -> Option<Self> {
+        let source = EventSource::new("/tasks/events")
+            .map_err(|e| log::error!("Failed to watch for updates: {:?}", e))
+            .ok()?;
+        let link = ctx.link().clone();
+        let onmessage = Closure::wrap(Box::new(move |e: MessageEvent| {
+            let data = e.data().as_string().unwrap_or_default();
+            match serde_json::from_str(&data) {
+                Ok(update) => link.send_message(ListM::Pushed(update)),
+                Err(e) => log::error!("Bad update {:?}: {:?}", data, e),
+            }
+        }) as Box<dyn FnMut(MessageEvent)>);
+        source.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        let link = ctx.link().clone();
+        let watched = source.clone();
+        let onerror = Closure::wrap(Box::new(move |_: web_sys::Event| {
+            // Only closed if the server refused, otherwise it is retrying.
+            if watched.ready_state() == EventSource::CLOSED {
+                link.send_message(ListM::Disconnected);
+            }
+        }) as Box<dyn FnMut(web_sys::Event)>);
+        source.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+        Some(Events {
+            source,
+            _onmessage: onmessage,
+            _onerror: onerror,
+        })
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.source.close();
+    }
+}
+
+/// How long to wait before watching again after the server refused.
+const RECONNECT_MS: i32 = 5000;
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum Ordering {
     Easiness,
@@ -283,43 +421,217 @@ impl Default for Ordering {
 
 #[derive(Clone, Debug)]
 enum ListM {
-    // Entry CRUD
-    AddEntry,
     // Drag and drop.
     SetDragged(Option<usize>),
     SetDraggedOver(Option<usize>),
     Dropped,
-    // Saving.
-    StartSaving,
+    // Editing, each edit is applied here and sent to the server.
+    Apply(Operation),
+    Applied(SaveStatus),
+    Rejected,
+    // Someone else changed what an edit refers to first.
+    Overtaken,
+    SaveConflict(SimpleAthensSpace),
+    Reload,
+    Merge,
+    Persisted(SaveStatus),
     LoadData(SimpleAthensSpace),
+    // Others' edits.
+    Pushed(Update),
+    Resync(SimpleAthensSpace),
+    Disconnected,
+    Reconnect,
+    DismissChanges,
     // Sorting
     SetOrdering(Ordering),
     // Null
     Ignore,
     //
     SetActiveUser(Option<UserId>),
-    AddUser,
+    // Accounts.
+    LogIn(Credentials),
+    Register(Credentials),
+    LogOut,
+    LoggedIn(Option<AccountInfo>),
+    AccountRejected(u16),
+    SetRole(Role),
+    // Invites.
+    Join,
+    Joined,
+    InviteRejected(u16),
+    CreateInvite,
+    InviteCreated(Invite),
 }
 
 impl List {
-    fn save_request(&self) -> Request {
-        Request::post("/tasks")
-            .json(&self.athens.inner.lock().unwrap().clone())
-            .expect("Failed to make request")
-    }
     fn athens(&self) -> &dyn AthensSpace {
         self.athens.get()
     }
+    /// Loads the space and watches it for updates, once logged in as
+    /// reading it needs an account.
+    fn load(&mut self, ctx: &Context<Self>) {
+        self._events = Events::open(ctx);
+        ctx.link().send_future(async {
+            match load_tasks().await {
+                Some(tasks) => ListM::LoadData(tasks),
+                None => ListM::Ignore,
+            }
+        });
+    }
+    /// Replaces the space with the server's latest.
+    fn reload(ctx: &Context<Self>) {
+        ctx.link().send_future(async {
+            match load_tasks().await {
+                Some(tasks) => ListM::Resync(tasks),
+                None => ListM::Ignore,
+            }
+        });
+    }
+    /// The unsent edits made to mean the same on `current`, as far as what
+    /// they refer to is still there, and `current` with them applied.
+    fn rebase(&self, current: &SimpleAthensSpace) -> (SimpleAthensSpace, VecDeque<Operation>) {
+        let made_on = self
+            .unsent_base
+            .as_ref()
+            .map(|base| base.lock().unwrap().clone());
+        let made_on: ParallelSimpleAthensSpace = Arc::new(Mutex::new(made_on.unwrap_or_default()));
+        let onto: ParallelSimpleAthensSpace = Arc::new(Mutex::new(current.clone()));
+        let mut rebased = VecDeque::new();
+        for op in &self.unsent {
+            if let Some(op) = op.rebase(&made_on, &onto) {
+                if op.apply(&onto).is_some() {
+                    rebased.push_back(op);
+                }
+            }
+            op.apply(&made_on);
+        }
+        let merged = onto.lock().unwrap().clone();
+        (merged, rebased)
+    }
+    /// Shows the logged in user's ballots after logging in, once they are
+    /// loaded.
+    fn select_own_user(&mut self) {
+        if !self.select_own {
+            return;
+        }
+        let user = self.account.as_ref().and_then(|a| a.user(SPACE));
+        if let Some(user) = user.filter(|u| self.athens().users().contains(u)) {
+            self.selected_user = Some(user);
+            self.select_own = false;
+        }
+    }
+    /// Logs in or registers with the call.
+    fn authenticate(ctx: &Context<Self>, call: client::Call<AccountInfo>) {
+        ctx.link().send_future(async move {
+            let path = call.path.clone();
+            match fetch(call).await {
+                Ok(account) => ListM::LoggedIn(Some(account)),
+                Err(client::Error::Status(status, _)) => ListM::AccountRejected(status),
+                Err(e) => {
+                    log::error!("Failed to reach {}: {:?}", path, e);
+                    ListM::Ignore
+                }
+            }
+        });
+    }
+    /// Sends an edit made against version `base`.
+    fn send(ctx: &Context<Self>, op: Operation, base: u64) {
+        ctx.link().send_future(async move {
+            match fetch(client::apply(&op, base)).await {
+                Ok(status) => ListM::Applied(status),
+                Err(client::Error::Status(409, _)) => {
+                    log::info!("{:?} was made against an older version", op);
+                    ListM::Overtaken
+                }
+                Err(e) => {
+                    log::error!("Server rejected {:?}: {:?}", op, e);
+                    ListM::Rejected
+                }
+            }
+        });
+    }
 }
 
-async fn load_tasks() -> Option<SimpleAthensSpace> {
-    Request::get("/tasks")
-        .send()
-        .await
-        .ok()?
-        .json()
+const LAST_SEEN_KEY: &str = "athens-last-seen";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// The space as this browser last loaded it.
+fn load_last_seen() -> Option<SimpleAthensSpace> {
+    let json = local_storage()?.get_item(LAST_SEEN_KEY).ok()??;
+    serde_json::from_str(&json).ok()
+}
+
+fn store_last_seen(space: &SimpleAthensSpace) {
+    let json = serde_json::to_string(space).expect("Failed to serialize space");
+    if let Some(storage) = local_storage() {
+        if let Err(e) = storage.set_item(LAST_SEEN_KEY, &json) {
+            log::error!("Failed to store last seen space: {:?}", e);
+        }
+    }
+}
+
+/// Long polls the server until `version` is written to disk.
+async fn wait_for_persisted(version: u64) -> ListM {
+    loop {
+        match fetch(client::save_status(Some(version))).await.ok() {
+            // Others may have saved since, only whether ours is on disk
+            // matters here.
+            Some(status) if status.persisted_version >= version => {
+                return ListM::Persisted(SaveStatus { version, ..status })
+            }
+            Some(_) => continue,
+            None => {
+                log::error!("Lost track of save {}", version);
+                return ListM::Ignore;
+            }
+        }
+    }
+}
+
+/// The token of `/?invite={token}` links.
+fn invite_token() -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let token = search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|p| p.strip_prefix("invite="));
+    token.filter(|t| !t.is_empty()).map(str::to_string)
+}
+
+/// Sends a typed call to the server, see the `client` crate.
+async fn fetch<T>(call: client::Call<T>) -> Result<T, client::Error> {
+    let method = match call.method {
+        client::Method::Get => Method::GET,
+        client::Method::Post => Method::POST,
+    };
+    let mut rq = Request::new(&call.path).method(method);
+    if let Some(body) = &call.body {
+        rq = rq
+            .header("Content-Type", body.content_type)
+            .body(body.text.clone());
+    }
+    let lost = |e: gloo_net::Error| client::Error::Transport(e.to_string());
+    let rp = rq.send().await.map_err(lost)?;
+    let text = rp.text().await.map_err(lost)?;
+    call.response(rp.status(), &text)
+}
+
+async fn load_account() -> Option<AccountInfo> {
+    fetch(client::account()).await.ok()
+}
+
+/// What the caller may do in the space, nothing if it is unknown.
+async fn load_role() -> Role {
+    fetch(client::space(SPACE).role())
         .await
-        .ok()?
+        .unwrap_or(Role::Viewer)
+}
+
+async fn load_tasks() -> Option<SimpleAthensSpace> {
+    fetch(client::load_tasks()).await.ok().flatten()
 }
 
 impl Component for List {
@@ -327,13 +639,8 @@ impl Component for List {
     type Properties = ();
 
     fn create(ctx: &Context<Self>) -> Self {
-        ctx.link().send_future(async {
-            if let Some(tasks) = load_tasks().await {
-                ListM::LoadData(tasks)
-            } else {
-                ListM::Ignore
-            }
-        });
+        ctx.link()
+            .send_future(async { ListM::LoggedIn(load_account().await) });
         let cb = ctx.link().callback(|_| ListM::Ignore);
         let (athens, _handle) = ctx.link().context::<Athens>(cb).unwrap();
         Self {
@@ -343,16 +650,27 @@ impl Component for List {
             athens,
             selected_user: None,
             _handle,
+            unsent: VecDeque::new(),
+            since_last_look: Vec::new(),
+            save_status: None,
+            resync: false,
+            unsent_base: None,
+            stale: None,
+            review: Vec::new(),
+            _events: None,
+            account: None,
+            role: Role::Viewer,
+            account_error: None,
+            select_own: true,
+            invite: invite_token(),
+            invite_error: None,
+            invite_link: None,
         }
     }
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         log::info!("List received Message: {:?}", &msg);
         match msg {
             ListM::Ignore => false,
-            ListM::AddEntry => {
-                self.athens().create_task();
-                true
-            }
             ListM::SetDragged(i) => {
                 self.dragged = i;
                 false
@@ -363,22 +681,20 @@ impl Component for List {
             }
             ListM::Dropped => {
                 if let (Some(from), Some(to)) = (self.dragged, self.dragged_over) {
-                    match self.ordering {
-                        Ordering::Importance => {
-                            let user = self.selected_user.unwrap();
-                            self.athens().swap_user_importance(user, from, to);
-                        }
-                        Ordering::Easiness => {
-                            let user = self.selected_user.unwrap();
-                            self.athens().swap_user_easiness(user, from, to);
+                    self.dragged = Some(from);
+                    let user = self.selected_user.unwrap();
+                    let op = match self.ordering {
+                        Ordering::Importance => Operation::MoveImportance { user, from, to },
+                        Ordering::Easiness => Operation::MoveEasiness { user, from, to },
+                        _ => {
+                            log::error!(
+                                "Tried to drag and drop when ordering is {:?}",
+                                self.ordering
+                            );
+                            return false;
                         }
-                        _ => log::error!(
-                            "Tried to drag and drop when ordering is {:?}",
-                            self.ordering
-                        ),
                     };
-                    self.dragged = Some(from);
-                    true
+                    self.update(ctx, ListM::Apply(op))
                 } else {
                     // dragged and dragover should both be set by web events before
                     // dropped is called and before dragexit.
@@ -390,29 +706,305 @@ impl Component for List {
                     false
                 }
             }
-            ListM::StartSaving => {
-                let rq = self.save_request();
+            ListM::Apply(op) => {
+                let before = self.athens.inner.lock().unwrap().clone();
+                if op.apply(self.athens()).is_none() {
+                    log::error!("Cannot apply {:?}", op);
+                    return false;
+                }
+                if self.unsent.is_empty() {
+                    self.unsent_base = Some(Arc::new(Mutex::new(before)));
+                }
+                self.unsent.push_back(op.clone());
+                if self.unsent.len() == 1 {
+                    // Versions only change with the server's, so this is
+                    // still the one the edit was made against.
+                    let base = self.athens.inner.lock().unwrap().version();
+                    Self::send(ctx, op, base);
+                }
+                true
+            }
+            ListM::Applied(status) => {
+                let applied = self.unsent.pop_front();
+                if let (Some(op), Some(base)) = (&applied, &self.unsent_base) {
+                    op.apply(base);
+                }
+                if self.unsent.is_empty() {
+                    self.unsent_base = None;
+                }
+                // Creating a user makes the logged in account that user, if
+                // it was nobody here yet.
+                let own_user = self.account.as_ref().and_then(|a| a.user(SPACE));
+                if let (Some(Operation::CreateUser { .. }), Some(_), None) =
+                    (applied, &self.account, own_user)
+                {
+                    ctx.link()
+                        .send_future(async { ListM::LoggedIn(load_account().await) });
+                }
+                let mut space = self.athens.inner.lock().unwrap();
+                // Someone else's edit was applied in between. Our copy does
+                // not have it, so it keeps the older version to send the
+                // rest of our edits against.
+                if status.version != space.version() + 1 {
+                    self.resync = true;
+                } else {
+                    space.set_version(status.version);
+                }
+                match self.unsent.front() {
+                    Some(next) => Self::send(ctx, next.clone(), space.version()),
+                    None if std::mem::take(&mut self.resync) => Self::reload(ctx),
+                    None => {
+                        store_last_seen(&space);
+                        if !status.is_persisted() {
+                            ctx.link().send_future(wait_for_persisted(status.version));
+                        }
+                    }
+                }
+                self.save_status = Some(status);
+                true
+            }
+            ListM::Overtaken => {
+                // Our edits stay queued until we reload or merge.
                 ctx.link().send_future(async {
-                    let rp = rq.send().await;
-                    log::info!("Response: {:?}", rp);
-                    ListM::Ignore
+                    match load_tasks().await {
+                        Some(current) => ListM::SaveConflict(current),
+                        None => ListM::Rejected,
+                    }
                 });
                 false
             }
+            ListM::SaveConflict(current) => {
+                let (merged, _) = self.rebase(&current);
+                self.review = self.athens.inner.lock().unwrap().diff(&merged).summary();
+                self.stale = Some(current);
+                true
+            }
+            ListM::Reload => {
+                if let Some(current) = self.stale.take() {
+                    self.unsent.clear();
+                    self.unsent_base = None;
+                    self.review.clear();
+                    store_last_seen(&current);
+                    *self.athens.inner.lock().unwrap() = current;
+                }
+                true
+            }
+            ListM::Merge => {
+                let current = match self.stale.take() {
+                    Some(current) => current,
+                    None => return false,
+                };
+                let (merged, rebased) = self.rebase(&current);
+                self.review.clear();
+                self.unsent = rebased;
+                self.unsent_base = Some(Arc::new(Mutex::new(current.clone())));
+                store_last_seen(&current);
+                *self.athens.inner.lock().unwrap() = merged;
+                match self.unsent.front() {
+                    Some(next) => Self::send(ctx, next.clone(), current.version()),
+                    None => self.unsent_base = None,
+                }
+                true
+            }
+            ListM::Rejected => {
+                // Our copy no longer matches the server's, start over from
+                // the server's.
+                self.unsent.clear();
+                self.unsent_base = None;
+                ctx.link().send_future(async {
+                    match load_tasks().await {
+                        Some(tasks) => ListM::LoadData(tasks),
+                        None => ListM::Ignore,
+                    }
+                });
+                false
+            }
+            ListM::Persisted(status) => {
+                // Ignore acknowledgements of older edits.
+                if self
+                    .save_status
+                    .map_or(true, |s| s.version <= status.version)
+                {
+                    self.save_status = Some(status);
+                }
+                true
+            }
+            ListM::Pushed(update) => {
+                let version = self.athens.inner.lock().unwrap().version();
+                // Our own edits come back too, and while ours are on their
+                // way `Applied` notices others' from the versions.
+                if update.version <= version || !self.unsent.is_empty() {
+                    return false;
+                }
+                match update.op {
+                    Some(op)
+                        if update.version == version + 1 && op.apply(self.athens()).is_some() =>
+                    {
+                        let mut space = self.athens.inner.lock().unwrap();
+                        space.set_version(update.version);
+                        store_last_seen(&space);
+                        true
+                    }
+                    // Missed some, or it was more than one edit.
+                    _ => {
+                        Self::reload(ctx);
+                        false
+                    }
+                }
+            }
+            ListM::Resync(model) => {
+                if !self.unsent.is_empty() {
+                    // It may not have our edits yet.
+                    self.resync = true;
+                    return false;
+                }
+                store_last_seen(&model);
+                *self.athens.inner.lock().unwrap() = model;
+                self.select_own_user();
+                true
+            }
+            ListM::Disconnected => {
+                self._events = None;
+                let link = ctx.link().clone();
+                let reconnect = Closure::once_into_js(move || link.send_message(ListM::Reconnect));
+                let window = web_sys::window().unwrap();
+                if let Err(e) = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                    reconnect.unchecked_ref(),
+                    RECONNECT_MS,
+                ) {
+                    log::error!("Failed to schedule reconnect: {:?}", e);
+                }
+                false
+            }
+            ListM::Reconnect => {
+                if self.account.is_none() {
+                    return false;
+                }
+                // Nothing missed is resent to a new connection.
+                self._events = Events::open(ctx);
+                Self::reload(ctx);
+                false
+            }
+            ListM::DismissChanges => {
+                self.since_last_look.clear();
+                true
+            }
             ListM::SetOrdering(o) => {
                 self.ordering = o;
                 true
             }
             ListM::LoadData(model) => {
+                if let Some(last_seen) = load_last_seen() {
+                    self.since_last_look = last_seen.diff(&model).summary();
+                }
+                store_last_seen(&model);
                 *self.athens.inner.lock().unwrap() = model;
+                self.select_own_user();
                 true
             }
             ListM::SetActiveUser(u) => {
                 self.selected_user = u;
                 true
             }
-            ListM::AddUser => {
-                self.athens().create_user();
+            ListM::LogIn(credentials) => {
+                Self::authenticate(ctx, client::login(&credentials));
+                false
+            }
+            ListM::Register(credentials) => {
+                Self::authenticate(ctx, client::register(&credentials));
+                false
+            }
+            ListM::LogOut => {
+                ctx.link().send_future(async {
+                    if let Err(e) = fetch(client::logout()).await {
+                        log::error!("Failed to log out: {:?}", e);
+                    }
+                    ListM::LoggedIn(None)
+                });
+                false
+            }
+            ListM::LoggedIn(account) => {
+                self.select_own = account.is_some();
+                match account {
+                    Some(_) => self.load(ctx),
+                    None => self._events = None,
+                }
+                self.account = account;
+                self.account_error = None;
+                self.select_own_user();
+                ctx.link()
+                    .send_future(async { ListM::SetRole(load_role().await) });
+                true
+            }
+            ListM::SetRole(role) => {
+                self.role = role;
+                true
+            }
+            ListM::Join => {
+                let token = match &self.invite {
+                    Some(token) => token.clone(),
+                    None => return false,
+                };
+                ctx.link().send_future(async move {
+                    match fetch(client::join(&token, None)).await {
+                        Ok(_) => ListM::Joined,
+                        Err(client::Error::Status(status, _)) => ListM::InviteRejected(status),
+                        Err(e) => {
+                            log::error!("Failed to join: {:?}", e);
+                            ListM::Ignore
+                        }
+                    }
+                });
+                false
+            }
+            ListM::Joined => {
+                self.invite = None;
+                // Our new user is in the reloaded space and on the account.
+                Self::reload(ctx);
+                ctx.link()
+                    .send_future(async { ListM::LoggedIn(load_account().await) });
+                true
+            }
+            ListM::InviteRejected(status) => {
+                self.invite_error = Some(match status {
+                    401 => "Log in to join",
+                    403 => "Only the space's owner can invite",
+                    404 => "There is no such invite",
+                    409 => "You are already in this space",
+                    410 => "This invite can no longer be used",
+                    _ => "Something went wrong",
+                });
+                true
+            }
+            ListM::CreateInvite => {
+                ctx.link().send_future(async {
+                    match fetch(client::space(SPACE).create_invite(None, None, None)).await {
+                        Ok(invite) => ListM::InviteCreated(invite),
+                        Err(client::Error::Status(status, _)) => ListM::InviteRejected(status),
+                        Err(e) => {
+                            log::error!("Failed to invite: {:?}", e);
+                            ListM::Ignore
+                        }
+                    }
+                });
+                false
+            }
+            ListM::InviteCreated(invite) => {
+                let origin = web_sys::window().and_then(|w| w.location().origin().ok());
+                self.invite_link = Some(format!(
+                    "{}/?invite={}",
+                    origin.unwrap_or_default(),
+                    invite.token
+                ));
+                true
+            }
+            ListM::AccountRejected(status) => {
+                self.account_error = Some(match status {
+                    400 => "Passwords need at least 8 characters",
+                    401 => "Wrong name or password",
+                    409 => "That name is taken",
+                    _ => "Something went wrong",
+                });
                 true
             }
         }
@@ -427,7 +1019,16 @@ impl Component for List {
             (None, Easiness) => self.athens().easy_tasks(),
             (None, ImportantAndEasy) => self.athens().important_and_easy_tasks(),
         };
-        let draggable = self.selected_user.is_some() && self.ordering != Ordering::ImportantAndEasy;
+        // Voters can only reorder their own ballots.
+        let own_user = self
+            .account
+            .as_ref()
+            .and_then(|a| a.user(SPACE))
+            .filter(|_| self.role >= Role::Voter);
+        let draggable = self.selected_user.is_some()
+            && self.selected_user == own_user
+            && self.ordering != Ordering::ImportantAndEasy;
+        let editable = self.role >= Role::Member;
         let entries_html: Vec<Html> = task_ids
             .into_iter()
             .enumerate()
@@ -438,14 +1039,17 @@ impl Component for List {
                         callback={ctx.link().callback(|x| x)}
                         draggable={draggable} order={order}
                     >
-                        <TaskInput id={id}/>
+                        <TaskInput id={id} editable={editable} apply={ctx.link().callback(ListM::Apply)}/>
                     </DraggableEntry>
                 }
             })
             .collect();
 
-        let addentry = ctx.link().callback(|_| ListM::AddEntry);
-        let save = ctx.link().callback(|_| ListM::StartSaving);
+        let addentry = ctx.link().callback(|_| {
+            ListM::Apply(Operation::CreateTask {
+                text: String::new(),
+            })
+        });
 
         let sort_msg = match self.ordering {
             Importance => "Sorted by importance",
@@ -461,18 +1065,96 @@ impl Component for List {
             ctx.link().callback(move |_| ListM::SetOrdering(next))
         };
 
+        let save_status = if self.stale.is_some() {
+            "Not saved"
+        } else if !self.unsent.is_empty() {
+            "Saving..."
+        } else {
+            match self.save_status {
+                Some(status) if !status.is_persisted() => "Saving...",
+                Some(_) => "Saved",
+                None => "",
+            }
+        };
+        let since_last_look = if self.since_last_look.is_empty() {
+            html! {}
+        } else {
+            html! {
+                <div class="changes">
+                    {"Changed since you last looked:"}
+                    <ul>{ for self.since_last_look.iter().map(|l| html! { <li>{l}</li> }) }</ul>
+                    <button onclick={ctx.link().callback(|_| ListM::DismissChanges)}>
+                        {"Close"}
+                    </button>
+                </div>
+            }
+        };
+
+        let conflict = if self.stale.is_none() {
+            html! {}
+        } else {
+            html! {
+                <div class="conflict">
+                    {"Someone else changed this while your edits were on their way."}
+                    if !self.review.is_empty() {
+                        <>
+                            {" Merging keeps your edits and changes:"}
+                            <ul>{ for self.review.iter().map(|l| html! { <li>{l}</li> }) }</ul>
+                        </>
+                    }
+                    <button onclick={ctx.link().callback(|_| ListM::Merge)}>{"Merge"}</button>
+                    <button onclick={ctx.link().callback(|_| ListM::Reload)}>
+                        {"Reload without my edits"}
+                    </button>
+                </div>
+            }
+        };
+
+        let invites = match (&self.invite, &self.account) {
+            (Some(_), Some(_)) => html! {
+                <button onclick={ctx.link().callback(|_| ListM::Join)}>{"Join this space"}</button>
+            },
+            (Some(_), None) => html! { <span>{"Log in or register to join this space"}</span> },
+            (None, Some(_)) if self.role == Role::Owner => html! {
+                <button onclick={ctx.link().callback(|_| ListM::CreateInvite)}>{"Invite someone"}</button>
+            },
+            (None, _) => html! {},
+        };
+
         html! {
             <div>
+                <Login
+                    account={self.account.clone()}
+                    error={self.account_error}
+                    log_in={ctx.link().callback(ListM::LogIn)}
+                    register={ctx.link().callback(ListM::Register)}
+                    log_out={ctx.link().callback(|_| ListM::LogOut)}
+                />
+                <div class="invites">
+                    {invites}
+                    if let Some(link) = &self.invite_link {
+                        <input readonly=true value={link.clone()}/>
+                    }
+                    if let Some(error) = self.invite_error {
+                        <span class="invite-error">{error}</span>
+                    }
+                </div>
+                <span class="save-status">{save_status}</span>
+                {conflict}
+                {since_last_look}
                 <button onclick={toggle_sort}>{sort_msg}</button>
                 <p style="display:inline-block; padding: 0 4 0 5">{" according to "}</p>
                 <UserSelect
                     active={self.selected_user}
                     set_active={ctx.link().callback(ListM::SetActiveUser)}
-                    add_user={ctx.link().callback(|_| ListM::AddUser)}
+                    apply={ctx.link().callback(ListM::Apply)}
+                    own_user={own_user}
+                    owner={self.role == Role::Owner}
                 />
                 <ul>{ for entries_html }</ul>
-                <button onclick={addentry}>{"Add"}</button>
-                <button onclick={save}>{"Save"}</button>
+                if editable {
+                    <button onclick={addentry}>{"Add"}</button>
+                }
             </div>
         }
     }
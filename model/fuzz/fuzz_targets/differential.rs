@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use model::differential::{differential, Op};
+use model::SqliteAthensSpace;
+
+fuzz_target!(|ops: Vec<Op>| {
+    let new_space = || SqliteAthensSpace::open_in_memory().unwrap();
+    if let Err((ops, mismatch)) = differential(new_space, &ops) {
+        panic!("{}\nops: {:#?}", mismatch, ops);
+    }
+});
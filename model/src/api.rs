@@ -0,0 +1,431 @@
+//! Types exchanged between the server and its clients.
+
+use crate::{AthensSpace, OrderedTasks, SpaceId, TaskId, Timestamp, UserId};
+use serde::{Deserialize, Serialize};
+
+/// The server's acknowledgement of saves. The server accepts a save
+/// immediately and writes it to disk a little later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveStatus {
+    /// The latest accepted version.
+    pub version: u64,
+    /// The latest version that is safely on disk.
+    pub persisted_version: u64,
+}
+
+impl SaveStatus {
+    pub fn is_persisted(&self) -> bool {
+        self.persisted_version >= self.version
+    }
+}
+
+/// A point in time copy of a space kept by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotInfo {
+    /// When it was taken, also identifies the snapshot.
+    pub at: Timestamp,
+    pub bytes: u64,
+}
+
+/// What `/space/{id}` says about a space, without its tasks and ballots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpaceInfo {
+    pub id: SpaceId,
+    pub alias: String,
+    pub version: u64,
+    pub tasks: usize,
+    pub users: usize,
+}
+
+/// One change to a space. Clients send these as edits happen, with the
+/// version they were made against, and the server applies them to its latest
+/// version of the space if that is still what they mean there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    CreateTask {
+        text: String,
+    },
+    SetTaskText {
+        task: TaskId,
+        text: String,
+    },
+    CreateUser {
+        alias: String,
+    },
+    RenameUser {
+        user: UserId,
+        alias: String,
+    },
+    /// Moves the task at position `from` of the user's ballot to `to`.
+    MoveImportance {
+        user: UserId,
+        from: usize,
+        to: usize,
+    },
+    MoveEasiness {
+        user: UserId,
+        from: usize,
+        to: usize,
+    },
+}
+
+impl Operation {
+    /// Applies the operation, or returns `None` without changing anything
+    /// if the task, user or position it refers to does not exist.
+    pub fn apply(&self, space: &dyn AthensSpace) -> Option<()> {
+        let in_range = |from: &usize, to: &usize| {
+            let len = space.tasks().len();
+            (*from < len && *to < len).then_some(())
+        };
+        match self {
+            Operation::CreateTask { text } => {
+                let mut task = space.create_task();
+                task.text = text.clone();
+                space.set_task(task);
+            }
+            Operation::SetTaskText { task, text } => {
+                let mut task = space.get_task(*task)?;
+                task.text = text.clone();
+                space.set_task(task);
+            }
+            Operation::CreateUser { alias } => {
+                let mut user = space.create_user();
+                user.alias = alias.clone();
+                space.set_user(user);
+            }
+            Operation::RenameUser { user, alias } => {
+                let mut user = space.get_user(*user)?;
+                user.alias = alias.clone();
+                space.set_user(user);
+            }
+            Operation::MoveImportance { user, from, to } => {
+                space.get_user(*user)?;
+                in_range(from, to)?;
+                space.swap_user_importance(*user, *from, *to);
+            }
+            Operation::MoveEasiness { user, from, to } => {
+                space.get_user(*user)?;
+                in_range(from, to)?;
+                space.swap_user_easiness(*user, *from, *to);
+            }
+        }
+        Some(())
+    }
+
+    /// Whether `other`, applied since the version this operation was made
+    /// against, changed what it refers to: the same task's text, the same
+    /// user's alias or ballot. New tasks shift the positions in every ballot.
+    pub fn overtaken_by(&self, other: &Operation) -> bool {
+        use Operation::*;
+        match (self, other) {
+            (SetTaskText { task, .. }, SetTaskText { task: other, .. }) => task == other,
+            (RenameUser { user, .. }, RenameUser { user: other, .. })
+            | (MoveImportance { user, .. }, MoveImportance { user: other, .. })
+            | (MoveEasiness { user, .. }, MoveEasiness { user: other, .. }) => user == other,
+            (MoveImportance { .. } | MoveEasiness { .. }, CreateTask { .. }) => true,
+            _ => false,
+        }
+    }
+
+    /// The operation made on `made_on`, changed to mean the same on `onto`.
+    /// Moves follow the tasks they moved to wherever they are in the ballot
+    /// now. `None` if what it refers to is not in `onto`.
+    pub fn rebase(&self, made_on: &dyn AthensSpace, onto: &dyn AthensSpace) -> Option<Operation> {
+        let users = |user: &UserId| made_on.get_user(*user).and(onto.get_user(*user));
+        match self {
+            Operation::CreateTask { .. } | Operation::CreateUser { .. } => Some(self.clone()),
+            Operation::SetTaskText { task, .. } => onto.get_task(*task).map(|_| self.clone()),
+            Operation::RenameUser { user, .. } => onto.get_user(*user).map(|_| self.clone()),
+            Operation::MoveImportance { user, from, to } => {
+                users(user)?;
+                let (from, to) = follow(
+                    &made_on.user_importance(*user),
+                    &onto.user_importance(*user),
+                    *from,
+                    *to,
+                )?;
+                Some(Operation::MoveImportance {
+                    user: *user,
+                    from,
+                    to,
+                })
+            }
+            Operation::MoveEasiness { user, from, to } => {
+                users(user)?;
+                let (from, to) = follow(
+                    &made_on.user_easiness(*user),
+                    &onto.user_easiness(*user),
+                    *from,
+                    *to,
+                )?;
+                Some(Operation::MoveEasiness {
+                    user: *user,
+                    from,
+                    to,
+                })
+            }
+        }
+    }
+}
+
+/// Where the tasks at positions `from` and `to` of `old` are in `new`.
+fn follow(
+    old: &OrderedTasks,
+    new: &OrderedTasks,
+    from: usize,
+    to: usize,
+) -> Option<(usize, usize)> {
+    let position = |i: usize| {
+        let task = old.iter().nth(i)?;
+        new.iter().position(|t| t == task)
+    };
+    Some((position(from)?, position(to)?))
+}
+
+/// A new version of a space, pushed by the server to the clients watching
+/// the space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Update {
+    pub version: u64,
+    /// The operation that made this version from the one before, if it was
+    /// a single operation. Otherwise clients reload the space.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub op: Option<Operation>,
+}
+
+/// What is posted to log in or to register an account.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub name: String,
+    pub password: String,
+}
+
+/// What is posted to change the logged in account's password.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordChange {
+    pub password: String,
+    pub new_password: String,
+}
+
+/// What someone may do in a space. Each role may do everything the ones
+/// before it may.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reads the space.
+    Viewer,
+    /// Ranks the tasks on their own ballots.
+    Voter,
+    /// Adds and edits tasks.
+    #[default]
+    Member,
+    /// Manages members, weights and invites.
+    Owner,
+}
+
+/// An account's place in a space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    pub space: SpaceId,
+    pub role: Role,
+    /// Who the account is in the space, if anyone yet.
+    #[serde(default)]
+    pub user: Option<UserId>,
+}
+
+/// A member of a space, as its owners see them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub account: String,
+    pub role: Role,
+    pub user: Option<UserId>,
+}
+
+/// The logged in account, and its place in each space.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub name: String,
+    pub spaces: Vec<Membership>,
+}
+
+impl AccountInfo {
+    pub fn membership(&self, space: SpaceId) -> Option<&Membership> {
+        self.spaces.iter().find(|m| m.space == space)
+    }
+    pub fn user(&self, space: SpaceId) -> Option<UserId> {
+        self.membership(space).and_then(|m| m.user)
+    }
+}
+
+/// A link that lets people join a space, see `/space/{id}/invite`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Invite {
+    /// What the link is made of, `/?invite={token}` in the frontend.
+    pub token: String,
+    pub space: SpaceId,
+    /// The account that made the invite.
+    pub created_by: String,
+    pub created: Timestamp,
+    pub expires: Option<Timestamp>,
+    pub max_uses: Option<u32>,
+    /// What people joining with the invite may do.
+    #[serde(default)]
+    pub role: Role,
+    pub revoked: bool,
+    pub redemptions: Vec<Redemption>,
+}
+
+/// Someone joining a space with an invite.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Redemption {
+    pub account: String,
+    pub user: UserId,
+    pub at: Timestamp,
+}
+
+impl Invite {
+    pub fn is_usable(&self, now: Timestamp) -> bool {
+        let used = self.redemptions.len();
+        !self.revoked
+            && self.expires.is_none_or(|expires| now < expires)
+            && self.max_uses.is_none_or(|uses| used < uses as usize)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParallelSimpleAthensSpace, SimpleAthensSpace};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn operations_apply_to_a_space() {
+        let space: ParallelSimpleAthensSpace = Arc::new(Mutex::new(SimpleAthensSpace::new()));
+        let ops = [
+            r#"{"op": "create_task", "text": "first"}"#,
+            r#"{"op": "create_task", "text": "second"}"#,
+            r#"{"op": "set_task_text", "task": 0, "text": "First"}"#,
+            r#"{"op": "create_user", "alias": ""}"#,
+            r#"{"op": "rename_user", "user": 0, "alias": "Ada"}"#,
+            r#"{"op": "move_importance", "user": 0, "from": 0, "to": 1}"#,
+        ];
+        for op in ops {
+            let op: Operation = serde_json::from_str(op).unwrap();
+            assert_eq!(op.apply(&space), Some(()), "{:?}", op);
+        }
+        assert_eq!(space.get_task(TaskId(0)).unwrap().text, "First");
+        assert_eq!(space.get_user(UserId(0)).unwrap().alias, "Ada");
+        assert_eq!(space.user_importance(UserId(0)), [TaskId(1), TaskId(0)]);
+
+        let user = UserId(0);
+        let missing = [
+            Operation::SetTaskText {
+                task: TaskId(2),
+                text: String::new(),
+            },
+            Operation::MoveEasiness {
+                user: UserId(1),
+                from: 0,
+                to: 1,
+            },
+            Operation::MoveEasiness {
+                user,
+                from: 0,
+                to: 2,
+            },
+        ];
+        for op in missing {
+            assert_eq!(op.apply(&space), None, "{:?}", op);
+        }
+        assert_eq!(space.user_easiness(user), [TaskId(0), TaskId(1)]);
+    }
+
+    #[test]
+    fn operations_are_overtaken_by_changes_to_what_they_refer_to() {
+        let text = |task| Operation::SetTaskText {
+            task: TaskId(task),
+            text: String::new(),
+        };
+        let rename = |user| Operation::RenameUser {
+            user: UserId(user),
+            alias: String::new(),
+        };
+        let importance = |user| Operation::MoveImportance {
+            user: UserId(user),
+            from: 0,
+            to: 1,
+        };
+        let easiness = Operation::MoveEasiness {
+            user: UserId(0),
+            from: 0,
+            to: 1,
+        };
+        let create_task = Operation::CreateTask {
+            text: String::new(),
+        };
+        assert!(text(0).overtaken_by(&text(0)));
+        assert!(!text(0).overtaken_by(&text(1)));
+        assert!(rename(0).overtaken_by(&rename(0)));
+        assert!(!rename(0).overtaken_by(&rename(1)));
+        assert!(!rename(0).overtaken_by(&importance(0)));
+        assert!(importance(0).overtaken_by(&importance(0)));
+        assert!(!importance(0).overtaken_by(&importance(1)));
+        assert!(!importance(0).overtaken_by(&easiness));
+        assert!(importance(0).overtaken_by(&create_task));
+        assert!(easiness.overtaken_by(&create_task));
+        assert!(!create_task.overtaken_by(&importance(0)));
+        assert!(!create_task.overtaken_by(&create_task));
+    }
+
+    #[test]
+    fn moves_are_rebased_onto_the_tasks_they_moved() {
+        let space = |tasks: usize| {
+            let space: ParallelSimpleAthensSpace = Arc::new(Mutex::new(SimpleAthensSpace::new()));
+            space.create_user();
+            for _ in 0..tasks {
+                space.create_task();
+            }
+            space
+        };
+        let made_on = space(2);
+        let onto = space(3);
+        let user = UserId(0);
+        assert_eq!(made_on.user_importance(user), [TaskId(1), TaskId(0)]);
+        assert_eq!(
+            onto.user_importance(user),
+            [TaskId(2), TaskId(1), TaskId(0)]
+        );
+        let moved = Operation::MoveImportance {
+            user,
+            from: 0,
+            to: 1,
+        };
+        let rebased = moved.rebase(&made_on, &onto).unwrap();
+        assert_eq!(
+            rebased,
+            Operation::MoveImportance {
+                user,
+                from: 1,
+                to: 2
+            }
+        );
+        rebased.apply(&onto).unwrap();
+        assert_eq!(
+            onto.user_importance(user),
+            [TaskId(2), TaskId(0), TaskId(1)]
+        );
+
+        let text = Operation::SetTaskText {
+            task: TaskId(2),
+            text: String::new(),
+        };
+        assert_eq!(text.rebase(&onto, &made_on), None);
+        assert_eq!(text.rebase(&made_on, &onto), Some(text));
+        let renamed = Operation::RenameUser {
+            user: UserId(1),
+            alias: String::new(),
+        };
+        assert_eq!(renamed.rebase(&made_on, &onto), None);
+    }
+}
@@ -0,0 +1,367 @@
+use super::{ExternalRef, OrderedTasks, SimpleAthensSpace, Task, TaskId, User, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Ballot {
+    Importance,
+    Easiness,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit<T> {
+    pub before: T,
+    pub after: T,
+}
+
+/// A task that was added to, removed from, or moved within an ordering.
+/// Tasks that only shifted because others moved around them are not moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub task: TaskId,
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BallotMove {
+    pub user: UserId,
+    pub ballot: Ballot,
+    #[serde(flatten)]
+    pub change: Move,
+}
+
+/// Everything that changed between two snapshots of a space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct SpaceDiff {
+    pub tasks_added: Vec<Task>,
+    pub tasks_removed: Vec<Task>,
+    pub tasks_edited: BTreeMap<TaskId, Edit<String>>,
+    /// Tasks whose link to an issue elsewhere was added, changed or removed.
+    pub tasks_relinked: BTreeMap<TaskId, Edit<Option<ExternalRef>>>,
+    pub users_added: Vec<User>,
+    pub users_removed: Vec<User>,
+    pub users_renamed: BTreeMap<UserId, Edit<String>>,
+    pub users_reweighted: BTreeMap<UserId, Edit<u32>>,
+    pub ballot_moves: Vec<BallotMove>,
+    pub importance: Vec<Move>,
+    pub easiness: Vec<Move>,
+}
+
+impl SimpleAthensSpace {
+    /// What changed going from `self` to `after`.
+    pub fn diff(&self, after: &Self) -> SpaceDiff {
+        let mut diff = SpaceDiff::default();
+
+        let before_tasks: BTreeMap<_, _> = self.tasks.iter().map(|t| (t.id, t)).collect();
+        let after_tasks: BTreeMap<_, _> = after.tasks.iter().map(|t| (t.id, t)).collect();
+        for (id, &b) in before_tasks.iter() {
+            let a = match after_tasks.get(id) {
+                Some(a) => a,
+                None => {
+                    diff.tasks_removed.push(b.clone());
+                    continue;
+                }
+            };
+            if a.text != b.text {
+                diff.tasks_edited.insert(*id, edit(&b.text, &a.text));
+            }
+            if a.external != b.external {
+                diff.tasks_relinked
+                    .insert(*id, edit(&b.external, &a.external));
+            }
+        }
+        for (id, &a) in after_tasks.iter() {
+            if !before_tasks.contains_key(id) {
+                diff.tasks_added.push(a.clone());
+            }
+        }
+
+        let before_users: BTreeMap<_, _> = self.users.iter().map(|u| (u.user.id, u)).collect();
+        let after_users: BTreeMap<_, _> = after.users.iter().map(|u| (u.user.id, u)).collect();
+        for (id, &b) in before_users.iter() {
+            let a = match after_users.get(id) {
+                Some(a) => a,
+                None => {
+                    diff.users_removed.push(b.user.clone());
+                    continue;
+                }
+            };
+            if a.user.alias != b.user.alias {
+                diff.users_renamed
+                    .insert(*id, edit(&b.user.alias, &a.user.alias));
+            }
+            if a.user.weight != b.user.weight {
+                diff.users_reweighted
+                    .insert(*id, edit(&b.user.weight, &a.user.weight));
+            }
+            for (ballot, b, a) in [
+                (Ballot::Importance, &b.importance, &a.importance),
+                (Ballot::Easiness, &b.easiness, &a.easiness),
+            ] {
+                diff.ballot_moves
+                    .extend(moves(b, a).into_iter().map(|change| BallotMove {
+                        user: *id,
+                        ballot,
+                        change,
+                    }));
+            }
+        }
+        for (id, &a) in after_users.iter() {
+            if !before_users.contains_key(id) {
+                diff.users_added.push(a.user.clone());
+            }
+        }
+
+        diff.importance = moves(&self.importance(), &after.importance());
+        diff.easiness = moves(&self.easiness(), &after.easiness());
+        diff
+    }
+}
+
+fn edit<T: Clone>(before: &T, after: &T) -> Edit<T> {
+    Edit {
+        before: before.clone(),
+        after: after.clone(),
+    }
+}
+
+/// The smallest set of tasks that were added, removed, or moved to get from
+/// `before` to `after`. Tasks that kept their relative order, i.e. the
+/// longest increasing run of old positions, did not move.
+fn moves(before: &OrderedTasks, after: &OrderedTasks) -> Vec<Move> {
+    let before_pos: BTreeMap<_, _> = before.iter().enumerate().map(|(i, t)| (t, i)).collect();
+    let after_pos: BTreeMap<_, _> = after.iter().enumerate().map(|(i, t)| (t, i)).collect();
+    // Old positions of kept tasks, in their new order.
+    let kept: Vec<(TaskId, usize)> = after
+        .iter()
+        .filter_map(|t| before_pos.get(&t).map(|&i| (t, i)))
+        .collect();
+    let stayed = longest_increasing(&kept.iter().map(|&(_, i)| i).collect::<Vec<_>>());
+
+    let mut moves = Vec::new();
+    for (k, &(task, before)) in kept.iter().enumerate() {
+        if !stayed.contains(&k) {
+            moves.push(Move {
+                task,
+                before: Some(before),
+                after: Some(after_pos[&task]),
+            });
+        }
+    }
+    for task in after.iter().filter(|t| !before_pos.contains_key(t)) {
+        moves.push(Move {
+            task,
+            before: None,
+            after: Some(after_pos[&task]),
+        });
+    }
+    for task in before.iter().filter(|t| !after_pos.contains_key(t)) {
+        moves.push(Move {
+            task,
+            before: Some(before_pos[&task]),
+            after: None,
+        });
+    }
+    moves
+}
+
+/// Indices of a longest strictly increasing subsequence of `xs`.
+fn longest_increasing(xs: &[usize]) -> Vec<usize> {
+    // O(n^2) dynamic programming, ballots are small.
+    let mut len = vec![1; xs.len()];
+    let mut prev = vec![None; xs.len()];
+    for i in 0..xs.len() {
+        for j in 0..i {
+            if xs[j] < xs[i] && len[j] + 1 > len[i] {
+                len[i] = len[j] + 1;
+                prev[i] = Some(j);
+            }
+        }
+    }
+    let mut end = (0..xs.len()).max_by_key(|&i| (len[i], std::cmp::Reverse(i)));
+    let mut indices = Vec::new();
+    while let Some(i) = end {
+        indices.push(i);
+        end = prev[i];
+    }
+    indices.reverse();
+    indices
+}
+
+impl SpaceDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+    /// One human readable line per change.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for t in self.tasks_added.iter() {
+            lines.push(format!("Added {:?} {:?}", t.id, t.text));
+        }
+        for t in self.tasks_removed.iter() {
+            lines.push(format!("Removed {:?} {:?}", t.id, t.text));
+        }
+        for (id, e) in self.tasks_edited.iter() {
+            lines.push(format!("Edited {:?}: {:?} -> {:?}", id, e.before, e.after));
+        }
+        for (id, e) in self.tasks_relinked.iter() {
+            let link = |external: &Option<ExternalRef>| match external {
+                Some(e) if e.url.is_empty() => format!("#{}", e.number),
+                Some(e) => format!("#{} {}", e.number, e.url),
+                None => "nothing".to_string(),
+            };
+            lines.push(format!(
+                "Relinked {:?}: {} -> {}",
+                id,
+                link(&e.before),
+                link(&e.after)
+            ));
+        }
+        for u in self.users_added.iter() {
+            lines.push(format!("Added user/{} {:?}", u.id.0, u.alias));
+        }
+        for u in self.users_removed.iter() {
+            lines.push(format!("Removed user/{} {:?}", u.id.0, u.alias));
+        }
+        for (id, e) in self.users_renamed.iter() {
+            lines.push(format!(
+                "Renamed user/{}: {:?} -> {:?}",
+                id.0, e.before, e.after
+            ));
+        }
+        for (id, e) in self.users_reweighted.iter() {
+            lines.push(format!(
+                "Reweighted user/{}: {} -> {}",
+                id.0, e.before, e.after
+            ));
+        }
+        for m in self.ballot_moves.iter() {
+            if let (Some(before), Some(after)) = (m.change.before, m.change.after) {
+                lines.push(format!(
+                    "user/{} moved {:?} from #{} to #{} by {:?}",
+                    m.user.0,
+                    m.change.task,
+                    before + 1,
+                    after + 1,
+                    m.ballot
+                ));
+            }
+        }
+        for (name, moves) in [
+            ("importance", &self.importance),
+            ("easiness", &self.easiness),
+        ] {
+            for m in moves.iter() {
+                if let (Some(before), Some(after)) = (m.before, m.after) {
+                    lines.push(format!(
+                        "{:?} is now #{} by {} (was #{})",
+                        m.task,
+                        after + 1,
+                        name,
+                        before + 1
+                    ));
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn ids(ids: &[usize]) -> OrderedTasks {
+        OrderedTasks::from_vec(ids.iter().map(|&i| TaskId(i)).collect::<Vec<_>>())
+    }
+    #[test]
+    fn moves_ignores_tasks_that_only_shifted() {
+        assert_eq!(
+            moves(&ids(&[0, 1, 2, 3]), &ids(&[1, 2, 0, 3])),
+            vec![Move {
+                task: TaskId(0),
+                before: Some(0),
+                after: Some(2),
+            }]
+        );
+        assert_eq!(
+            moves(&ids(&[0, 1]), &ids(&[2, 0, 1])),
+            vec![Move {
+                task: TaskId(2),
+                before: None,
+                after: Some(0),
+            }]
+        );
+        assert_eq!(moves(&ids(&[0, 1]), &ids(&[0, 1])), vec![]);
+    }
+    #[test]
+    fn diff_of_identical_spaces_is_empty() {
+        let mut s = SimpleAthensSpace::new();
+        s.new_task();
+        s.new_user();
+        assert!(s.diff(&s.clone()).is_empty());
+    }
+    #[test]
+    fn diff_finds_edits_users_and_moves() {
+        let mut before = SimpleAthensSpace::new();
+        before.new_task().text = "a".to_string();
+        before.new_task().text = "b".to_string();
+        before.new_user();
+        let mut after = before.clone();
+        after.mut_task(TaskId(0)).unwrap().text = "A".to_string();
+        after.new_task().text = "c".to_string();
+        after.mut_user(UserId(0)).unwrap().user.alias = "casper".to_string();
+        after.mut_user(UserId(0)).unwrap().move_easiness(0, 2);
+        after.new_user().user.weight = 2;
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.tasks_added.len(), 1);
+        assert_eq!(diff.tasks_added[0].text, "c");
+        assert_eq!(
+            diff.tasks_edited[&TaskId(0)],
+            edit(&"a".into(), &"A".into())
+        );
+        assert_eq!(diff.users_added.len(), 1);
+        assert_eq!(diff.users_renamed[&UserId(0)].after, "casper");
+        assert_eq!(
+            diff.ballot_moves
+                .iter()
+                .filter(|m| m.ballot == Ballot::Easiness)
+                .map(|m| m.change)
+                .collect::<Vec<_>>(),
+            vec![Move {
+                task: TaskId(2),
+                before: None,
+                after: Some(2),
+            }]
+        );
+        assert!(!diff.summary().is_empty());
+    }
+    #[test]
+    fn diff_finds_changed_issue_links() {
+        let mut before = SimpleAthensSpace::new();
+        before.new_task().external = Some(ExternalRef {
+            number: 1,
+            url: "https://example.com/issues/1".to_string(),
+        });
+        before.new_task();
+        let mut after = before.clone();
+        after.mut_task(TaskId(0)).unwrap().external = None;
+        after.mut_task(TaskId(1)).unwrap().external = Some(ExternalRef {
+            number: 2,
+            url: String::new(),
+        });
+
+        let diff = before.diff(&after);
+        assert!(diff.tasks_edited.is_empty());
+        assert_eq!(diff.tasks_relinked[&TaskId(0)].after, None);
+        assert_eq!(diff.tasks_relinked[&TaskId(1)].before, None);
+        assert_eq!(
+            diff.summary(),
+            [
+                "Relinked task/0: #1 https://example.com/issues/1 -> nothing",
+                "Relinked task/1: nothing -> #2"
+            ]
+        );
+    }
+}
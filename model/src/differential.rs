@@ -0,0 +1,526 @@
+//! Differential testing of AthensSpace implementations.
+//!
+//! A sequence of [`Op`]s is applied to a reference `ParallelSimpleAthensSpace`
+//! and to another implementation. After every op each read is compared, and a
+//! failing sequence is shrunk to a small one that still fails.
+//!
+//! Ops refer to users and tasks by index into the ids that exist when the op
+//! is applied, so any sequence is valid, which makes them easy to generate,
+//! fuzz and shrink. An index one past the end names a user that does not
+//! exist, so lookups of missing users are compared too.
+
+use super::{AthensSpace, OrderedTasks, ParallelSimpleAthensSpace, SimpleAthensSpace, TaskId};
+use super::{User, UserId};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Op {
+    CreateUser,
+    CreateTask,
+    SetUser {
+        user: usize,
+        alias: String,
+        weight: u8,
+    },
+    /// Links the task to issue `issue`, if given.
+    SetTask {
+        task: usize,
+        text: String,
+        issue: Option<u8>,
+    },
+    /// Orders the tasks by `keys`, cycling through them.
+    SetImportance {
+        user: usize,
+        keys: Vec<u8>,
+    },
+    SetEasiness {
+        user: usize,
+        keys: Vec<u8>,
+    },
+    SwapImportance {
+        user: usize,
+        from: usize,
+        to: usize,
+    },
+    SwapEasiness {
+        user: usize,
+        from: usize,
+        to: usize,
+    },
+}
+
+/// The first read on which two spaces disagreed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// How many ops had been applied, the op itself is included when its
+    /// return value differed.
+    pub step: usize,
+    pub read: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "after {} ops {} was {} but expected {}",
+            self.step, self.read, self.actual, self.expected
+        )
+    }
+}
+
+impl Op {
+    /// Applies the op and describes what it returned.
+    fn apply(&self, space: &dyn AthensSpace, users: &[UserId], tasks: &[TaskId]) -> String {
+        let user = |i: usize| UserId(i % (users.len() + 1));
+        let ord = |u: UserId, easiness: bool| {
+            if u.0 == users.len() {
+                OrderedTasks::default()
+            } else if easiness {
+                space.user_easiness(u)
+            } else {
+                space.user_importance(u)
+            }
+        };
+        let swap = |u: UserId, from: usize, to: usize, easiness: bool| {
+            let n = ord(u, easiness).iter().count();
+            if n == 0 {
+                // Both indices are out of range, which the reference panics on.
+                return None;
+            }
+            let (from, to) = (from % n, to % n);
+            if easiness {
+                space.swap_user_easiness(u, from, to)
+            } else {
+                space.swap_user_importance(u, from, to)
+            }
+        };
+        match self {
+            Op::CreateUser => format!("{:?}", space.create_user()),
+            Op::CreateTask => format!("{:?}", space.create_task()),
+            Op::SetUser {
+                user: u,
+                alias,
+                weight,
+            } => format!(
+                "{:?}",
+                space.set_user(User {
+                    id: user(*u),
+                    alias: alias.clone(),
+                    weight: *weight as u32,
+                })
+            ),
+            Op::SetTask { task, text, issue } => match tasks.len() {
+                0 => "no tasks".to_string(),
+                n => format!(
+                    "{:?}",
+                    space.set_task(super::Task {
+                        id: tasks[task % n],
+                        text: text.clone(),
+                        external: issue.map(|number| super::ExternalRef {
+                            number: number.into(),
+                            url: format!("https://example.com/issues/{}", number),
+                        }),
+                    })
+                ),
+            },
+            Op::SetImportance { user: u, keys } => {
+                format!(
+                    "{:?}",
+                    space.set_user_importance(user(*u), permutation(tasks, keys))
+                )
+            }
+            Op::SetEasiness { user: u, keys } => {
+                format!(
+                    "{:?}",
+                    space.set_user_easiness(user(*u), permutation(tasks, keys))
+                )
+            }
+            Op::SwapImportance { user: u, from, to } => {
+                format!("{:?}", swap(user(*u), *from, *to, false))
+            }
+            Op::SwapEasiness { user: u, from, to } => {
+                format!("{:?}", swap(user(*u), *from, *to, true))
+            }
+        }
+    }
+
+    /// Simpler versions of this op, used for shrinking.
+    fn simplifications(&self) -> Vec<Op> {
+        let mut simpler = Vec::new();
+        match self {
+            Op::CreateUser | Op::CreateTask => {}
+            Op::SetUser {
+                user,
+                alias,
+                weight,
+            } => {
+                if !alias.is_empty() || *weight != 0 {
+                    simpler.push(Op::SetUser {
+                        user: *user,
+                        alias: String::new(),
+                        weight: 0,
+                    });
+                }
+                if *user != 0 {
+                    simpler.push(Op::SetUser {
+                        user: 0,
+                        alias: alias.clone(),
+                        weight: *weight,
+                    });
+                }
+            }
+            Op::SetTask { task, text, issue } => {
+                if !text.is_empty() {
+                    simpler.push(Op::SetTask {
+                        task: *task,
+                        text: String::new(),
+                        issue: *issue,
+                    });
+                }
+                if issue.is_some() {
+                    simpler.push(Op::SetTask {
+                        task: *task,
+                        text: text.clone(),
+                        issue: None,
+                    });
+                }
+                if *task != 0 {
+                    simpler.push(Op::SetTask {
+                        task: 0,
+                        text: text.clone(),
+                        issue: *issue,
+                    });
+                }
+            }
+            Op::SetImportance { user, keys } | Op::SetEasiness { user, keys } => {
+                let rebuild = |user, keys| match self {
+                    Op::SetImportance { .. } => Op::SetImportance { user, keys },
+                    _ => Op::SetEasiness { user, keys },
+                };
+                for i in 0..keys.len() {
+                    let mut fewer = keys.clone();
+                    fewer.remove(i);
+                    simpler.push(rebuild(*user, fewer));
+                }
+                if *user != 0 {
+                    simpler.push(rebuild(0, keys.clone()));
+                }
+            }
+            Op::SwapImportance { user, from, to } | Op::SwapEasiness { user, from, to } => {
+                let rebuild = |user, from, to| match self {
+                    Op::SwapImportance { .. } => Op::SwapImportance { user, from, to },
+                    _ => Op::SwapEasiness { user, from, to },
+                };
+                for (user, from, to) in [
+                    (0, *from, *to),
+                    (*user, 0, *to),
+                    (*user, *from, 0),
+                    (*user, from / 2, *to),
+                    (*user, *from, to / 2),
+                ] {
+                    let op = rebuild(user, from, to);
+                    if &op != self {
+                        simpler.push(op);
+                    }
+                }
+            }
+        }
+        simpler
+    }
+}
+
+/// The tasks sorted by `keys`, the `i`th task gets the `i`th key, cycling.
+/// Ties keep the tasks' original order.
+fn permutation(tasks: &[TaskId], keys: &[u8]) -> OrderedTasks {
+    let mut tasks = tasks.to_vec();
+    if !keys.is_empty() {
+        let key = |t: &TaskId| keys[t.0 % keys.len()];
+        tasks.sort_by_key(key);
+    }
+    OrderedTasks::from_vec(tasks)
+}
+
+fn check(read: &str, step: usize, expected: String, actual: String) -> Result<(), Mismatch> {
+    if expected == actual {
+        return Ok(());
+    }
+    Err(Mismatch {
+        step,
+        read: read.to_string(),
+        expected,
+        actual,
+    })
+}
+
+/// Compares every read of the two spaces.
+fn compare_reads(
+    expected: &dyn AthensSpace,
+    actual: &dyn AthensSpace,
+    step: usize,
+) -> Result<(), Mismatch> {
+    macro_rules! compare {
+        ($name:expr, |$s:ident| $read:expr) => {
+            check(
+                &$name,
+                step,
+                {
+                    let $s = expected;
+                    format!("{:?}", $read)
+                },
+                {
+                    let $s = actual;
+                    format!("{:?}", $read)
+                },
+            )?
+        };
+    }
+    compare!("id", |s| s.id());
+    compare!("tasks", |s| s.tasks());
+    compare!("users", |s| s.users());
+    compare!("important_tasks", |s| s.important_tasks());
+    compare!("easy_tasks", |s| s.easy_tasks());
+    compare!("important_and_easy_tasks", |s| s.important_and_easy_tasks());
+    for t in expected.tasks() {
+        compare!(format!("get_task({:?})", t), |s| s.get_task(t));
+    }
+    for u in expected.users() {
+        compare!(format!("get_user({:?})", u), |s| s.get_user(u));
+        compare!(format!("user_importance({:?})", u), |s| s
+            .user_importance(u));
+        compare!(format!("user_easiness({:?})", u), |s| s.user_easiness(u));
+        compare!(format!("user_important_and_easy({:?})", u), |s| s
+            .user_important_and_easy(u));
+    }
+    Ok(())
+}
+
+/// Applies `ops` to both spaces, comparing what each op returns and every
+/// read after it.
+pub fn compare(
+    expected: &dyn AthensSpace,
+    actual: &dyn AthensSpace,
+    ops: &[Op],
+) -> Result<(), Mismatch> {
+    compare_reads(expected, actual, 0)?;
+    for (i, op) in ops.iter().enumerate() {
+        let users = expected.users();
+        let tasks = expected.tasks();
+        check(
+            &format!("{:?}", op),
+            i + 1,
+            op.apply(expected, &users, &tasks),
+            op.apply(actual, &users, &tasks),
+        )?;
+        compare_reads(expected, actual, i + 1)?;
+    }
+    Ok(())
+}
+
+/// Finds a small subsequence of `ops`, with simplified ops, that still
+/// `fails`. `ops` itself must fail.
+pub fn shrink(ops: &[Op], mut fails: impl FnMut(&[Op]) -> bool) -> Vec<Op> {
+    let mut ops = ops.to_vec();
+    loop {
+        let mut progress = false;
+        // Remove chunks, halving their size down to single ops.
+        let mut chunk = (ops.len() / 2).max(1);
+        while chunk > 0 {
+            let mut start = 0;
+            while start < ops.len() {
+                let end = (start + chunk).min(ops.len());
+                let candidate = [&ops[..start], &ops[end..]].concat();
+                if fails(&candidate) {
+                    ops = candidate;
+                    progress = true;
+                } else {
+                    start += chunk;
+                }
+            }
+            chunk /= 2;
+        }
+        for i in 0..ops.len() {
+            for op in ops[i].simplifications() {
+                let mut candidate = ops.clone();
+                candidate[i] = op;
+                if fails(&candidate) {
+                    ops = candidate;
+                    progress = true;
+                    break;
+                }
+            }
+        }
+        if !progress {
+            return ops;
+        }
+    }
+}
+
+/// Runs `ops` on a new `ParallelSimpleAthensSpace` and a new space from
+/// `new_space`. If they disagree returns the shrunk ops and how they disagree.
+pub fn differential<S: AthensSpace>(
+    new_space: impl Fn() -> S,
+    ops: &[Op],
+) -> Result<(), (Vec<Op>, Mismatch)> {
+    let run = |ops: &[Op]| {
+        let reference: ParallelSimpleAthensSpace = Arc::new(Mutex::new(SimpleAthensSpace::new()));
+        compare(&reference, &new_space(), ops)
+    };
+    if run(ops).is_ok() {
+        return Ok(());
+    }
+    let ops = shrink(ops, |ops| run(ops).is_err());
+    let mismatch = run(&ops).unwrap_err();
+    Err((ops, mismatch))
+}
+
+/// A deterministic pseudo random sequence of `len` ops.
+pub fn random_ops(seed: u64, len: usize) -> Vec<Op> {
+    // xorshift64*, zero is a fixed point so avoid it.
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut next = |n: usize| {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as usize % n
+    };
+    (0..len)
+        .map(|_| match next(10) {
+            0 => Op::CreateUser,
+            1 | 2 => Op::CreateTask,
+            3 => Op::SetUser {
+                user: next(8),
+                alias: format!("user {}", next(100)),
+                weight: next(4) as u8,
+            },
+            4 => Op::SetTask {
+                task: next(16),
+                text: format!("task {}", next(100)),
+                issue: (next(4) == 0).then(|| next(256) as u8),
+            },
+            5 => Op::SetImportance {
+                user: next(8),
+                keys: (0..next(8)).map(|_| next(256) as u8).collect(),
+            },
+            6 => Op::SetEasiness {
+                user: next(8),
+                keys: (0..next(8)).map(|_| next(256) as u8).collect(),
+            },
+            7 | 8 => Op::SwapImportance {
+                user: next(8),
+                from: next(16),
+                to: next(16),
+            },
+            _ => Op::SwapEasiness {
+                user: next(8),
+                from: next(16),
+                to: next(16),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Task;
+
+    fn assert_agrees<S: AthensSpace>(new_space: impl Fn() -> S) {
+        for seed in 0..100 {
+            if let Err((ops, mismatch)) = differential(&new_space, &random_ops(seed, 40)) {
+                panic!("seed {}: {}\nops: {:#?}", seed, mismatch, ops);
+            }
+        }
+    }
+
+    #[test]
+    fn simple_agrees_with_itself() {
+        assert_agrees(|| Arc::new(Mutex::new(SimpleAthensSpace::new())));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_agrees_with_simple() {
+        assert_agrees(|| crate::SqliteAthensSpace::open_in_memory().unwrap());
+    }
+
+    /// Forgets task text edits once there are three tasks.
+    struct Forgetful(ParallelSimpleAthensSpace);
+
+    impl AthensSpace for Forgetful {
+        fn id(&self) -> crate::SpaceId {
+            self.0.id()
+        }
+        fn tasks(&self) -> Vec<TaskId> {
+            self.0.tasks()
+        }
+        fn important_tasks(&self) -> OrderedTasks {
+            self.0.important_tasks()
+        }
+        fn easy_tasks(&self) -> OrderedTasks {
+            self.0.easy_tasks()
+        }
+        fn important_and_easy_tasks(&self) -> OrderedTasks {
+            self.0.important_and_easy_tasks()
+        }
+        fn users(&self) -> Vec<UserId> {
+            self.0.users()
+        }
+        fn create_user(&self) -> User {
+            self.0.create_user()
+        }
+        fn get_user(&self, user: UserId) -> Option<User> {
+            self.0.get_user(user)
+        }
+        fn set_user(&self, user: User) -> Option<User> {
+            self.0.set_user(user)
+        }
+        fn create_task(&self) -> Task {
+            self.0.create_task()
+        }
+        fn get_task(&self, id: TaskId) -> Option<Task> {
+            self.0.get_task(id)
+        }
+        fn set_task(&self, task: Task) -> Option<Task> {
+            if self.0.tasks().len() >= 3 {
+                return self.0.get_task(task.id);
+            }
+            self.0.set_task(task)
+        }
+        fn user_importance(&self, id: UserId) -> OrderedTasks {
+            self.0.user_importance(id)
+        }
+        fn user_easiness(&self, id: UserId) -> OrderedTasks {
+            self.0.user_easiness(id)
+        }
+        fn user_important_and_easy(&self, id: UserId) -> OrderedTasks {
+            self.0.user_important_and_easy(id)
+        }
+        fn set_user_importance(&self, id: UserId, ord: OrderedTasks) -> Option<OrderedTasks> {
+            self.0.set_user_importance(id, ord)
+        }
+        fn set_user_easiness(&self, id: UserId, ord: OrderedTasks) -> Option<OrderedTasks> {
+            self.0.set_user_easiness(id, ord)
+        }
+        fn swap_user_importance(&self, id: UserId, from: usize, to: usize) -> Option<OrderedTasks> {
+            self.0.swap_user_importance(id, from, to)
+        }
+        fn swap_user_easiness(&self, id: UserId, from: usize, to: usize) -> Option<OrderedTasks> {
+            self.0.swap_user_easiness(id, from, to)
+        }
+    }
+
+    #[test]
+    fn failures_shrink_to_minimal_ops() {
+        let new_space = || Forgetful(Arc::new(Mutex::new(SimpleAthensSpace::new())));
+        let (ops, mismatch) = (0..)
+            .find_map(|seed| differential(new_space, &random_ops(seed, 40)).err())
+            .unwrap();
+        assert_eq!(ops[..3], [Op::CreateTask, Op::CreateTask, Op::CreateTask]);
+        assert!(matches!(ops[3..], [Op::SetTask { task: 0, .. }]));
+        assert_eq!(mismatch.step, 4);
+    }
+}
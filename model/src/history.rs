@@ -0,0 +1,261 @@
+use super::{ranked_pairs_ordering, OrderedTasks, TaskId, UserId};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Seconds since the unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp(pub u64);
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+impl Timestamp {
+    /// Midnight UTC at the start of the given calendar day, `None` for days
+    /// the month does not have like February 30th.
+    pub fn from_date(year: i64, month: u32, day: u32) -> Option<Self> {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        if !(1..=days_in_month).contains(&day) {
+            return None;
+        }
+        // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let mp = (month as i64 + 9) % 12;
+        let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        u64::try_from(days)
+            .ok()
+            .map(|d| Timestamp(d * SECONDS_PER_DAY))
+    }
+    /// The UTC calendar day as `(year, month, day)`.
+    pub fn to_date(self) -> (i64, u32, u32) {
+        // Civil from days, the inverse of `from_date`.
+        let z = (self.0 / SECONDS_PER_DAY) as i64 + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        (year, month, day)
+    }
+}
+
+impl std::str::FromStr for Timestamp {
+    type Err = String;
+    /// Parses either unix seconds or a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(secs) = s.parse::<u64>() {
+            return Ok(Timestamp(secs));
+        }
+        let bad = || format!("Expected unix seconds or YYYY-MM-DD, got {:?}", s);
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().ok_or_else(bad);
+        let year = next()?.parse().map_err(|_| bad())?;
+        let month = next()?.parse().map_err(|_| bad())?;
+        let day = next()?.parse().map_err(|_| bad())?;
+        Timestamp::from_date(year, month, day).ok_or_else(bad)
+    }
+}
+
+/// A user's ballots as they were at some point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BallotRecord {
+    pub at: Timestamp,
+    pub user: UserId,
+    pub importance: OrderedTasks,
+    pub easiness: OrderedTasks,
+    /// The user was removed from the space, and has no ballots from then on.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub removed: bool,
+}
+
+/// Where a task was in an aggregate ordering at some point in time.
+/// `rank` is None if the task did not exist yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RankPoint {
+    pub at: Timestamp,
+    pub rank: Option<usize>,
+}
+
+/// Timestamped record of every ballot change in a space, oldest first.
+// Records are only appended when a user's ballots change. Adding a task
+// changes every ballot, so the latest records of all users at any point in
+// time always cover the same set of tasks. Users that are removed, like by
+// restoring an older space, are recorded as such so they drop out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct BallotHistory(Vec<BallotRecord>);
+
+impl BallotHistory {
+    /// Records the user's ballots if they differ from the latest record.
+    /// Returns whether anything was recorded.
+    pub fn record(
+        &mut self,
+        at: Timestamp,
+        user: UserId,
+        importance: &OrderedTasks,
+        easiness: &OrderedTasks,
+    ) -> bool {
+        if let Some(latest) = self.0.iter().rev().find(|r| r.user == user) {
+            if &latest.importance == importance && &latest.easiness == easiness {
+                return false;
+            }
+        }
+        // Clocks may go backwards, but history should not.
+        let at = self.0.last().map_or(at, |r| r.at.max(at));
+        self.0.push(BallotRecord {
+            at,
+            user,
+            importance: importance.clone(),
+            easiness: easiness.clone(),
+            removed: false,
+        });
+        true
+    }
+    /// Records that the user is gone, if they had ballots. Returns whether
+    /// anything was recorded.
+    pub fn record_removal(&mut self, at: Timestamp, user: UserId) -> bool {
+        match self.0.iter().rev().find(|r| r.user == user) {
+            Some(latest) if !latest.removed => {}
+            _ => return false,
+        }
+        let at = self.0.last().map_or(at, |r| r.at.max(at));
+        self.0.push(BallotRecord {
+            at,
+            user,
+            importance: OrderedTasks::default(),
+            easiness: OrderedTasks::default(),
+            removed: true,
+        });
+        true
+    }
+    /// The users with ballots after the latest record.
+    pub fn voters(&self) -> Vec<UserId> {
+        self.ballots_at(Timestamp(u64::MAX)).into_keys().collect()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &BallotRecord> + '_ {
+        self.0.iter()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    /// Every user's ballots as they were at `at`.
+    pub fn ballots_at(&self, at: Timestamp) -> BTreeMap<UserId, &BallotRecord> {
+        let mut ballots = BTreeMap::new();
+        for r in self.0.iter().take_while(|r| r.at <= at) {
+            if r.removed {
+                ballots.remove(&r.user);
+            } else {
+                ballots.insert(r.user, r);
+            }
+        }
+        ballots
+    }
+    /// The aggregated importance ordering at `at`.
+    pub fn importance_at(&self, at: Timestamp) -> OrderedTasks {
+        let ords: Vec<_> = self
+            .ballots_at(at)
+            .into_values()
+            .map(|r| r.importance.clone())
+            .collect();
+        ranked_pairs_ordering(&ords)
+    }
+    /// The aggregated easiness ordering at `at`.
+    pub fn easiness_at(&self, at: Timestamp) -> OrderedTasks {
+        let ords: Vec<_> = self
+            .ballots_at(at)
+            .into_values()
+            .map(|r| r.easiness.clone())
+            .collect();
+        ranked_pairs_ordering(&ords)
+    }
+    /// The task's rank in the importance ordering at `from` and after every
+    /// change up to and including `to`.
+    pub fn importance_trajectory(
+        &self,
+        task: TaskId,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Vec<RankPoint> {
+        let mut times = vec![from];
+        times.extend(self.0.iter().map(|r| r.at).filter(|&t| from < t && t <= to));
+        times.dedup();
+        times
+            .into_iter()
+            .map(|at| RankPoint {
+                at,
+                rank: self.importance_at(at).iter().position(|id| id == task),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn ord(ids: &[usize]) -> OrderedTasks {
+        OrderedTasks::from_vec(ids.iter().map(|&i| TaskId(i)).collect::<Vec<_>>())
+    }
+    #[test]
+    fn timestamp_parses_dates_and_seconds() {
+        assert_eq!("1970-01-01".parse(), Ok(Timestamp(0)));
+        assert_eq!("2026-09-01".parse(), Ok(Timestamp(1_788_220_800)));
+        assert_eq!("1234".parse(), Ok(Timestamp(1234)));
+        assert_eq!(Timestamp(1_788_220_800 + 60).to_date(), (2026, 9, 1));
+        assert_eq!(Timestamp(0).to_date(), (1970, 1, 1));
+        for date in [(2000, 2, 29), (2026, 12, 31), (2100, 3, 1)] {
+            let (y, m, d) = date;
+            assert_eq!(Timestamp::from_date(y, m, d).unwrap().to_date(), date);
+        }
+        assert!("2026-13-01".parse::<Timestamp>().is_err());
+        for impossible in ["2023-02-31", "2023-02-29", "2100-02-29", "2026-04-31"] {
+            assert!(impossible.parse::<Timestamp>().is_err(), "{}", impossible);
+        }
+        assert!("yesterday".parse::<Timestamp>().is_err());
+    }
+    #[test]
+    fn record_skips_unchanged_ballots() {
+        let mut h = BallotHistory::default();
+        assert!(h.record(Timestamp(1), UserId(0), &ord(&[0, 1]), &ord(&[0, 1])));
+        assert!(!h.record(Timestamp(2), UserId(0), &ord(&[0, 1]), &ord(&[0, 1])));
+        assert!(h.record(Timestamp(3), UserId(0), &ord(&[1, 0]), &ord(&[0, 1])));
+        assert_eq!(h.iter().count(), 2);
+    }
+    #[test]
+    fn importance_at_past_time() {
+        let mut h = BallotHistory::default();
+        h.record(Timestamp(10), UserId(0), &ord(&[0, 1]), &ord(&[0, 1]));
+        h.record(Timestamp(10), UserId(1), &ord(&[0, 1]), &ord(&[0, 1]));
+        h.record(Timestamp(20), UserId(0), &ord(&[1, 0]), &ord(&[0, 1]));
+        h.record(Timestamp(30), UserId(1), &ord(&[1, 0]), &ord(&[0, 1]));
+        assert_eq!(h.importance_at(Timestamp(5)), vec![]);
+        assert_eq!(h.importance_at(Timestamp(15)), vec![TaskId(0), TaskId(1)]);
+        assert_eq!(h.importance_at(Timestamp(30)), vec![TaskId(1), TaskId(0)]);
+    }
+    #[test]
+    fn importance_trajectory_tracks_rank() {
+        let mut h = BallotHistory::default();
+        h.record(Timestamp(10), UserId(0), &ord(&[0]), &ord(&[0]));
+        h.record(Timestamp(20), UserId(0), &ord(&[1, 0]), &ord(&[1, 0]));
+        h.record(Timestamp(30), UserId(0), &ord(&[0, 1]), &ord(&[1, 0]));
+        h.record(Timestamp(40), UserId(0), &ord(&[1, 0]), &ord(&[1, 0]));
+        let at = |t, rank| RankPoint {
+            at: Timestamp(t),
+            rank,
+        };
+        assert_eq!(
+            h.importance_trajectory(TaskId(1), Timestamp(0), Timestamp(30)),
+            vec![at(0, None), at(10, None), at(20, Some(0)), at(30, Some(1))]
+        );
+    }
+}
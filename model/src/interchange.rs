@@ -0,0 +1,443 @@
+//! Moving tasks in and out of Athens as CSV, Markdown checklists and
+//! todo.txt, and publishing them as an iCalendar feed.
+
+use super::{AthensSpace, OrderedTasks, TaskId, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+    /// `rank,id,text` followed by any extra columns, with a header row.
+    Csv,
+    /// A `- [ ] text` checklist.
+    Markdown,
+    /// One task per line, prioritized `(A)` to `(Z)` by rank.
+    TodoTxt,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "md" | "markdown" => Ok(Format::Markdown),
+            "txt" | "todo" | "todo.txt" => Ok(Format::TodoTxt),
+            _ => Err(format!("Unknown format {:?}", s)),
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Markdown => "md",
+            Format::TodoTxt => "txt",
+        }
+    }
+}
+
+/// An extra CSV column with a value per task, tasks without one are blank.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Column {
+    pub name: String,
+    pub values: BTreeMap<TaskId, String>,
+}
+
+/// The result of an import.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Imported {
+    pub created: Vec<TaskId>,
+    /// Existing tasks changed to match what was imported.
+    #[serde(default)]
+    pub updated: Vec<TaskId>,
+    /// Texts that matched an existing or earlier imported task.
+    pub duplicates: Vec<String>,
+}
+
+/// Writes the tasks in `order` in the given format. `extra` columns are only
+/// written to CSV.
+pub fn export(
+    space: &dyn AthensSpace,
+    order: &OrderedTasks,
+    format: Format,
+    extra: &[Column],
+) -> String {
+    let texts: Vec<(TaskId, String)> = order
+        .iter()
+        .map(|id| (id, space.get_task(id).map(|t| t.text).unwrap_or_default()))
+        .collect();
+    let mut out = String::new();
+    match format {
+        Format::Csv => {
+            let mut header = vec!["rank", "id", "text"];
+            header.extend(extra.iter().map(|c| c.name.as_str()));
+            write_csv_row(&mut out, header);
+            for (rank, (id, text)) in texts.iter().enumerate() {
+                let mut row = vec![(rank + 1).to_string(), id.0.to_string(), text.clone()];
+                for column in extra {
+                    row.push(column.values.get(id).cloned().unwrap_or_default());
+                }
+                write_csv_row(&mut out, row.iter().map(String::as_str));
+            }
+        }
+        Format::Markdown => {
+            for (_, text) in texts.iter() {
+                out.push_str(&format!("- [ ] {}\n", one_line(text)));
+            }
+        }
+        Format::TodoTxt => {
+            for (rank, (_, text)) in texts.iter().enumerate() {
+                let priority = priority(rank, texts.len());
+                out.push_str(&format!("({}) {}\n", priority, one_line(text)));
+            }
+        }
+    }
+    out
+}
+
+/// An iCalendar feed of the space's tasks as VTODOs, prioritized by the
+/// consensus importance. UIDs only depend on the space and task ids, so
+/// subscribers update tasks in place.
+pub fn export_ical(space: &dyn AthensSpace, now: Timestamp) -> String {
+    let order = space.important_tasks();
+    let len = order.iter().count();
+    let stamp = ical_time(now);
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Athens//Athens//EN".to_string(),
+    ];
+    for (rank, id) in order.iter().enumerate() {
+        let text = space.get_task(id).map(|t| t.text).unwrap_or_default();
+        lines.extend([
+            "BEGIN:VTODO".to_string(),
+            format!("UID:space-{}-task-{}@athens", space.id().0, id.0),
+            format!("DTSTAMP:{}", stamp),
+            format!("SUMMARY:{}", ical_escape(&text)),
+            // 1 is the highest priority and 9 the lowest.
+            format!("PRIORITY:{}", 1 + rank * 9 / len.max(9)),
+            "STATUS:NEEDS-ACTION".to_string(),
+            "END:VTODO".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+    lines.iter().map(|l| fold(l)).collect()
+}
+
+fn ical_time(at: Timestamp) -> String {
+    let (year, month, day) = at.to_date();
+    let secs = at.0 % (24 * 60 * 60);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn ical_escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Ends the line with CRLF, folding it so no line is over 75 bytes.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            width = 1;
+        }
+        folded.push(c);
+        width += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// Creates a task for each one in `input` whose text is not already in the
+/// space.
+pub fn import(space: &dyn AthensSpace, input: &str, format: Format) -> Imported {
+    let mut seen: BTreeSet<String> = space
+        .tasks()
+        .into_iter()
+        .filter_map(|id| space.get_task(id))
+        .map(|t| t.text.trim().to_string())
+        .collect();
+    let mut imported = Imported::default();
+    for text in parse(input, format) {
+        if !seen.insert(text.clone()) {
+            imported.duplicates.push(text);
+            continue;
+        }
+        let mut task = space.create_task();
+        task.text = text;
+        imported.created.push(task.id);
+        space.set_task(task);
+    }
+    imported
+}
+
+/// The trimmed, non-empty task texts in `input`.
+fn parse(input: &str, format: Format) -> Vec<String> {
+    let texts = match format {
+        Format::Csv => {
+            let mut rows = read_csv(input).into_iter();
+            let header = rows.next().unwrap_or_default();
+            match header
+                .iter()
+                .position(|h| h.trim().eq_ignore_ascii_case("text"))
+            {
+                Some(column) => rows
+                    .filter_map(|mut r| r.get_mut(column).map(std::mem::take))
+                    .collect(),
+                // Without a header every row is a task, in the first column.
+                None => std::iter::once(header)
+                    .chain(rows)
+                    .filter_map(|r| r.into_iter().next())
+                    .collect(),
+            }
+        }
+        Format::Markdown => input.lines().filter_map(markdown_item).collect(),
+        Format::TodoTxt => input.lines().map(todo_txt_text).collect::<Vec<_>>(),
+    };
+    texts
+        .into_iter()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn one_line(text: &str) -> String {
+    text.lines().collect::<Vec<_>>().join(" ")
+}
+
+/// `A` for the top task, down to `Z`. Lists longer than the alphabet share
+/// letters between neighbouring ranks.
+fn priority(rank: usize, len: usize) -> char {
+    (b'A' + (rank * 26 / len.max(26)) as u8) as char
+}
+
+fn markdown_item(line: &str) -> Option<String> {
+    let line = line.trim_start();
+    let item = ["- ", "* ", "+ "]
+        .iter()
+        .find_map(|bullet| line.strip_prefix(bullet))
+        .or_else(|| {
+            // Numbered lists, `1. text`.
+            let (number, rest) = line.split_once(". ")?;
+            number.chars().all(|c| c.is_ascii_digit()).then_some(rest)
+        })?;
+    let item = ["[ ] ", "[x] ", "[X] "]
+        .iter()
+        .find_map(|check| item.strip_prefix(check))
+        .unwrap_or(item);
+    Some(item.to_string())
+}
+
+/// Strips the completion mark, priority and dates from a todo.txt line.
+fn todo_txt_text(line: &str) -> String {
+    let mut rest = line.trim_start();
+    rest = rest.strip_prefix("x ").unwrap_or(rest);
+    let bytes = rest.as_bytes();
+    if bytes.len() >= 4
+        && bytes[0] == b'('
+        && bytes[1].is_ascii_uppercase()
+        && &bytes[2..4] == b") "
+    {
+        rest = &rest[4..];
+    }
+    // Completion and creation dates.
+    for _ in 0..2 {
+        let is_date = rest.len() >= 11
+            && rest.as_bytes()[10] == b' '
+            && rest[..10].char_indices().all(|(i, c)| {
+                if i == 4 || i == 7 {
+                    c == '-'
+                } else {
+                    c.is_ascii_digit()
+                }
+            });
+        if is_date {
+            rest = &rest[11..];
+        }
+    }
+    rest.to_string()
+}
+
+fn write_csv_row<'a>(out: &mut String, fields: impl IntoIterator<Item = &'a str>) {
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|f| {
+            if f.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", f.replace('"', "\"\""))
+            } else {
+                f.to_string()
+            }
+        })
+        .collect();
+    out.push_str(&fields.join(","));
+    out.push_str("\r\n");
+}
+
+/// Parses RFC 4180 CSV, skipping blank lines.
+fn read_csv(input: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|f| !f.is_empty()));
+    rows
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParallelSimpleAthensSpace, SimpleAthensSpace};
+    use std::sync::{Arc, Mutex};
+
+    fn space(texts: &[&str]) -> ParallelSimpleAthensSpace {
+        let space = Arc::new(Mutex::new(SimpleAthensSpace::new()));
+        for text in texts {
+            let mut task = space.create_task();
+            task.text = text.to_string();
+            space.set_task(task);
+        }
+        space
+    }
+
+    fn texts(space: &dyn AthensSpace, ids: &[TaskId]) -> Vec<String> {
+        ids.iter()
+            .map(|&id| space.get_task(id).unwrap().text)
+            .collect()
+    }
+
+    #[test]
+    fn exports_each_format() {
+        let s = space(&["Fix the build", "Say \"hi\", then leave"]);
+        let order = OrderedTasks::from_vec([TaskId(1), TaskId(0)]);
+        let easiness = Column {
+            name: "easiness".to_string(),
+            values: [(TaskId(0), "1".to_string())].into_iter().collect(),
+        };
+        assert_eq!(
+            export(&s, &order, Format::Csv, &[easiness]),
+            "rank,id,text,easiness\r\n\
+             1,1,\"Say \"\"hi\"\", then leave\",\r\n\
+             2,0,Fix the build,1\r\n"
+        );
+        assert_eq!(
+            export(&s, &order, Format::Markdown, &[]),
+            "- [ ] Say \"hi\", then leave\n- [ ] Fix the build\n"
+        );
+        assert_eq!(
+            export(&s, &order, Format::TodoTxt, &[]),
+            "(A) Say \"hi\", then leave\n(B) Fix the build\n"
+        );
+    }
+
+    #[test]
+    fn exports_round_trip() {
+        let texts_in = ["a, b", "d", "quote \" c"];
+        for format in [Format::Csv, Format::Markdown, Format::TodoTxt] {
+            let from = space(&texts_in);
+            let exported = export(&from, &from.important_tasks(), format, &[]);
+            let to = space(&[]);
+            let imported = import(&to, &exported, format);
+            let mut got = texts(&to, &imported.created);
+            got.sort();
+            assert_eq!(got, texts_in, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn import_skips_duplicates() {
+        let s = space(&["existing"]);
+        let imported = import(
+            &s,
+            "- [ ] existing\n- [x] new\nnot an item\n* new\n1. numbered\n",
+            Format::Markdown,
+        );
+        assert_eq!(texts(&s, &imported.created), vec!["new", "numbered"]);
+        assert_eq!(imported.duplicates, vec!["existing", "new"]);
+    }
+
+    #[test]
+    fn imports_todo_txt_and_headerless_csv() {
+        assert_eq!(
+            parse(
+                "x 2026-01-02 2026-01-01 done +project\n(B) 2026-01-01 call mom\nplain\n\n",
+                Format::TodoTxt
+            ),
+            vec!["done +project", "call mom", "plain"]
+        );
+        assert_eq!(
+            parse("one\r\n\"two, three\"\r\n", Format::Csv),
+            vec!["one", "two, three"]
+        );
+        assert_eq!(parse("id,Text\n4,four\n", Format::Csv), vec!["four"]);
+    }
+
+    #[test]
+    fn priorities_follow_rank() {
+        assert_eq!(priority(0, 3), 'A');
+        assert_eq!(priority(2, 3), 'C');
+        assert_eq!(priority(51, 52), 'Z');
+        assert_eq!(priority(1, 52), 'A');
+    }
+
+    #[test]
+    fn ical_feed() {
+        let s = space(&["Plan Q3; maybe, later", &"long ".repeat(20)]);
+        let feed = export_ical(&s, Timestamp(1_788_220_800 + 3661));
+        assert!(feed.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(feed.ends_with("END:VCALENDAR\r\n"));
+        assert!(feed.contains("UID:space-0-task-1@athens\r\n"));
+        assert!(feed.contains("DTSTAMP:20260901T010101Z\r\n"));
+        assert!(feed.contains("SUMMARY:Plan Q3\\; maybe\\, later\r\n"));
+        assert!(feed.lines().all(|l| l.len() <= 75));
+        let unfolded = feed.replace("\r\n ", "");
+        assert!(unfolded.contains(&format!("SUMMARY:{}\r\n", "long ".repeat(20))));
+        let priorities: Vec<&str> = feed.lines().filter(|l| l.starts_with("PRIORITY")).collect();
+        assert_eq!(priorities, vec!["PRIORITY:1", "PRIORITY:2"]);
+    }
+}
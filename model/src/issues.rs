@@ -0,0 +1,241 @@
+//! Importing issues from an issue tracker's JSON export.
+//!
+//! Both GitHub REST issue objects and a generic `{title, body, labels}`
+//! shape are read, either as a list or as `{"issues": [...]}`. Issues with
+//! a number keep it and their URL as the task's `ExternalRef`, so importing
+//! a newer export updates their tasks instead of adding new ones. Task texts
+//! are a single line, so bodies stay in the tracker.
+
+use super::interchange::Imported;
+use super::{AthensSpace, ExternalRef, Task};
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Export {
+    List(Vec<Issue>),
+    Wrapped { issues: Vec<Issue> },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Issue {
+    pub title: String,
+    #[serde(default)]
+    pub labels: Vec<Label>,
+    #[serde(default)]
+    pub number: Option<u64>,
+    /// The API URL on GitHub, where `html_url` is the one to link to.
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub html_url: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Only set on GitHub pull requests.
+    #[serde(default)]
+    pub pull_request: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Label {
+    Name(String),
+    Object { name: String },
+}
+
+impl Issue {
+    fn is_open_issue(&self) -> bool {
+        self.pull_request.is_none() && self.state.as_deref() != Some("closed")
+    }
+
+    /// The title, followed by the labels as hashtags.
+    fn text(&self) -> String {
+        let mut text = self.title.trim().to_string();
+        for label in self.labels.iter() {
+            let (Label::Name(name) | Label::Object { name }) = label;
+            text.push_str(" #");
+            text.push_str(&name.trim().replace(char::is_whitespace, "-"));
+        }
+        text
+    }
+
+    fn external(&self) -> Option<ExternalRef> {
+        let url = self.html_url.as_ref().or(self.url.as_ref());
+        self.number.map(|number| ExternalRef {
+            number,
+            url: url.cloned().unwrap_or_default(),
+        })
+    }
+}
+
+/// Reads the issues in an export.
+pub fn parse(input: &str) -> serde_json::Result<Vec<Issue>> {
+    match serde_json::from_str(input)? {
+        Export::List(issues) | Export::Wrapped { issues } => Ok(issues),
+    }
+}
+
+/// Creates a task for each open issue, or updates the task already
+/// tracking it. A task with the same text and no reference yet is taken to
+/// be the issue's. Closed issues and pull requests are skipped.
+pub fn import(space: &dyn AthensSpace, issues: &[Issue]) -> Imported {
+    let mut tasks: Vec<Task> = space
+        .tasks()
+        .into_iter()
+        .filter_map(|id| space.get_task(id))
+        .collect();
+    let mut imported = Imported::default();
+    for issue in issues.iter().filter(|i| i.is_open_issue()) {
+        let text = issue.text();
+        let external = issue.external();
+        let existing = tasks.iter_mut().find(|t| match (&t.external, &external) {
+            (Some(a), Some(b)) => a.same_issue(b),
+            (None, _) => t.text.trim() == text,
+            (Some(_), None) => false,
+        });
+        match existing {
+            Some(task) => {
+                // A URL is kept when a later export has none.
+                let external = match (task.external.clone(), external) {
+                    (Some(known), Some(new)) if new.url.is_empty() => Some(known),
+                    (known, new) => new.or(known),
+                };
+                if task.text == text && task.external == external {
+                    imported.duplicates.push(text);
+                    continue;
+                }
+                task.text = text;
+                task.external = external;
+                imported.updated.push(task.id);
+                space.set_task(task.clone());
+            }
+            None => {
+                let mut task = space.create_task();
+                task.text = text;
+                task.external = external;
+                imported.created.push(task.id);
+                space.set_task(task.clone());
+                tasks.push(task);
+            }
+        }
+    }
+    imported
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParallelSimpleAthensSpace, SimpleAthensSpace, TaskId};
+    use std::sync::{Arc, Mutex};
+
+    const GITHUB: &str = r#"[
+        {
+            "url": "https://api.github.com/repos/o/r/issues/1",
+            "html_url": "https://github.com/o/r/issues/1",
+            "number": 1,
+            "title": "Crash on empty space",
+            "body": "Steps to reproduce...",
+            "state": "open",
+            "labels": [{"id": 5, "name": "bug"}, {"id": 6, "name": "good first issue"}]
+        },
+        {
+            "html_url": "https://github.com/o/r/pull/2",
+            "number": 2,
+            "title": "Fix crash",
+            "state": "open",
+            "labels": [],
+            "pull_request": {"url": "https://api.github.com/repos/o/r/pulls/2"}
+        },
+        {
+            "html_url": "https://github.com/o/r/issues/3",
+            "number": 3,
+            "title": "Old idea",
+            "state": "closed",
+            "labels": []
+        }
+    ]"#;
+
+    fn space() -> ParallelSimpleAthensSpace {
+        Arc::new(Mutex::new(SimpleAthensSpace::new()))
+    }
+
+    fn import_issues(space: &dyn AthensSpace, input: &str) -> Imported {
+        import(space, &parse(input).unwrap())
+    }
+
+    #[test]
+    fn imports_open_github_issues() {
+        let space = space();
+        let imported = import_issues(&space, GITHUB);
+        assert_eq!(imported.created, vec![TaskId(0)]);
+        let task = space.get_task(TaskId(0)).unwrap();
+        assert_eq!(task.text, "Crash on empty space #bug #good-first-issue");
+        assert_eq!(
+            task.external,
+            Some(ExternalRef {
+                number: 1,
+                url: "https://github.com/o/r/issues/1".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn reimporting_updates_tasks() {
+        let space = space();
+        import_issues(&space, GITHUB);
+        let again = import_issues(&space, GITHUB);
+        assert_eq!(again.created, vec![]);
+        assert_eq!(again.duplicates.len(), 1);
+
+        let renamed = GITHUB.replace("Crash on empty space", "Crash when empty");
+        let renamed = import_issues(&space, &renamed);
+        assert_eq!(renamed.updated, vec![TaskId(0)]);
+        assert_eq!(space.tasks().len(), 1);
+        assert!(space
+            .get_task(TaskId(0))
+            .unwrap()
+            .text
+            .starts_with("Crash when empty"));
+    }
+
+    #[test]
+    fn reimporting_matches_on_the_number() {
+        let space = space();
+        import_issues(&space, GITHUB);
+        let without_url = r#"[{"number": 1, "title": "Crash on empty space", "labels": ["bug"]}]"#;
+        let again = import_issues(&space, without_url);
+        assert_eq!((again.created, again.updated), (vec![], vec![TaskId(0)]));
+        let task = space.get_task(TaskId(0)).unwrap();
+        assert_eq!(
+            task.external.unwrap().url,
+            "https://github.com/o/r/issues/1"
+        );
+
+        // Another tracker's issue 1 is another issue.
+        let elsewhere = r#"[{"number": 1, "html_url": "https://x/1", "title": "Other"}]"#;
+        assert_eq!(import_issues(&space, elsewhere).created, vec![TaskId(1)]);
+    }
+
+    #[test]
+    fn imports_generic_issues() {
+        let space = space();
+        let mut task = space.create_task();
+        task.text = "Write docs".to_string();
+        space.set_task(task);
+        let input = r#"{"issues": [
+            {"title": "Write docs", "number": 12},
+            {"title": "Plan release", "body": "Q3", "labels": ["ops"]}
+        ]}"#;
+        let imported = import_issues(&space, input);
+        // The existing task now tracks issue 12.
+        assert_eq!(imported.updated, vec![TaskId(0)]);
+        assert_eq!(imported.created, vec![TaskId(1)]);
+        let docs = space.get_task(TaskId(0)).unwrap();
+        assert_eq!(docs.external.map(|e| e.number), Some(12));
+        let release = space.get_task(TaskId(1)).unwrap();
+        assert_eq!(release.text, "Plan release #ops");
+        assert_eq!(release.external, None);
+
+        assert!(parse("{\"title\": 1}").is_err());
+    }
+}
@@ -9,6 +9,31 @@ use ranked_pairs::ranked_pairs_ordering;
 mod simple_athens_space;
 pub use simple_athens_space::*;
 
+#[cfg(feature = "sqlite")]
+mod sqlite_athens_space;
+#[cfg(feature = "sqlite")]
+pub use sqlite_athens_space::*;
+
+mod history;
+pub use history::*;
+
+mod merge;
+pub use merge::*;
+
+mod diff;
+pub use diff::*;
+
+mod stored;
+pub use stored::*;
+
+mod validate;
+pub use validate::*;
+
+pub mod api;
+pub mod differential;
+pub mod interchange;
+pub mod issues;
+
 /// Permenant unique identifier for a user.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct UserId(pub usize);
@@ -19,7 +44,7 @@ pub struct TaskId(pub usize);
 
 /// Globally unique identifier for a space of tasks and users.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct SpaceId(usize);
+pub struct SpaceId(pub usize);
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct User {
@@ -32,6 +57,28 @@ pub struct User {
 pub struct Task {
     pub id: TaskId,
     pub text: String,
+    /// Where the task was imported from, if it tracks an issue elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external: Option<ExternalRef>,
+}
+
+/// An issue in another tracker. Re-importing the same issue updates its
+/// task, see `same_issue`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExternalRef {
+    pub number: u64,
+    /// Empty when the export had no URL.
+    #[serde(default)]
+    pub url: String,
+}
+
+impl ExternalRef {
+    /// Whether both refer to the same issue: the numbers match, and so do
+    /// the URLs if both have one.
+    pub fn same_issue(&self, other: &ExternalRef) -> bool {
+        self.number == other.number
+            && (self.url.is_empty() || other.url.is_empty() || self.url == other.url)
+    }
 }
 
 /// Iterate over TaskId, contains all tasks in the space.
@@ -103,6 +150,16 @@ impl OrderedTasks {
         self.0.iter().copied()
     }
 }
+impl PartialEq<Vec<TaskId>> for OrderedTasks {
+    fn eq(&self, other: &Vec<TaskId>) -> bool {
+        &self.0 == other
+    }
+}
+impl<const N: usize> PartialEq<[TaskId; N]> for OrderedTasks {
+    fn eq(&self, other: &[TaskId; N]) -> bool {
+        self.0 == other
+    }
+}
 impl IntoIterator for OrderedTasks {
     type Item = TaskId;
     type IntoIter = std::vec::IntoIter<TaskId>;
@@ -153,6 +210,8 @@ mod tests {
     fn test_empty_simple_athens_space() {
         let s = Arc::new(Mutex::new(SimpleAthensSpace::new()));
         assert_eq!(s.important_tasks(), vec![]);
+        assert_eq!(s.get_task(TaskId(0)), None);
+        assert_eq!(s.get_user(UserId(0)), None);
     }
     #[test]
     fn test_simple_athens_space_no_users() {
@@ -160,4 +219,23 @@ mod tests {
         let t0 = s.create_task().id;
         assert_eq!(s.important_tasks(), vec![t0]);
     }
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_athens_space() {
+        let s = SqliteAthensSpace::open_in_memory().unwrap();
+        simple_athens_space(&s);
+    }
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_empty_sqlite_athens_space() {
+        let s = SqliteAthensSpace::open_in_memory().unwrap();
+        assert_eq!(s.important_tasks(), vec![]);
+    }
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_athens_space_no_users() {
+        let s = SqliteAthensSpace::open_in_memory().unwrap();
+        let t0 = s.create_task().id;
+        assert_eq!(s.important_tasks(), vec![t0]);
+    }
 }
@@ -0,0 +1,365 @@
+use super::{OrderedTasks, SimpleAthensSpace, Task, TaskId, User, UserId, UserWithOrds};
+use serde::{Deserialize, Serialize};
+
+/// Something both sides changed differently. The merged space keeps our side.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Conflict {
+    SpaceAlias {
+        ours: String,
+        theirs: String,
+    },
+    TaskText {
+        task: TaskId,
+        ours: String,
+        theirs: String,
+    },
+    UserAlias {
+        user: UserId,
+        ours: String,
+        theirs: String,
+    },
+    UserWeight {
+        user: UserId,
+        ours: u32,
+        theirs: u32,
+    },
+    Importance {
+        user: UserId,
+    },
+    Easiness {
+        user: UserId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// One side has fewer tasks or users than the ancestor, so it cannot
+    /// descend from it.
+    NotDescendant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    pub space: SimpleAthensSpace,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl SimpleAthensSpace {
+    /// Three way merge of two copies of a space that were both edited from
+    /// `base`. Tasks and users created on both sides are kept, theirs are
+    /// renumbered after ours. Each user's ballots are merged independently of
+    /// everyone else's, so they only conflict if both sides moved that user's
+    /// ballot. The ballot history and version are taken from our side.
+    pub fn merge(base: &Self, ours: &Self, theirs: &Self) -> Result<Merged, MergeError> {
+        for side in [ours, theirs] {
+            if side.tasks.len() < base.tasks.len() || side.users.len() < base.users.len() {
+                return Err(MergeError::NotDescendant);
+            }
+        }
+        let ids = Renumbering {
+            base_tasks: base.tasks.len(),
+            our_tasks: ours.tasks.len(),
+            their_tasks: theirs.tasks.len(),
+            base_users: base.users.len(),
+            our_users: ours.users.len(),
+        };
+        let mut conflicts = Vec::new();
+        let alias = merge_value(&base.alias, &ours.alias, &theirs.alias, |o, t| {
+            conflicts.push(Conflict::SpaceAlias {
+                ours: o.clone(),
+                theirs: t.clone(),
+            })
+        })
+        .clone();
+
+        // Tasks.
+        let mut tasks = Vec::new();
+        for (i, b) in base.tasks.iter().enumerate() {
+            let (o, t) = (&ours.tasks[i], &theirs.tasks[i]);
+            let text = merge_value(&b.text, &o.text, &t.text, |o, t| {
+                conflicts.push(Conflict::TaskText {
+                    task: b.id,
+                    ours: o.clone(),
+                    theirs: t.clone(),
+                })
+            });
+            // References only change on import, no need to report them.
+            let external = merge_value(&b.external, &o.external, &t.external, |_, _| {});
+            tasks.push(Task {
+                id: b.id,
+                text: text.clone(),
+                external: external.clone(),
+            });
+        }
+        tasks.extend(ours.tasks[ids.base_tasks..].iter().cloned());
+        tasks.extend(theirs.tasks[ids.base_tasks..].iter().map(|t| Task {
+            id: ids.their_task(t.id),
+            ..t.clone()
+        }));
+
+        // Users and their ballots.
+        let mut users = Vec::new();
+        for (i, b) in base.users.iter().enumerate() {
+            let (o, t) = (&ours.users[i], &theirs.users[i]);
+            let id = b.user.id;
+            let alias = merge_value(&b.user.alias, &o.user.alias, &t.user.alias, |o, t| {
+                conflicts.push(Conflict::UserAlias {
+                    user: id,
+                    ours: o.clone(),
+                    theirs: t.clone(),
+                })
+            });
+            let weight = merge_value(&b.user.weight, &o.user.weight, &t.user.weight, |o, t| {
+                conflicts.push(Conflict::UserWeight {
+                    user: id,
+                    ours: *o,
+                    theirs: *t,
+                })
+            });
+            let user = User {
+                id,
+                alias: alias.clone(),
+                weight: *weight,
+            };
+            let importance = ids
+                .merge_ballot(&b.importance, &o.importance, &t.importance)
+                .unwrap_or_else(|ord| {
+                    conflicts.push(Conflict::Importance { user: id });
+                    ord
+                });
+            let easiness = ids
+                .merge_ballot(&b.easiness, &o.easiness, &t.easiness)
+                .unwrap_or_else(|ord| {
+                    conflicts.push(Conflict::Easiness { user: id });
+                    ord
+                });
+            users.push(UserWithOrds {
+                user,
+                importance,
+                easiness,
+            });
+        }
+        for o in ours.users[ids.base_users..].iter() {
+            let mut u = o.clone();
+            ids.add_their_tasks(&mut u.importance);
+            ids.add_their_tasks(&mut u.easiness);
+            users.push(u);
+        }
+        for t in theirs.users[ids.base_users..].iter() {
+            let mut importance = ids.map_theirs(&t.importance);
+            let mut easiness = ids.map_theirs(&t.easiness);
+            ids.add_our_tasks(&mut importance);
+            ids.add_our_tasks(&mut easiness);
+            users.push(UserWithOrds {
+                user: User {
+                    id: ids.their_user(t.user.id),
+                    ..t.user.clone()
+                },
+                importance,
+                easiness,
+            });
+        }
+
+        let space = SimpleAthensSpace {
+            id: ours.id,
+            alias,
+            tasks,
+            users,
+            history: ours.history.clone(),
+            version: ours.version,
+        };
+        Ok(Merged { space, conflicts })
+    }
+}
+
+/// Takes whichever side changed. If both did, calls `conflict` unless they
+/// agree, and keeps ours.
+fn merge_value<'a, T: PartialEq>(
+    base: &'a T,
+    ours: &'a T,
+    theirs: &'a T,
+    conflict: impl FnOnce(&T, &T),
+) -> &'a T {
+    if ours == base {
+        theirs
+    } else if theirs == base || ours == theirs {
+        ours
+    } else {
+        conflict(ours, theirs);
+        ours
+    }
+}
+
+/// Where tasks and users created on either side end up in the merged space.
+struct Renumbering {
+    base_tasks: usize,
+    our_tasks: usize,
+    their_tasks: usize,
+    base_users: usize,
+    our_users: usize,
+}
+
+impl Renumbering {
+    fn their_task(&self, id: TaskId) -> TaskId {
+        if id.0 < self.base_tasks {
+            id
+        } else {
+            TaskId(id.0 - self.base_tasks + self.our_tasks)
+        }
+    }
+    fn their_user(&self, id: UserId) -> UserId {
+        UserId(id.0 - self.base_users + self.our_users)
+    }
+    fn map_theirs(&self, ord: &OrderedTasks) -> OrderedTasks {
+        OrderedTasks(ord.iter().map(|id| self.their_task(id)).collect())
+    }
+    fn base_only(&self, ord: &OrderedTasks) -> Vec<TaskId> {
+        ord.iter().filter(|id| id.0 < self.base_tasks).collect()
+    }
+    /// New tasks go to the front like `SimpleAthensSpace::new_task` does.
+    fn add_our_tasks(&self, ord: &mut OrderedTasks) {
+        for id in self.base_tasks..self.our_tasks {
+            ord.push_front(TaskId(id));
+        }
+    }
+    fn add_their_tasks(&self, ord: &mut OrderedTasks) {
+        for id in self.base_tasks..self.their_tasks {
+            ord.push_front(self.their_task(TaskId(id)));
+        }
+    }
+    /// Merges one user's ballot, comparing sides only by how they ordered
+    /// the tasks that existed in `base`. Returns Err with our ballot if both
+    /// sides reordered those tasks differently.
+    fn merge_ballot(
+        &self,
+        base: &OrderedTasks,
+        ours: &OrderedTasks,
+        theirs: &OrderedTasks,
+    ) -> Result<OrderedTasks, OrderedTasks> {
+        let theirs = self.map_theirs(theirs);
+        let (b, o, t) = (
+            self.base_only(base),
+            self.base_only(ours),
+            self.base_only(&theirs),
+        );
+        // Start from the side that moved things and slot the other side's
+        // new tasks into the positions that side gave them.
+        let (mut merged, other, conflict) = if o == b {
+            (theirs, ours.clone(), false)
+        } else {
+            (ours.clone(), theirs, t != b && t != o)
+        };
+        for (pos, id) in other.iter().enumerate() {
+            if !merged.0.contains(&id) {
+                merged.0.insert(pos.min(merged.0.len()), id);
+            }
+        }
+        if conflict {
+            Err(merged)
+        } else {
+            Ok(merged)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    fn base() -> SimpleAthensSpace {
+        let mut s = SimpleAthensSpace::new();
+        for text in ["a", "b", "c"] {
+            s.new_task().text = text.to_string();
+        }
+        s.new_user();
+        s.new_user();
+        s
+    }
+    fn texts(s: &SimpleAthensSpace) -> Vec<&str> {
+        s.tasks.iter().map(|t| t.text.as_str()).collect()
+    }
+    #[test]
+    fn merge_keeps_tasks_created_on_both_sides() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.new_task().text = "ours".to_string();
+        let mut theirs = base.clone();
+        theirs.new_task().text = "theirs".to_string();
+        theirs.new_task().text = "theirs too".to_string();
+
+        let merged = SimpleAthensSpace::merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(merged.conflicts, vec![]);
+        let space = merged.space;
+        assert_eq!(
+            texts(&space),
+            ["a", "b", "c", "ours", "theirs", "theirs too"]
+        );
+        assert_eq!(space.tasks[5].id, TaskId(5));
+        for u in space.users.iter() {
+            assert_eq!(u.importance.0.len(), 6);
+            assert_eq!(u.easiness.0.len(), 6);
+        }
+    }
+    #[test]
+    fn merge_combines_edits_to_different_tasks() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.mut_task(TaskId(0)).unwrap().text = "A".to_string();
+        let mut theirs = base.clone();
+        theirs.mut_task(TaskId(2)).unwrap().text = "C".to_string();
+
+        let merged = SimpleAthensSpace::merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(merged.conflicts, vec![]);
+        assert_eq!(texts(&merged.space), ["A", "b", "C"]);
+    }
+    #[test]
+    fn merge_reports_conflicting_edits() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.mut_task(TaskId(1)).unwrap().text = "mine".to_string();
+        ours.mut_user(UserId(0)).unwrap().move_importance(0, 2);
+        let mut theirs = base.clone();
+        theirs.mut_task(TaskId(1)).unwrap().text = "yours".to_string();
+        theirs.mut_user(UserId(0)).unwrap().move_importance(1, 0);
+
+        let merged = SimpleAthensSpace::merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(
+            merged.conflicts,
+            vec![
+                Conflict::TaskText {
+                    task: TaskId(1),
+                    ours: "mine".to_string(),
+                    theirs: "yours".to_string(),
+                },
+                Conflict::Importance { user: UserId(0) },
+            ]
+        );
+        assert_eq!(merged.space, ours);
+    }
+    #[test]
+    fn merge_takes_each_users_ballot_from_the_side_that_moved_it() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.mut_user(UserId(0)).unwrap().move_easiness(0, 2);
+        ours.new_task();
+        let mut theirs = base.clone();
+        theirs.mut_user(UserId(1)).unwrap().move_importance(2, 0);
+        theirs.new_user();
+
+        let merged = SimpleAthensSpace::merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(merged.conflicts, vec![]);
+        let space = merged.space;
+        let ids = |ids: &[usize]| ids.iter().map(|&i| TaskId(i)).collect::<Vec<_>>();
+        assert_eq!(space.user(UserId(0)).easiness, ids(&[3, 1, 2, 0]));
+        assert_eq!(space.user(UserId(1)).importance, ids(&[3, 2, 0, 1]));
+        assert_eq!(space.user(UserId(2)).importance, ids(&[3, 0, 1, 2]));
+    }
+    #[test]
+    fn merge_rejects_unrelated_spaces() {
+        let base = base();
+        let empty = SimpleAthensSpace::new();
+        assert_eq!(
+            SimpleAthensSpace::merge(&base, &empty, &base),
+            Err(MergeError::NotDescendant)
+        );
+    }
+}
@@ -28,8 +28,12 @@ pub fn ranked_pairs_ordering(orderings: &[OrderedTasks]) -> OrderedTasks {
             let (i, j) = if i < j { (i, j) } else { (j, i) };
             for u in user_to_taskid_to_order.iter() {
                 let rate = win_margins.entry((i, j)).or_insert(0);
-                let i_rank = u.get(&i).unwrap();
-                let j_rank = u.get(&j).unwrap();
+                let (i_rank, j_rank) = match (u.get(&i), u.get(&j)) {
+                    (Some(i_rank), Some(j_rank)) => (i_rank, j_rank),
+                    // Histories from before removals were recorded can have
+                    // ballots of departed users, which miss newer tasks.
+                    _ => continue,
+                };
                 if i_rank < j_rank {
                     *rate += 1;
                 } else {
@@ -53,6 +57,9 @@ pub fn ranked_pairs_ordering(orderings: &[OrderedTasks]) -> OrderedTasks {
     // 4. Commit the wins into a directed acyclic graph in order of margin.
     // If an edge creates a cycle, it will not be inserted.
     let mut dag = TaskIdDag::default();
+    for &id in orderings[0].0.iter() {
+        dag.0.entry(id).or_default(); // Lone tasks have no edges.
+    }
     for (_, edge) in win_margins {
         dag.try_insert(edge);
     }
@@ -106,8 +113,8 @@ impl TaskIdDag {
             // Consider the nodes not yet in the topological sort.
             let mut unsorted_nodes = self
                 .0
-                .iter()
-                .flat_map(|(node, _)| {
+                .keys()
+                .flat_map(|node| {
                     if sort.contains(node) {
                         None
                     } else {
@@ -125,7 +132,7 @@ impl TaskIdDag {
                 }
             }
             let prev_len = sort.len();
-            sort.extend(unsorted_nodes.into_iter());
+            sort.extend(unsorted_nodes);
             assert!(prev_len < sort.len());
         }
         sort
@@ -193,4 +200,10 @@ mod test {
             ]
         );
     }
+    #[test]
+    fn ballots_missing_tasks_do_not_panic() {
+        let ord = |ids: &[usize]| OrderedTasks(ids.iter().map(|&i| TaskId(i)).collect());
+        let orderings = [ord(&[1, 0]), ord(&[0])];
+        assert_eq!(ranked_pairs_ordering(&orderings), ord(&[1, 0]));
+    }
 }
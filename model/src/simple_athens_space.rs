@@ -1,5 +1,6 @@
 use super::{
-    ranked_pairs_ordering, AthensSpace, OrderedTasks, SpaceId, Task, TaskId, User, UserId,
+    ranked_pairs_ordering, AthensSpace, BallotHistory, OrderedTasks, SpaceId, Task, TaskId,
+    Timestamp, User, UserId,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -37,11 +38,10 @@ impl AthensSpace for ParallelSimpleAthensSpace {
         })
     }
     fn get_user(&self, id: UserId) -> Option<User> {
-        // TODO: This will panic probably on unknown id
-        Some(self.lock().unwrap().user(id).user.clone())
+        self.lock().unwrap().users.get(id.0).map(|u| u.user.clone())
     }
     fn get_task(&self, id: TaskId) -> Option<Task> {
-        Some(self.lock().unwrap().task(id).clone())
+        self.lock().unwrap().tasks.get(id.0).cloned()
     }
 
     fn set_task(&self, task: Task) -> Option<Task> {
@@ -97,7 +97,10 @@ impl AthensSpace for ParallelSimpleAthensSpace {
     }
 }
 
-fn combine_important_and_easy(importance: &OrderedTasks, easiness: &OrderedTasks) -> OrderedTasks {
+pub(crate) fn combine_important_and_easy(
+    importance: &OrderedTasks,
+    easiness: &OrderedTasks,
+) -> OrderedTasks {
     let mut ords = BTreeMap::<TaskId, (usize, usize)>::new();
     for (ord, id) in importance.iter().enumerate() {
         assert!(ords.insert(id, (ord, 0)).is_none());
@@ -116,10 +119,15 @@ fn combine_important_and_easy(importance: &OrderedTasks, easiness: &OrderedTasks
 // One implementation of an AthensSpace
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct SimpleAthensSpace {
-    id: SpaceId,
-    alias: String,
-    tasks: Vec<Task>,
-    users: Vec<UserWithOrds>,
+    pub(crate) id: SpaceId,
+    pub(crate) alias: String,
+    pub(crate) tasks: Vec<Task>,
+    pub(crate) users: Vec<UserWithOrds>,
+    #[serde(default)]
+    pub(crate) history: BallotHistory,
+    /// Incremented by the server on every accepted save.
+    #[serde(default)]
+    pub(crate) version: u64,
 }
 impl Default for SimpleAthensSpace {
     fn default() -> Self {
@@ -129,12 +137,15 @@ impl Default for SimpleAthensSpace {
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct UserWithOrds {
-    user: User,
-    importance: OrderedTasks, // TODO: Setter
-    easiness: OrderedTasks,
+    pub(crate) user: User,
+    pub(crate) importance: OrderedTasks, // TODO: Setter
+    pub(crate) easiness: OrderedTasks,
 }
 
 impl UserWithOrds {
+    pub fn user(&self) -> &User {
+        &self.user
+    }
     pub fn move_importance(&mut self, from: usize, to: usize) -> &mut Self {
         self.importance.reorder(from, to);
         self
@@ -147,11 +158,16 @@ impl UserWithOrds {
 
 impl SimpleAthensSpace {
     pub fn new() -> Self {
+        Self::with_id(SpaceId(0))
+    }
+    pub fn with_id(id: SpaceId) -> Self {
         Self {
-            id: SpaceId(0), // TODO
+            id,
             alias: "My space".to_string(),
             tasks: vec![],
             users: vec![],
+            history: BallotHistory::default(),
+            version: 0,
         }
     }
     pub fn new_user(&mut self) -> &mut UserWithOrds {
@@ -171,6 +187,9 @@ impl SimpleAthensSpace {
     pub fn task_ids(&self) -> impl Iterator<Item = TaskId> + '_ {
         self.tasks.iter().map(|t| t.id)
     }
+    pub fn user_ids(&self) -> impl Iterator<Item = UserId> + '_ {
+        self.users.iter().map(|u| u.user.id)
+    }
     pub fn user(&self, id: UserId) -> &UserWithOrds {
         &self.users[id.0]
     }
@@ -182,6 +201,7 @@ impl SimpleAthensSpace {
         self.tasks.push(Task {
             id,
             text: String::new(),
+            external: None,
         });
         for user in self.users.iter_mut() {
             user.importance.push_front(id);
@@ -195,6 +215,64 @@ impl SimpleAthensSpace {
     pub fn task(&self, id: TaskId) -> &Task {
         &self.tasks[id.0]
     }
+    pub fn id(&self) -> SpaceId {
+        self.id
+    }
+    pub fn alias(&self) -> &str {
+        &self.alias
+    }
+    pub fn set_alias(&mut self, alias: String) {
+        self.alias = alias;
+    }
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+    pub fn set_version(&mut self, version: u64) {
+        self.version = version;
+    }
+    pub fn history(&self) -> &BallotHistory {
+        &self.history
+    }
+    pub fn set_history(&mut self, history: BallotHistory) {
+        self.history = history;
+    }
+    /// Appends the current ballots of every user whose ballots changed since
+    /// they were last recorded, and the removal of users no longer in the
+    /// space. Returns whether anything was recorded.
+    pub fn record_ballots(&mut self, at: Timestamp) -> bool {
+        let mut recorded = false;
+        for voter in self.history.voters() {
+            if self.users.iter().all(|u| u.user.id != voter) {
+                recorded |= self.history.record_removal(at, voter);
+            }
+        }
+        for u in self.users.iter() {
+            recorded |= self
+                .history
+                .record(at, u.user.id, &u.importance, &u.easiness);
+        }
+        recorded
+    }
+    /// The users of this space whose ballots rank its tasks differently in
+    /// `newer`, or who are missing from it. Tasks new in `newer` are ignored.
+    pub fn changed_voters(&self, newer: &Self) -> Vec<UserId> {
+        let known = |ballot: &OrderedTasks| -> Vec<TaskId> {
+            let tasks = self.tasks.len();
+            ballot.iter().filter(|t| t.0 < tasks).collect()
+        };
+        let changed = |u: &&UserWithOrds| match newer.users.get(u.user.id.0) {
+            Some(n) => {
+                known(&n.importance) != known(&u.importance)
+                    || known(&n.easiness) != known(&u.easiness)
+            }
+            None => true,
+        };
+        self.users
+            .iter()
+            .filter(changed)
+            .map(|u| u.user.id)
+            .collect()
+    }
     pub fn easiness(&self) -> OrderedTasks {
         if self.users.is_empty() {
             return OrderedTasks(self.task_ids().collect());
@@ -218,3 +296,58 @@ impl SimpleAthensSpace {
         ranked_pairs_ordering(&ords)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn record_ballots_only_records_changes() {
+        let mut space = SimpleAthensSpace::new();
+        let t0 = space.new_task().id;
+        let t1 = space.new_task().id;
+        space.new_user();
+        space.new_user();
+        assert!(space.record_ballots(Timestamp(10)));
+        assert!(!space.record_ballots(Timestamp(20)));
+        space.mut_user(UserId(1)).unwrap().move_importance(0, 1);
+        assert!(space.record_ballots(Timestamp(30)));
+        assert_eq!(space.history().iter().count(), 3);
+        assert_eq!(space.history().importance_at(Timestamp(20)), [t0, t1]);
+    }
+
+    #[test]
+    fn removed_users_drop_out_of_history() {
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_user();
+        let one_user = space.clone();
+        space.new_user().move_importance(0, 0);
+        space.record_ballots(Timestamp(10));
+
+        // Like restoring a snapshot from before the second user.
+        let mut restored = one_user;
+        restored.set_history(space.history().clone());
+        assert!(restored.record_ballots(Timestamp(20)));
+        restored.new_task();
+        restored.record_ballots(Timestamp(30));
+        assert_eq!(restored.history().voters(), vec![UserId(0)]);
+        let (t0, t1) = (TaskId(0), TaskId(1));
+        assert_eq!(restored.history().importance_at(Timestamp(30)), [t1, t0]);
+        assert_eq!(restored.history().ballots_at(Timestamp(10)).len(), 2);
+    }
+
+    #[test]
+    fn changed_voters_ignores_new_tasks() {
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_task();
+        space.new_user();
+        space.new_user();
+        let mut newer = space.clone();
+        newer.new_task();
+        newer.mut_user(UserId(0)).unwrap().move_importance(0, 1);
+        assert_eq!(space.changed_voters(&newer), vec![]);
+        newer.mut_user(UserId(1)).unwrap().move_easiness(1, 2);
+        assert_eq!(space.changed_voters(&newer), vec![UserId(1)]);
+    }
+}
@@ -0,0 +1,411 @@
+use super::{
+    combine_important_and_easy, ranked_pairs_ordering, AthensSpace, ExternalRef, OrderedTasks,
+    SpaceId, Task, TaskId, User, UserId,
+};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+use std::sync::Mutex;
+
+// Which of a user's orderings a row in `positions` belongs to.
+const IMPORTANCE: i64 = 0;
+const EASINESS: i64 = 1;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS space (
+        id INTEGER PRIMARY KEY,
+        alias TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS tasks (
+        id INTEGER PRIMARY KEY,
+        text TEXT NOT NULL,
+        external TEXT
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY,
+        alias TEXT NOT NULL,
+        weight INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS positions (
+        user INTEGER NOT NULL REFERENCES users(id),
+        ballot INTEGER NOT NULL,
+        task INTEGER NOT NULL REFERENCES tasks(id),
+        position INTEGER NOT NULL,
+        PRIMARY KEY (user, ballot, task)
+    );
+    CREATE INDEX IF NOT EXISTS positions_by_rank ON positions (user, ballot, position);
+";
+
+/// An AthensSpace stored in an SQLite database, one space per database.
+/// Every method runs in its own transaction.
+// Errors from SQLite are treated like the out of bounds panics in
+// SimpleAthensSpace, there is no way to report them through AthensSpace.
+pub struct SqliteAthensSpace {
+    id: SpaceId,
+    conn: Mutex<Connection>,
+}
+
+impl SqliteAthensSpace {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+    fn from_connection(mut conn: Connection) -> rusqlite::Result<Self> {
+        let tx = conn.transaction()?;
+        tx.execute_batch(SCHEMA)?;
+        // Databases from before tasks had external references.
+        if tx.prepare("SELECT external FROM tasks").is_err() {
+            tx.execute("ALTER TABLE tasks ADD COLUMN external TEXT", [])?;
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO space (id, alias) VALUES (0, 'My space')",
+            [],
+        )?;
+        let id: i64 = tx.query_row("SELECT id FROM space", [], |r| r.get(0))?;
+        tx.commit()?;
+        Ok(Self {
+            id: SpaceId(id as usize),
+            conn: Mutex::new(conn),
+        })
+    }
+    /// Runs `f` in a transaction that is committed if it returns.
+    fn transaction<T>(&self, f: impl FnOnce(&Transaction) -> rusqlite::Result<T>) -> T {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().expect("Failed to start transaction");
+        let result = f(&tx).expect("SQLite error");
+        tx.commit().expect("Failed to commit");
+        result
+    }
+}
+
+// External references are stored as JSON, NULL for none.
+fn to_json(external: &Option<ExternalRef>) -> Option<String> {
+    external.as_ref().map(|e| serde_json::to_string(e).unwrap())
+}
+
+fn from_json(column: usize, json: Option<String>) -> rusqlite::Result<Option<ExternalRef>> {
+    json.map(|j| serde_json::from_str(&j))
+        .transpose()
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, Type::Text, e.into()))
+}
+
+fn task_ids(tx: &Transaction) -> rusqlite::Result<Vec<TaskId>> {
+    let mut stmt = tx.prepare_cached("SELECT id FROM tasks ORDER BY id")?;
+    let ids = stmt.query_map([], |r| Ok(TaskId(r.get::<_, i64>(0)? as usize)))?;
+    ids.collect()
+}
+
+fn user_ids(tx: &Transaction) -> rusqlite::Result<Vec<UserId>> {
+    let mut stmt = tx.prepare_cached("SELECT id FROM users ORDER BY id")?;
+    let ids = stmt.query_map([], |r| Ok(UserId(r.get::<_, i64>(0)? as usize)))?;
+    ids.collect()
+}
+
+fn user_exists(tx: &Transaction, id: UserId) -> rusqlite::Result<bool> {
+    tx.query_row(
+        "SELECT 1 FROM users WHERE id = ?",
+        [id.0 as i64],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|r| r.is_some())
+}
+
+fn ordering(tx: &Transaction, user: UserId, ballot: i64) -> rusqlite::Result<OrderedTasks> {
+    let mut stmt = tx.prepare_cached(
+        "SELECT task FROM positions WHERE user = ? AND ballot = ? ORDER BY position",
+    )?;
+    let ids = stmt.query_map(params![user.0 as i64, ballot], |r| {
+        Ok(TaskId(r.get::<_, i64>(0)? as usize))
+    })?;
+    Ok(OrderedTasks(ids.collect::<rusqlite::Result<_>>()?))
+}
+
+fn set_ordering(
+    tx: &Transaction,
+    user: UserId,
+    ballot: i64,
+    ord: &OrderedTasks,
+) -> rusqlite::Result<()> {
+    tx.execute(
+        "DELETE FROM positions WHERE user = ? AND ballot = ?",
+        params![user.0 as i64, ballot],
+    )?;
+    let mut stmt = tx.prepare_cached(
+        "INSERT INTO positions (user, ballot, task, position) VALUES (?, ?, ?, ?)",
+    )?;
+    for (position, task) in ord.iter().enumerate() {
+        stmt.execute(params![
+            user.0 as i64,
+            ballot,
+            task.0 as i64,
+            position as i64
+        ])?;
+    }
+    Ok(())
+}
+
+/// Moves the task at `from` to `to`, shifting the tasks in between.
+fn reorder(
+    tx: &Transaction,
+    user: UserId,
+    ballot: i64,
+    from: usize,
+    to: usize,
+) -> rusqlite::Result<Option<OrderedTasks>> {
+    let (user_, from_, to_) = (user.0 as i64, from as i64, to as i64);
+    let task: Option<i64> = tx
+        .query_row(
+            "SELECT task FROM positions WHERE user = ? AND ballot = ? AND position = ?",
+            params![user_, ballot, from_],
+            |r| r.get(0),
+        )
+        .optional()?;
+    let len: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM positions WHERE user = ? AND ballot = ?",
+        params![user_, ballot],
+        |r| r.get(0),
+    )?;
+    let task = match task {
+        Some(task) if to_ < len => task,
+        _ => return Ok(None),
+    };
+    if from < to {
+        tx.execute(
+            "UPDATE positions SET position = position - 1
+             WHERE user = ? AND ballot = ? AND position > ? AND position <= ?",
+            params![user_, ballot, from_, to_],
+        )?;
+    } else {
+        tx.execute(
+            "UPDATE positions SET position = position + 1
+             WHERE user = ? AND ballot = ? AND position >= ? AND position < ?",
+            params![user_, ballot, to_, from_],
+        )?;
+    }
+    tx.execute(
+        "UPDATE positions SET position = ? WHERE user = ? AND ballot = ? AND task = ?",
+        params![to_, user_, ballot, task],
+    )?;
+    ordering(tx, user, ballot).map(Some)
+}
+
+/// Aggregates every user's ordering of one ballot with ranked pairs.
+fn aggregate(tx: &Transaction, ballot: i64) -> rusqlite::Result<OrderedTasks> {
+    let users = user_ids(tx)?;
+    if users.is_empty() {
+        return Ok(OrderedTasks(task_ids(tx)?));
+    }
+    let ords = users
+        .into_iter()
+        .map(|u| ordering(tx, u, ballot))
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(ranked_pairs_ordering(&ords))
+}
+
+impl AthensSpace for SqliteAthensSpace {
+    fn id(&self) -> SpaceId {
+        self.id
+    }
+    fn tasks(&self) -> Vec<TaskId> {
+        self.transaction(task_ids)
+    }
+    fn important_tasks(&self) -> OrderedTasks {
+        self.transaction(|tx| aggregate(tx, IMPORTANCE))
+    }
+    fn easy_tasks(&self) -> OrderedTasks {
+        self.transaction(|tx| aggregate(tx, EASINESS))
+    }
+    fn important_and_easy_tasks(&self) -> OrderedTasks {
+        self.transaction(|tx| {
+            let i = aggregate(tx, IMPORTANCE)?;
+            let e = aggregate(tx, EASINESS)?;
+            Ok(combine_important_and_easy(&i, &e))
+        })
+    }
+    fn users(&self) -> Vec<UserId> {
+        self.transaction(user_ids)
+    }
+    fn create_user(&self) -> User {
+        self.transaction(|tx| {
+            let id = UserId(user_ids(tx)?.len());
+            let user = User {
+                id,
+                alias: String::new(),
+                weight: 1,
+            };
+            tx.execute(
+                "INSERT INTO users (id, alias, weight) VALUES (?, ?, ?)",
+                params![id.0 as i64, user.alias, user.weight],
+            )?;
+            let default_order = OrderedTasks(task_ids(tx)?);
+            set_ordering(tx, id, IMPORTANCE, &default_order)?;
+            set_ordering(tx, id, EASINESS, &default_order)?;
+            Ok(user)
+        })
+    }
+    fn get_user(&self, id: UserId) -> Option<User> {
+        self.transaction(|tx| {
+            tx.query_row(
+                "SELECT alias, weight FROM users WHERE id = ?",
+                [id.0 as i64],
+                |r| {
+                    Ok(User {
+                        id,
+                        alias: r.get(0)?,
+                        weight: r.get(1)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+    fn set_user(&self, user: User) -> Option<User> {
+        self.transaction(|tx| {
+            let updated = tx.execute(
+                "UPDATE users SET alias = ?, weight = ? WHERE id = ?",
+                params![user.alias, user.weight, user.id.0 as i64],
+            )?;
+            Ok((updated > 0).then_some(user))
+        })
+    }
+    fn create_task(&self) -> Task {
+        self.transaction(|tx| {
+            let id = TaskId(task_ids(tx)?.len());
+            let task = Task {
+                id,
+                text: String::new(),
+                external: None,
+            };
+            tx.execute(
+                "INSERT INTO tasks (id, text) VALUES (?, ?)",
+                params![id.0 as i64, task.text],
+            )?;
+            // New tasks go to the front of every ordering.
+            tx.execute("UPDATE positions SET position = position + 1", [])?;
+            tx.execute(
+                "INSERT INTO positions (user, ballot, task, position)
+                 SELECT users.id, ballots.ballot, ?, 0
+                 FROM users, (SELECT ? AS ballot UNION SELECT ?) AS ballots",
+                params![id.0 as i64, IMPORTANCE, EASINESS],
+            )?;
+            Ok(task)
+        })
+    }
+    fn get_task(&self, id: TaskId) -> Option<Task> {
+        self.transaction(|tx| {
+            tx.query_row(
+                "SELECT text, external FROM tasks WHERE id = ?",
+                [id.0 as i64],
+                |r| {
+                    Ok(Task {
+                        id,
+                        text: r.get(0)?,
+                        external: from_json(1, r.get(1)?)?,
+                    })
+                },
+            )
+            .optional()
+        })
+    }
+    fn set_task(&self, task: Task) -> Option<Task> {
+        self.transaction(|tx| {
+            let updated = tx.execute(
+                "UPDATE tasks SET text = ?, external = ? WHERE id = ?",
+                params![task.text, to_json(&task.external), task.id.0 as i64],
+            )?;
+            Ok((updated > 0).then_some(task))
+        })
+    }
+    fn user_importance(&self, id: UserId) -> OrderedTasks {
+        self.transaction(|tx| ordering(tx, id, IMPORTANCE))
+    }
+    fn user_easiness(&self, id: UserId) -> OrderedTasks {
+        self.transaction(|tx| ordering(tx, id, EASINESS))
+    }
+    fn user_important_and_easy(&self, id: UserId) -> OrderedTasks {
+        self.transaction(|tx| {
+            let i = ordering(tx, id, IMPORTANCE)?;
+            let e = ordering(tx, id, EASINESS)?;
+            Ok(combine_important_and_easy(&i, &e))
+        })
+    }
+    fn set_user_importance(&self, id: UserId, ord: OrderedTasks) -> Option<OrderedTasks> {
+        // TODO: Verification of taskIds.
+        self.transaction(|tx| {
+            if !user_exists(tx, id)? {
+                return Ok(None);
+            }
+            set_ordering(tx, id, IMPORTANCE, &ord)?;
+            Ok(Some(ord))
+        })
+    }
+    fn set_user_easiness(&self, id: UserId, ord: OrderedTasks) -> Option<OrderedTasks> {
+        // TODO: Verification of taskIds.
+        self.transaction(|tx| {
+            if !user_exists(tx, id)? {
+                return Ok(None);
+            }
+            set_ordering(tx, id, EASINESS, &ord)?;
+            Ok(Some(ord))
+        })
+    }
+    fn swap_user_importance(&self, id: UserId, from: usize, to: usize) -> Option<OrderedTasks> {
+        self.transaction(|tx| reorder(tx, id, IMPORTANCE, from, to))
+    }
+    fn swap_user_easiness(&self, id: UserId, from: usize, to: usize) -> Option<OrderedTasks> {
+        self.transaction(|tx| reorder(tx, id, EASINESS, from, to))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ParallelSimpleAthensSpace, SimpleAthensSpace};
+    #[test]
+    fn swaps_match_simple_athens_space() {
+        let sqlite = SqliteAthensSpace::open_in_memory().unwrap();
+        let simple = ParallelSimpleAthensSpace::new(SimpleAthensSpace::new().into());
+        for space in [&sqlite as &dyn AthensSpace, &simple] {
+            space.create_user();
+            for _ in 0..5 {
+                space.create_task();
+            }
+            space.create_user();
+        }
+        for (user, from, to) in [(0, 0, 4), (0, 3, 1), (1, 2, 2), (1, 4, 0)] {
+            assert_eq!(
+                sqlite.swap_user_importance(UserId(user), from, to),
+                simple.swap_user_importance(UserId(user), from, to),
+            );
+        }
+        assert_eq!(sqlite.swap_user_easiness(UserId(0), 5, 0), None);
+        assert_eq!(sqlite.important_tasks(), simple.important_tasks());
+    }
+    #[test]
+    fn reopening_keeps_data() {
+        let path = std::env::temp_dir().join(format!("athens-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let space = SqliteAthensSpace::open(&path).unwrap();
+            let id = space.create_task().id;
+            space.set_task(Task {
+                id,
+                text: "persisted".to_string(),
+                external: Some(ExternalRef {
+                    number: 7,
+                    url: "https://example.com/issues/7".to_string(),
+                }),
+            });
+            space.create_user();
+        }
+        let space = SqliteAthensSpace::open(&path).unwrap();
+        let task = space.get_task(TaskId(0)).unwrap();
+        assert_eq!(task.text, "persisted");
+        assert_eq!(task.external.unwrap().number, 7);
+        assert_eq!(space.users(), vec![UserId(0)]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
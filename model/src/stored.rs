@@ -0,0 +1,165 @@
+//! The on disk format of a space.
+//!
+//! Stored documents are `{"schema": N, "space": ...}`. Documents written by
+//! older versions are upgraded on load by a chain of migrations, each taking
+//! the JSON of schema `N` to schema `N + 1`. Older shapes have no envelope:
+//!
+//! - Schema 0 is the frontend's original single user
+//!   `Model { entries, importance, easiness }`.
+//! - Schema 1 is serde's rendering of `SimpleAthensSpace`, as stored in
+//!   `data.txt`.
+//!
+//! To change the format, bump `SCHEMA` and add a migration to `MIGRATIONS`.
+
+use super::SimpleAthensSpace;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The schema written by `to_stored`.
+pub const SCHEMA: u64 = 2;
+
+/// `MIGRATIONS[n]` upgrades schema `n` to `n + 1`.
+const MIGRATIONS: [fn(Value) -> Result<Value, FormatError>; SCHEMA as usize] =
+    [legacy_model_to_space, unversioned_to_envelope];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// Not JSON or not the shape its schema says.
+    Json(String),
+    /// Written by a newer version of Athens.
+    UnknownSchema(u64),
+}
+
+impl From<serde_json::Error> for FormatError {
+    fn from(e: serde_json::Error) -> Self {
+        FormatError::Json(e.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    schema: u64,
+    space: T,
+}
+
+impl SimpleAthensSpace {
+    /// Serializes the space in the current schema.
+    pub fn to_stored(&self) -> String {
+        serde_json::to_string(&Envelope {
+            schema: SCHEMA,
+            space: self,
+        })
+        .unwrap()
+    }
+    /// Loads a space stored in any schema.
+    pub fn from_stored(stored: &str) -> Result<Self, FormatError> {
+        let document = serde_json::from_str(stored)?;
+        let Envelope { space, .. } = serde_json::from_value(upgrade(document)?)?;
+        Ok(space)
+    }
+}
+
+fn schema_of(document: &Value) -> u64 {
+    match document.get("schema").and_then(Value::as_u64) {
+        Some(schema) => schema,
+        None if document.get("entries").is_some() => 0,
+        None => 1,
+    }
+}
+
+/// Migrates `document` to the current schema.
+fn upgrade(mut document: Value) -> Result<Value, FormatError> {
+    let schema = schema_of(&document);
+    if schema > SCHEMA {
+        return Err(FormatError::UnknownSchema(schema));
+    }
+    for migrate in MIGRATIONS[schema as usize..].iter() {
+        document = migrate(document)?;
+    }
+    Ok(document)
+}
+
+/// The legacy model had a single implicit user, who keeps its ballots.
+fn legacy_model_to_space(model: Value) -> Result<Value, FormatError> {
+    #[derive(Deserialize)]
+    struct Model {
+        entries: Vec<String>,
+        importance: Vec<usize>,
+        easiness: Vec<usize>,
+    }
+    let model: Model = serde_json::from_value(model)?;
+    let tasks: Vec<Value> = model
+        .entries
+        .into_iter()
+        .enumerate()
+        .map(|(id, text)| json!({ "id": id, "text": text }))
+        .collect();
+    Ok(json!({
+        "id": 0,
+        "alias": "My space",
+        "tasks": tasks,
+        "users": [{
+            "user": { "id": 0, "alias": "", "weight": 1 },
+            "importance": model.importance,
+            "easiness": model.easiness,
+        }],
+    }))
+}
+
+/// `data.txt` predates history and versions, they start out empty.
+fn unversioned_to_envelope(mut space: Value) -> Result<Value, FormatError> {
+    let fields = space
+        .as_object_mut()
+        .ok_or_else(|| FormatError::Json("Expected a space".to_string()))?;
+    fields.entry("history").or_insert_with(|| json!([]));
+    fields.entry("version").or_insert_with(|| json!(0));
+    Ok(json!({ "schema": 2, "space": space }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // The same space in every schema.
+    const GOLDEN: [&str; SCHEMA as usize + 1] = [
+        include_str!("../testdata/schema-0.json"),
+        include_str!("../testdata/schema-1.json"),
+        include_str!("../testdata/schema-2.json"),
+    ];
+
+    fn parse(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn golden_files_have_their_schema() {
+        for (schema, golden) in GOLDEN.iter().enumerate() {
+            assert_eq!(schema_of(&parse(golden)), schema as u64);
+        }
+    }
+
+    #[test]
+    fn each_migration_matches_next_golden_file() {
+        for (schema, migrate) in MIGRATIONS.iter().enumerate() {
+            let migrated = migrate(parse(GOLDEN[schema])).unwrap();
+            assert_eq!(migrated, parse(GOLDEN[schema + 1]), "schema {}", schema);
+        }
+    }
+
+    #[test]
+    fn every_schema_loads() {
+        let current = SimpleAthensSpace::from_stored(GOLDEN[SCHEMA as usize]).unwrap();
+        for golden in GOLDEN {
+            assert_eq!(SimpleAthensSpace::from_stored(golden).unwrap(), current);
+        }
+        assert_eq!(parse(&current.to_stored()), parse(GOLDEN[SCHEMA as usize]));
+    }
+
+    #[test]
+    fn newer_schema_is_rejected() {
+        assert_eq!(
+            SimpleAthensSpace::from_stored(r#"{"schema": 99, "space": {}}"#),
+            Err(FormatError::UnknownSchema(99))
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Checks that a space from an untrusted client is one Athens could have
+//! made.
+
+use super::{Ballot, OrderedTasks, SimpleAthensSpace, TaskId, UserId};
+use std::fmt;
+
+/// The largest weight a user can have.
+pub const MAX_WEIGHT: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalid {
+    /// Tasks are numbered by their position in the space.
+    TaskId {
+        position: usize,
+        id: TaskId,
+    },
+    /// Users are numbered by their position in the space.
+    UserId {
+        position: usize,
+        id: UserId,
+    },
+    Weight {
+        user: UserId,
+        weight: u32,
+    },
+    /// Every ballot ranks each task exactly once.
+    UnknownTask {
+        user: UserId,
+        ballot: Ballot,
+        task: TaskId,
+    },
+    RepeatedTask {
+        user: UserId,
+        ballot: Ballot,
+        task: TaskId,
+    },
+    MissingTask {
+        user: UserId,
+        ballot: Ballot,
+        task: TaskId,
+    },
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = |ballot: &Ballot| match ballot {
+            Ballot::Importance => "importance",
+            Ballot::Easiness => "easiness",
+        };
+        match self {
+            Invalid::TaskId { position, id } => {
+                write!(f, "Task number {} has id {}", position, id.0)
+            }
+            Invalid::UserId { position, id } => {
+                write!(f, "User number {} has id {}", position, id.0)
+            }
+            Invalid::Weight { user, weight } => write!(
+                f,
+                "User {} has weight {}, at most {} is allowed",
+                user.0, weight, MAX_WEIGHT
+            ),
+            Invalid::UnknownTask { user, ballot, task } => {
+                let ballot = name(ballot);
+                write!(
+                    f,
+                    "User {}'s {} ballot has unknown task {}",
+                    user.0, ballot, task.0
+                )
+            }
+            Invalid::RepeatedTask { user, ballot, task } => {
+                let ballot = name(ballot);
+                write!(
+                    f,
+                    "User {}'s {} ballot repeats task {}",
+                    user.0, ballot, task.0
+                )
+            }
+            Invalid::MissingTask { user, ballot, task } => {
+                let ballot = name(ballot);
+                write!(
+                    f,
+                    "User {}'s {} ballot is missing task {}",
+                    user.0, ballot, task.0
+                )
+            }
+        }
+    }
+}
+
+impl SimpleAthensSpace {
+    /// Checks ids are dense, weights are at most `MAX_WEIGHT` and every
+    /// ballot is a permutation of the tasks.
+    pub fn validate(&self) -> Result<(), Invalid> {
+        for (position, task) in self.tasks.iter().enumerate() {
+            if task.id != TaskId(position) {
+                let id = task.id;
+                return Err(Invalid::TaskId { position, id });
+            }
+        }
+        for (position, u) in self.users.iter().enumerate() {
+            let user = u.user.id;
+            if user != UserId(position) {
+                return Err(Invalid::UserId { position, id: user });
+            }
+            if u.user.weight > MAX_WEIGHT {
+                let weight = u.user.weight;
+                return Err(Invalid::Weight { user, weight });
+            }
+            let tasks = self.tasks.len();
+            check_ballot(user, Ballot::Importance, &u.importance, tasks)?;
+            check_ballot(user, Ballot::Easiness, &u.easiness, tasks)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_ballot(
+    user: UserId,
+    ballot: Ballot,
+    order: &OrderedTasks,
+    tasks: usize,
+) -> Result<(), Invalid> {
+    let mut seen = vec![false; tasks];
+    for task in order.iter() {
+        match seen.get_mut(task.0) {
+            None => return Err(Invalid::UnknownTask { user, ballot, task }),
+            Some(true) => return Err(Invalid::RepeatedTask { user, ballot, task }),
+            Some(seen) => *seen = true,
+        }
+    }
+    match seen.iter().position(|seen| !seen) {
+        Some(missing) => Err(Invalid::MissingTask {
+            user,
+            ballot,
+            task: TaskId(missing),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn space() -> SimpleAthensSpace {
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_task();
+        space.new_user();
+        space
+    }
+
+    #[test]
+    fn accepts_what_athens_makes() {
+        assert_eq!(SimpleAthensSpace::new().validate(), Ok(()));
+        assert_eq!(space().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_ids_weights_and_ballots() {
+        let mut s = space();
+        s.tasks[1].id = TaskId(0);
+        let expected = Invalid::TaskId {
+            position: 1,
+            id: TaskId(0),
+        };
+        assert_eq!(s.validate(), Err(expected));
+
+        let mut s = space();
+        s.users[0].user.weight = MAX_WEIGHT + 1;
+        assert!(matches!(s.validate(), Err(Invalid::Weight { .. })));
+
+        let invalid = |importance: Vec<TaskId>| {
+            let mut s = space();
+            s.users[0].importance = OrderedTasks(importance);
+            s.validate().unwrap_err()
+        };
+        let (user, ballot) = (UserId(0), Ballot::Importance);
+        assert_eq!(
+            invalid(vec![TaskId(0), TaskId(2)]),
+            Invalid::UnknownTask {
+                user,
+                ballot,
+                task: TaskId(2)
+            }
+        );
+        assert_eq!(
+            invalid(vec![TaskId(1), TaskId(1)]),
+            Invalid::RepeatedTask {
+                user,
+                ballot,
+                task: TaskId(1)
+            }
+        );
+        let missing = invalid(vec![TaskId(1)]);
+        assert_eq!(
+            missing.to_string(),
+            "User 0's importance ballot is missing task 0"
+        );
+    }
+}
@@ -0,0 +1,933 @@
+//! Accounts people log in with, which user each is in every space, and the
+//! invites to join spaces.
+//!
+//! Accounts are kept in the JSON file at `accounts_path`, or only in memory
+//! with the `"memory"` storage. Passwords are stored as salted
+//! PBKDF2-HMAC-SHA256 hashes of `password_iterations` rounds.
+//!
+//! Accounts are members of spaces with a `Role`. The account that creates a
+//! space owns it. Spaces from before accounts, which nobody owns, may only
+//! be viewed, except by the accounts listed in `admins`, who may make
+//! owners of any space. Nothing is changed without logging in. Owners
+//! invite others with links that can expire, run out of uses, or be
+//! revoked. Each use of an invite is kept on it. Only owners say who is
+//! which existing user, others become the new user they join as.
+//!
+//! A login is a random session id kept by the server for `session_days`,
+//! and in a private cookie encrypted with Rocket's `secret_key`, which has
+//! to be set in release builds. Logging out ends the session, changing the
+//! password ends every session of the account. Sessions are only kept in
+//! memory, so restarts log everyone out.
+
+use crate::journal::write_atomic;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use hmac::{Hmac, Mac};
+use model::api::{
+    AccountInfo, Credentials, Invite, Member, Membership, PasswordChange, Redemption, Role,
+};
+use model::{SpaceId, Timestamp, UserId};
+use rocket::http::{Cookie, CookieJar, Status};
+use rocket::request::{self, FromRequest, Request};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub const SESSION_COOKIE: &str = "athens_session";
+const MIN_PASSWORD_LEN: usize = 8;
+const SALT_LEN: usize = 16;
+const TOKEN_LEN: usize = 16;
+/// How long sessions last unless configured otherwise.
+const SESSION_DAYS: u64 = 30;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Account {
+    name: String,
+    salt: String,
+    hash: String,
+    iterations: u32,
+    #[serde(default)]
+    spaces: Vec<Membership>,
+}
+
+impl Account {
+    fn info(&self) -> AccountInfo {
+        AccountInfo {
+            name: self.name.clone(),
+            spaces: self.spaces.clone(),
+        }
+    }
+    fn membership(&mut self, space: SpaceId) -> Option<&mut Membership> {
+        self.spaces.iter_mut().find(|m| m.space == space)
+    }
+    fn has_password(&self, password: &str) -> bool {
+        let salt = BASE64.decode(&self.salt).unwrap_or_default();
+        let hash = pbkdf2(password, &salt, self.iterations);
+        let stored = BASE64.decode(&self.hash).unwrap_or_default();
+        // Compares every byte, so the time taken does not tell how much matched.
+        stored.len() == hash.len()
+            && stored
+                .iter()
+                .zip(hash.iter())
+                .fold(0, |d, (a, b)| d | (a ^ b))
+                == 0
+    }
+}
+
+/// Everything in the accounts file.
+#[derive(Default, Serialize, Deserialize)]
+struct Directory {
+    accounts: BTreeMap<String, Account>,
+    #[serde(default)]
+    invites: Vec<Invite>,
+    /// Accounts that own the spaces nobody owns, from the configuration.
+    #[serde(skip)]
+    admins: BTreeSet<String>,
+}
+
+impl Directory {
+    fn is_owned(&self, space: SpaceId) -> bool {
+        let mut memberships = self.accounts.values().flat_map(|a| a.spaces.iter());
+        memberships.any(|m| m.space == space && m.role == Role::Owner)
+    }
+
+    /// Only members and admins read a space somebody owns. Spaces nobody owns
+    /// may be read by every account.
+    fn may_read(&self, name: &str, space: SpaceId) -> bool {
+        let member = self
+            .accounts
+            .get(name)
+            .is_some_and(|a| a.spaces.iter().any(|m| m.space == space));
+        member || self.admins.contains(name) || !self.is_owned(space)
+    }
+
+    /// Accounts that are not members of a space may only view it. Admins own
+    /// the spaces nobody owns, until they make someone else owner.
+    fn role(&self, name: Option<&str>, space: SpaceId) -> Role {
+        if !self.is_owned(space) {
+            return match name {
+                Some(name) if self.admins.contains(name) => Role::Owner,
+                _ => Role::Viewer,
+            };
+        }
+        let account = name.and_then(|name| self.accounts.get(name));
+        let membership = account.and_then(|a| a.spaces.iter().find(|m| m.space == space));
+        membership.map_or(Role::Viewer, |m| m.role)
+    }
+
+    /// Makes the member `user` in the space. 403 if another account already
+    /// is, 404 if the account is not a member and 409 if it is already
+    /// someone else there.
+    fn bind(&mut self, name: &str, space: SpaceId, user: UserId) -> Result<(), Status> {
+        let taken = self.accounts.values().any(|a| {
+            let user = Some(user);
+            a.name != name && a.spaces.iter().any(|m| m.space == space && m.user == user)
+        });
+        if taken {
+            return Err(Status::Forbidden);
+        }
+        let account = self.accounts.get_mut(name).ok_or(Status::NotFound)?;
+        match account.membership(space) {
+            Some(m) if m.user == Some(user) => Ok(()),
+            Some(m) if m.user.is_some() => Err(Status::Conflict),
+            Some(m) => {
+                m.user = Some(user);
+                Ok(())
+            }
+            None => Err(Status::NotFound),
+        }
+    }
+
+    /// The invite, if the account can join its space with it, see
+    /// `Accounts::invited`.
+    fn invite_for(&self, name: &str, token: &str, now: Timestamp) -> Result<&Invite, Status> {
+        let invite = self
+            .invites
+            .iter()
+            .find(|i| i.token == token)
+            .ok_or(Status::NotFound)?;
+        if !invite.is_usable(now) {
+            return Err(Status::Gone);
+        }
+        let account = self.accounts.get(name).ok_or(Status::Unauthorized)?;
+        if account.spaces.iter().any(|m| m.space == invite.space) {
+            return Err(Status::Conflict);
+        }
+        Ok(invite)
+    }
+
+    /// 403 unless the account owns the space.
+    fn check_owner(&self, name: &str, space: SpaceId) -> Result<(), Status> {
+        match self.role(Some(name), space) {
+            Role::Owner => Ok(()),
+            _ => Err(Status::Forbidden),
+        }
+    }
+}
+
+pub struct Accounts {
+    // Where accounts are written, none to keep them in memory.
+    path: Option<PathBuf>,
+    iterations: u32,
+    directory: Mutex<Directory>,
+    sessions: Mutex<HashMap<String, Login>>,
+    session_lifetime: Duration,
+}
+
+/// A session, whose id is in the caller's cookie.
+struct Login {
+    account: String,
+    expires: Instant,
+}
+
+/// PBKDF2 with a single block, as long as the HMAC.
+fn pbkdf2(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mac = Hmac::<Sha256>::new_from_slice(password.as_bytes()).unwrap();
+    let mut block = mac.clone();
+    block.update(salt);
+    block.update(&1u32.to_be_bytes());
+    let mut u: [u8; 32] = block.finalize().into_bytes().into();
+    let mut hash = u;
+    for _ in 1..iterations {
+        let mut round = mac.clone();
+        round.update(&u);
+        u = round.finalize().into_bytes().into();
+        hash.iter_mut().zip(u.iter()).for_each(|(h, u)| *h ^= u);
+    }
+    hash
+}
+
+impl Accounts {
+    pub fn open(path: impl Into<PathBuf>, iterations: u32) -> io::Result<Self> {
+        let path = path.into();
+        let directory = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Directory::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: Some(path),
+            iterations,
+            directory: Mutex::new(directory),
+            sessions: Mutex::default(),
+            session_lifetime: Duration::from_secs(SESSION_DAYS * 24 * 3600),
+        })
+    }
+
+    pub fn in_memory(iterations: u32) -> Self {
+        Self {
+            path: None,
+            iterations,
+            directory: Mutex::default(),
+            sessions: Mutex::default(),
+            session_lifetime: Duration::from_secs(SESSION_DAYS * 24 * 3600),
+        }
+    }
+
+    /// The accounts that own the spaces nobody owns.
+    pub fn with_admins(self, admins: impl IntoIterator<Item = String>) -> Self {
+        self.directory.lock().unwrap().admins = admins.into_iter().collect();
+        self
+    }
+
+    /// Sessions end `lifetime` after logging in.
+    pub fn with_session_lifetime(self, lifetime: Duration) -> Self {
+        Self {
+            session_lifetime: lifetime,
+            ..self
+        }
+    }
+
+    fn save(&self, directory: &Directory) -> Result<(), Status> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let json = serde_json::to_vec_pretty(directory).unwrap();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(crate::internal_error)?;
+        }
+        write_atomic(path, &json).map_err(crate::internal_error)
+    }
+
+    /// 400 for a blank name or a password shorter than 8 characters, 409
+    /// if the name is taken.
+    pub fn register(&self, credentials: &Credentials) -> Result<AccountInfo, Status> {
+        let name = credentials.name.trim();
+        if name.is_empty() || credentials.password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(Status::BadRequest);
+        }
+        let mut directory = self.directory.lock().unwrap();
+        if directory.accounts.contains_key(name) {
+            return Err(Status::Conflict);
+        }
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let account = Account {
+            name: name.to_string(),
+            salt: BASE64.encode(salt),
+            hash: BASE64.encode(pbkdf2(&credentials.password, &salt, self.iterations)),
+            iterations: self.iterations,
+            spaces: Vec::new(),
+        };
+        let info = account.info();
+        directory.accounts.insert(account.name.clone(), account);
+        self.save(&directory)?;
+        Ok(info)
+    }
+
+    /// 401 unless the account exists and the password is right.
+    pub fn login(&self, credentials: &Credentials) -> Result<AccountInfo, Status> {
+        let directory = self.directory.lock().unwrap();
+        let account = match directory.accounts.get(credentials.name.trim()) {
+            Some(account) => account,
+            None => {
+                // Takes as long as a wrong password, so the time taken does
+                // not tell which names exist.
+                pbkdf2(&credentials.password, &[0; SALT_LEN], self.iterations);
+                return Err(Status::Unauthorized);
+            }
+        };
+        if !account.has_password(&credentials.password) {
+            return Err(Status::Unauthorized);
+        }
+        Ok(account.info())
+    }
+
+    /// Changes the account's password and ends its sessions. 401 unless
+    /// `password` is the current one, 400 if the new one is too short.
+    pub fn set_password(&self, name: &str, change: &PasswordChange) -> Result<(), Status> {
+        if change.new_password.chars().count() < MIN_PASSWORD_LEN {
+            return Err(Status::BadRequest);
+        }
+        let mut directory = self.directory.lock().unwrap();
+        let account = directory
+            .accounts
+            .get_mut(name)
+            .ok_or(Status::Unauthorized)?;
+        if !account.has_password(&change.password) {
+            return Err(Status::Unauthorized);
+        }
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        account.salt = BASE64.encode(salt);
+        account.hash = BASE64.encode(pbkdf2(&change.new_password, &salt, self.iterations));
+        account.iterations = self.iterations;
+        self.save(&directory)?;
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, login| login.account != name);
+        Ok(())
+    }
+
+    /// Starts a session for the account, returning its id.
+    pub fn start_session(&self, name: &str) -> String {
+        let mut id = [0; TOKEN_LEN];
+        OsRng.fill_bytes(&mut id);
+        let id = URL_SAFE_NO_PAD.encode(id);
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, login| login.expires > now);
+        let login = Login {
+            account: name.to_string(),
+            expires: now + self.session_lifetime,
+        };
+        sessions.insert(id.clone(), login);
+        id
+    }
+
+    /// The account logged in with the session, unless it ended.
+    pub fn session(&self, id: &str) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap();
+        let login = sessions.get(id).filter(|l| l.expires > Instant::now())?;
+        Some(login.account.clone())
+    }
+
+    pub fn end_session(&self, id: &str) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    pub fn info(&self, name: &str) -> Option<AccountInfo> {
+        let directory = self.directory.lock().unwrap();
+        directory.accounts.get(name).map(Account::info)
+    }
+
+    /// What the account, or someone not logged in, may do in the space.
+    pub fn role(&self, name: Option<&str>, space: SpaceId) -> Role {
+        self.directory.lock().unwrap().role(name, space)
+    }
+
+    pub fn may_read(&self, name: &str, space: SpaceId) -> bool {
+        self.directory.lock().unwrap().may_read(name, space)
+    }
+
+    /// Whether the account is listed in `admins`.
+    pub fn is_admin(&self, name: &str) -> bool {
+        self.directory.lock().unwrap().admins.contains(name)
+    }
+
+    /// Makes the account `user` in the space, for owners who created or
+    /// claim the user. 403 unless the account owns the space or if another
+    /// account already is the user, 404 if the account is not a member and
+    /// 409 if it is already someone else there. Others are made users by the
+    /// owners, see `assign`.
+    pub fn bind(&self, name: &str, space: SpaceId, user: UserId) -> Result<(), Status> {
+        let mut directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        directory.bind(name, space, user)?;
+        self.save(&directory)
+    }
+
+    /// Makes a member `user` in the space, if the account owns it. 404 if
+    /// they are not a member, otherwise like `bind`.
+    pub fn assign(
+        &self,
+        name: &str,
+        space: SpaceId,
+        member: &str,
+        user: UserId,
+    ) -> Result<Member, Status> {
+        let mut directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        directory.bind(member, space, user)?;
+        let account = directory.accounts.get_mut(member).ok_or(Status::NotFound)?;
+        let role = account.membership(space).ok_or(Status::NotFound)?.role;
+        let member = Member {
+            account: account.name.clone(),
+            role,
+            user: Some(user),
+        };
+        self.save(&directory)?;
+        Ok(member)
+    }
+
+    /// Makes the account the space's owner, unless it has one.
+    pub fn adopt(&self, name: &str, space: SpaceId) -> Result<(), Status> {
+        let mut directory = self.directory.lock().unwrap();
+        if directory.is_owned(space) {
+            return Ok(());
+        }
+        let account = directory
+            .accounts
+            .get_mut(name)
+            .ok_or(Status::Unauthorized)?;
+        account.spaces.push(Membership {
+            space,
+            role: Role::Owner,
+            user: None,
+        });
+        self.save(&directory)
+    }
+
+    /// Makes the member an owner of the space, or the account a new member
+    /// owning it, if the account is an admin. 404 for unknown members.
+    pub fn make_owner(&self, name: &str, space: SpaceId, member: &str) -> Result<Member, Status> {
+        let mut directory = self.directory.lock().unwrap();
+        if !directory.admins.contains(name) {
+            return Err(Status::Forbidden);
+        }
+        let account = directory.accounts.get_mut(member).ok_or(Status::NotFound)?;
+        let member_name = account.name.clone();
+        let membership = match account.membership(space) {
+            Some(membership) => membership,
+            None => {
+                account.spaces.push(Membership {
+                    space,
+                    role: Role::Owner,
+                    user: None,
+                });
+                account.spaces.last_mut().unwrap()
+            }
+        };
+        membership.role = Role::Owner;
+        let member = Member {
+            account: member_name,
+            role: Role::Owner,
+            user: membership.user,
+        };
+        self.save(&directory)?;
+        log::info!(
+            "{} made {} owner of space {}",
+            name,
+            member.account,
+            space.0
+        );
+        Ok(member)
+    }
+
+    /// The space's members, if the account owns it.
+    pub fn members(&self, name: &str, space: SpaceId) -> Result<Vec<Member>, Status> {
+        let directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        let members = directory.accounts.values().filter_map(|a| {
+            let m = a.spaces.iter().find(|m| m.space == space)?;
+            Some(Member {
+                account: a.name.clone(),
+                role: m.role,
+                user: m.user,
+            })
+        });
+        Ok(members.collect())
+    }
+
+    /// Changes a member's role, if the account owns the space. 404 if they
+    /// are not a member, 409 if it would leave the space without owners.
+    pub fn set_role(
+        &self,
+        name: &str,
+        space: SpaceId,
+        member: &str,
+        role: Role,
+    ) -> Result<Member, Status> {
+        let mut directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        let owners = directory
+            .accounts
+            .values()
+            .filter(|a| {
+                a.spaces
+                    .iter()
+                    .any(|m| m.space == space && m.role == Role::Owner)
+            })
+            .count();
+        let account = directory.accounts.get_mut(member).ok_or(Status::NotFound)?;
+        let name = account.name.clone();
+        let membership = account.membership(space).ok_or(Status::NotFound)?;
+        if membership.role == Role::Owner && role != Role::Owner && owners == 1 {
+            return Err(Status::Conflict);
+        }
+        membership.role = role;
+        let member = Member {
+            account: name,
+            role,
+            user: membership.user,
+        };
+        self.save(&directory)?;
+        Ok(member)
+    }
+
+    /// A new invite to the space for people with `role`, if the account
+    /// owns it.
+    pub fn invite(
+        &self,
+        name: &str,
+        space: SpaceId,
+        now: Timestamp,
+        expires: Option<Timestamp>,
+        max_uses: Option<u32>,
+        role: Role,
+    ) -> Result<Invite, Status> {
+        let mut directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        let mut token = [0; TOKEN_LEN];
+        OsRng.fill_bytes(&mut token);
+        let invite = Invite {
+            token: URL_SAFE_NO_PAD.encode(token),
+            space,
+            created_by: name.to_string(),
+            created: now,
+            expires,
+            max_uses,
+            role,
+            revoked: false,
+            redemptions: Vec::new(),
+        };
+        directory.invites.push(invite.clone());
+        self.save(&directory)?;
+        Ok(invite)
+    }
+
+    /// The space's invites, if the account owns it.
+    pub fn invites(&self, name: &str, space: SpaceId) -> Result<Vec<Invite>, Status> {
+        let directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        let invites = directory.invites.iter().filter(|i| i.space == space);
+        Ok(invites.cloned().collect())
+    }
+
+    /// Stops the invite from being used, if the account owns its space.
+    pub fn revoke(&self, name: &str, space: SpaceId, token: &str) -> Result<Invite, Status> {
+        let mut directory = self.directory.lock().unwrap();
+        directory.check_owner(name, space)?;
+        let invite = directory
+            .invites
+            .iter_mut()
+            .find(|i| i.space == space && i.token == token)
+            .ok_or(Status::NotFound)?;
+        invite.revoked = true;
+        let invite = invite.clone();
+        self.save(&directory)?;
+        Ok(invite)
+    }
+
+    /// The space the invite is to, if the account can join it with the
+    /// invite. 404 for unknown invites, 410 if it can no longer be used and
+    /// 409 if the account is already a member of the space.
+    pub fn invited(&self, name: &str, token: &str, now: Timestamp) -> Result<SpaceId, Status> {
+        let directory = self.directory.lock().unwrap();
+        directory.invite_for(name, token, now).map(|i| i.space)
+    }
+
+    /// Makes the account a member as the user who joined with the invite,
+    /// and logs the use on the invite. Checks the invite again like
+    /// `invited`, so it cannot be used more often than allowed, and changes
+    /// nothing if anything fails.
+    pub fn redeem(
+        &self,
+        name: &str,
+        token: &str,
+        user: UserId,
+        now: Timestamp,
+    ) -> Result<(), Status> {
+        let mut directory = self.directory.lock().unwrap();
+        let invite = directory.invite_for(name, token, now)?;
+        let membership = Membership {
+            space: invite.space,
+            role: invite.role,
+            user: Some(user),
+        };
+        // Found by `invite_for`.
+        let index = directory.invites.iter().position(|i| i.token == token);
+        let index = index.unwrap();
+        directory.invites[index].redemptions.push(Redemption {
+            account: name.to_string(),
+            user,
+            at: now,
+        });
+        let account = directory.accounts.get_mut(name).unwrap();
+        account.spaces.push(membership);
+        if let Err(status) = self.save(&directory) {
+            directory.accounts.get_mut(name).unwrap().spaces.pop();
+            directory.invites[index].redemptions.pop();
+            return Err(status);
+        }
+        log::info!(
+            "{} joined space {} as user {}",
+            name,
+            membership.space.0,
+            user.0
+        );
+        Ok(())
+    }
+}
+
+/// Whoever is calling, logged in or not.
+pub struct Caller {
+    account: Option<String>,
+    accounts: Arc<Accounts>,
+}
+
+impl Caller {
+    pub fn account(&self) -> Option<&str> {
+        self.account.as_deref()
+    }
+
+    pub fn info(&self) -> Option<AccountInfo> {
+        self.accounts.info(self.account()?)
+    }
+
+    /// Who the caller is in the space.
+    pub fn user(&self, space: SpaceId) -> Option<UserId> {
+        self.info().and_then(|info| info.user(space))
+    }
+
+    pub fn role(&self, space: SpaceId) -> Role {
+        self.accounts.role(self.account(), space)
+    }
+
+    /// For reading and changing the space: 401 unless the caller is logged
+    /// in, 403 unless they may read it and their role is at least `needed`.
+    pub fn require(&self, space: SpaceId, needed: Role) -> Result<(), Status> {
+        match self.account() {
+            None => Err(Status::Unauthorized),
+            Some(name) if !self.accounts.may_read(name, space) => Err(Status::Forbidden),
+            Some(_) if self.role(space) >= needed => Ok(()),
+            Some(_) => Err(Status::Forbidden),
+        }
+    }
+
+    /// For looking after the space, like its snapshots: as `require` for
+    /// owners, except that admins may look after any space.
+    pub fn require_admin(&self, space: SpaceId) -> Result<(), Status> {
+        match self.account() {
+            Some(name) if self.accounts.is_admin(name) => Ok(()),
+            _ => self.require(space, Role::Owner),
+        }
+    }
+
+    /// Whether the caller is a member of the space, which admins of spaces
+    /// nobody owns are not.
+    pub fn is_member(&self, space: SpaceId) -> bool {
+        self.info()
+            .is_some_and(|info| info.membership(space).is_some())
+    }
+
+    pub fn bind(&self, space: SpaceId, user: UserId) -> Result<(), Status> {
+        let account = self.account().ok_or(Status::Unauthorized)?;
+        self.accounts.bind(account, space, user)
+    }
+
+    /// Only `user` may change their ballots, and only if they may vote.
+    pub fn may_vote(&self, space: SpaceId, user: UserId) -> Result<(), Status> {
+        self.account().ok_or(Status::Unauthorized)?;
+        self.require(space, Role::Voter)?;
+        match self.user(space) {
+            Some(caller) if caller == user => Ok(()),
+            _ => Err(Status::Forbidden),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Caller {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let accounts = request.rocket().state::<Arc<Accounts>>().unwrap().clone();
+        let account = request
+            .cookies()
+            .get_private(SESSION_COOKIE)
+            .and_then(|cookie| accounts.session(cookie.value()))
+            // The account may be gone with an old accounts file.
+            .filter(|account| accounts.info(account).is_some());
+        request::Outcome::Success(Caller { account, accounts })
+    }
+}
+
+/// A logged in caller, 401 if there is none.
+pub struct Session(Caller);
+
+impl Session {
+    /// Starts a session and remembers it in the caller's cookies.
+    pub fn start(cookies: &CookieJar<'_>, accounts: &Accounts, account: &str) {
+        let mut cookie = Cookie::new(SESSION_COOKIE, accounts.start_session(account));
+        cookie.set_secure(true);
+        cookies.add_private(cookie);
+    }
+
+    /// Ends the caller's session, if they have one.
+    pub fn end(cookies: &CookieJar<'_>, accounts: &Accounts) {
+        if let Some(cookie) = cookies.get_private(SESSION_COOKIE) {
+            accounts.end_session(cookie.value());
+        }
+        cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.account().unwrap()
+    }
+}
+
+impl Deref for Session {
+    type Target = Caller;
+
+    fn deref(&self) -> &Caller {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let caller = match Caller::from_request(request).await {
+            request::Outcome::Success(caller) => caller,
+            _ => unreachable!("Every request has a caller"),
+        };
+        match caller.account {
+            Some(_) => request::Outcome::Success(Session(caller)),
+            None => request::Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn credentials(name: &str, password: &str) -> Credentials {
+        Credentials {
+            name: name.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn pbkdf2_matches_the_rfc_vectors() {
+        // RFC 7914 section 11, the first 32 bytes.
+        let hash = pbkdf2("passwd", b"salt", 1);
+        assert_eq!(hash[..8], [0x55, 0xac, 0x04, 0x6e, 0x56, 0xe3, 0x08, 0x9f]);
+    }
+
+    #[test]
+    fn register_login_and_bind() {
+        let dir = std::env::temp_dir().join(format!("athens-accounts-{}", std::process::id()));
+        let path = dir.join("accounts.json");
+        let accounts = Accounts::open(&path, 10).unwrap();
+        let ada = credentials("ada", "correct horse");
+        accounts.register(&ada).unwrap();
+        assert_eq!(accounts.register(&ada), Err(Status::Conflict));
+        let short = credentials("bob", "short");
+        assert_eq!(accounts.register(&short), Err(Status::BadRequest));
+        let wrong = credentials("ada", "wrong horse");
+        assert_eq!(accounts.login(&wrong), Err(Status::Unauthorized));
+
+        accounts
+            .register(&credentials("bob", "battery staple"))
+            .unwrap();
+        // Binding alone makes nobody owner.
+        let ownerless = accounts.bind("ada", SpaceId(0), UserId(1));
+        assert_eq!(ownerless, Err(Status::Forbidden));
+        accounts.adopt("ada", SpaceId(0)).unwrap();
+        accounts.bind("ada", SpaceId(0), UserId(1)).unwrap();
+        let bob = accounts.bind("bob", SpaceId(0), UserId(1));
+        assert_eq!(bob, Err(Status::Forbidden));
+        let twice = accounts.bind("ada", SpaceId(0), UserId(2));
+        assert_eq!(twice, Err(Status::Conflict));
+        // Nobody claims a user of a space they do not own.
+        let unbound = accounts.bind("bob", SpaceId(0), UserId(2));
+        assert_eq!(unbound, Err(Status::Forbidden));
+
+        let session = accounts.start_session("ada");
+        assert_eq!(accounts.session(&session).as_deref(), Some("ada"));
+        accounts.end_session(&session);
+        assert_eq!(accounts.session(&session), None);
+        let expired = Accounts::in_memory(1).with_session_lifetime(Duration::ZERO);
+        assert_eq!(expired.session(&expired.start_session("ada")), None);
+
+        let reopened = Accounts::open(&path, 10).unwrap();
+        let info = reopened.login(&ada).unwrap();
+        assert_eq!(info.user(SpaceId(0)), Some(UserId(1)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invites_expire_run_out_and_are_revoked() {
+        let accounts = Accounts::in_memory(1);
+        for name in ["ada", "bob", "cy"] {
+            accounts
+                .register(&credentials(name, "correct horse"))
+                .unwrap();
+        }
+        let (space, now) = (SpaceId(3), Timestamp(100));
+        accounts.adopt("ada", space).unwrap();
+        accounts.bind("ada", space, UserId(0)).unwrap();
+        let invite = |expires, uses| accounts.invite("ada", space, now, expires, uses, Role::Voter);
+        assert_eq!(
+            accounts.invite("bob", space, now, None, None, Role::Member),
+            Err(Status::Forbidden)
+        );
+
+        let once = invite(None, Some(1)).unwrap();
+        assert_eq!(
+            accounts.invited("ada", &once.token, now),
+            Err(Status::Conflict)
+        );
+        assert_eq!(accounts.invited("bob", &once.token, now), Ok(space));
+        accounts.redeem("bob", &once.token, UserId(1), now).unwrap();
+        assert_eq!(accounts.info("bob").unwrap().user(space), Some(UserId(1)));
+        assert_eq!(accounts.role(Some("bob"), space), Role::Voter);
+        assert_eq!(accounts.invited("cy", &once.token, now), Err(Status::Gone));
+        // Redeeming checks again, for joins that raced.
+        let raced = accounts.redeem("cy", &once.token, UserId(2), now);
+        assert_eq!(raced, Err(Status::Gone));
+
+        let soon = invite(Some(Timestamp(150)), None).unwrap();
+        assert_eq!(accounts.invited("cy", &soon.token, now), Ok(space));
+        let later = Timestamp(200);
+        assert_eq!(
+            accounts.invited("cy", &soon.token, later),
+            Err(Status::Gone)
+        );
+
+        let revoked = invite(None, None).unwrap();
+        accounts.revoke("ada", space, &revoked.token).unwrap();
+        assert_eq!(
+            accounts.invited("cy", &revoked.token, now),
+            Err(Status::Gone)
+        );
+        assert_eq!(accounts.invited("cy", "nope", now), Err(Status::NotFound));
+
+        let invites = accounts.invites("ada", space).unwrap();
+        assert_eq!(invites.len(), 3);
+        assert_eq!(invites[0].redemptions[0].account, "bob");
+    }
+
+    #[test]
+    fn owners_manage_roles() {
+        let accounts = Accounts::in_memory(1);
+        for name in ["ada", "bob", "cy"] {
+            accounts
+                .register(&credentials(name, "correct horse"))
+                .unwrap();
+        }
+        let space = SpaceId(4);
+        assert_eq!(accounts.role(None, space), Role::Viewer);
+        assert_eq!(accounts.role(Some("ada"), space), Role::Viewer);
+        accounts.adopt("ada", space).unwrap();
+        assert_eq!(accounts.role(None, space), Role::Viewer);
+        assert_eq!(accounts.role(Some("bob"), space), Role::Viewer);
+        assert!(!accounts.may_read("bob", space));
+        assert_eq!(
+            accounts.bind("bob", space, UserId(0)),
+            Err(Status::Forbidden)
+        );
+
+        let invite = accounts
+            .invite("ada", space, Timestamp(0), None, None, Role::Member)
+            .unwrap();
+        accounts
+            .redeem("bob", &invite.token, UserId(0), Timestamp(0))
+            .unwrap();
+        assert_eq!(accounts.role(Some("bob"), space), Role::Member);
+        assert!(accounts.may_read("bob", space));
+        assert!(!accounts.may_read("cy", space));
+        assert_eq!(accounts.members("bob", space), Err(Status::Forbidden));
+        let members = accounts.members("ada", space).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(
+            accounts.bind("bob", space, UserId(1)),
+            Err(Status::Forbidden)
+        );
+        let assign = |name, member, user| accounts.assign(name, space, member, UserId(user));
+        assert_eq!(assign("bob", "ada", 1), Err(Status::Forbidden));
+        assert_eq!(assign("ada", "ada", 0), Err(Status::Forbidden));
+        assert_eq!(assign("ada", "ada", 1).unwrap().user, Some(UserId(1)));
+
+        let set = |member, role| accounts.set_role("ada", space, member, role);
+        assert_eq!(set("cy", Role::Voter), Err(Status::NotFound));
+        assert_eq!(set("ada", Role::Member), Err(Status::Conflict));
+        assert_eq!(set("bob", Role::Owner).unwrap().role, Role::Owner);
+        assert_eq!(set("ada", Role::Viewer).unwrap().role, Role::Viewer);
+        assert_eq!(accounts.role(Some("ada"), space), Role::Viewer);
+    }
+
+    #[test]
+    fn admins_make_owners_of_ownerless_spaces() {
+        let accounts = Accounts::in_memory(1).with_admins(["root".to_string()]);
+        for name in ["root", "ada"] {
+            accounts
+                .register(&credentials(name, "correct horse"))
+                .unwrap();
+        }
+        let space = SpaceId(0);
+        assert_eq!(accounts.role(Some("root"), space), Role::Owner);
+        assert_eq!(accounts.role(Some("ada"), space), Role::Viewer);
+        assert!(accounts.may_read("ada", space));
+        let make = |name, member| accounts.make_owner(name, space, member);
+        assert_eq!(make("ada", "ada"), Err(Status::Forbidden));
+        assert_eq!(make("root", "bob"), Err(Status::NotFound));
+        assert_eq!(make("root", "ada").unwrap().role, Role::Owner);
+        assert_eq!(accounts.role(Some("ada"), space), Role::Owner);
+        // Owned spaces are up to their owners, admins may still read them.
+        assert_eq!(accounts.role(Some("root"), space), Role::Viewer);
+        assert!(accounts.may_read("root", space));
+    }
+}
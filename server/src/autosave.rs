@@ -0,0 +1,401 @@
+//! Saves are accepted into memory immediately and written to storage once
+//! edits have stopped for a while, so bursts of edits cost one write.
+//!
+//! Every accepted version is also broadcast to the clients watching its
+//! space, and the latest are kept for clients catching up after a
+//! reconnect.
+
+use crate::limits::Caps;
+use crate::storage::Storage;
+use model::api::{Operation, SaveStatus, Update};
+use model::{SimpleAthensSpace, SpaceId};
+use rocket::tokio::sync::{broadcast, watch};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How many updates are kept for reconnecting clients, and how far a slow
+/// client can fall behind before it is disconnected.
+const RECENT_UPDATES: usize = 256;
+
+pub struct Autosave {
+    store: Mutex<Store>,
+    persisted: Persisted,
+    debounce: Duration,
+}
+
+/// The latest version of each space that is on disk. Spaces are in it once
+/// loaded or written.
+type Persisted = Arc<watch::Sender<BTreeMap<SpaceId, u64>>>;
+
+/// The latest accepted spaces and which still need to be written. Spaces
+/// are loaded from storage when first used.
+pub struct Store {
+    /// Locked after the store, if at all. Flushes write with only this held.
+    storage: Arc<Mutex<Box<dyn Storage>>>,
+    spaces: BTreeMap<SpaceId, SimpleAthensSpace>,
+    unsaved: BTreeSet<SpaceId>,
+    changed: Option<Changed>,
+    updates: broadcast::Sender<(SpaceId, Update)>,
+    recent: VecDeque<(SpaceId, Update)>,
+    caps: Caps,
+    persisted: Persisted,
+}
+
+struct Changed {
+    first: Instant,
+    last: Instant,
+}
+
+impl Store {
+    /// The space served by `/tasks`.
+    pub fn current(&self) -> Option<&SimpleAthensSpace> {
+        self.spaces.get(&crate::DEFAULT_SPACE)
+    }
+    /// The spaces loaded so far.
+    pub fn loaded(&self) -> impl Iterator<Item = &SimpleAthensSpace> {
+        self.spaces.values()
+    }
+    pub fn space(&mut self, id: SpaceId) -> io::Result<Option<&SimpleAthensSpace>> {
+        if !self.spaces.contains_key(&id) {
+            match self.storage.lock().unwrap().load(id)? {
+                Some(space) => {
+                    let version = space.version();
+                    self.persisted.send_modify(|p| {
+                        p.entry(id).or_insert(version);
+                    });
+                    self.spaces.insert(id, space)
+                }
+                None => return Ok(None),
+            };
+        }
+        Ok(self.spaces.get(&id))
+    }
+    /// Every space, stored or not yet saved.
+    pub fn ids(&mut self) -> io::Result<Vec<SpaceId>> {
+        let mut ids: BTreeSet<SpaceId> = self.storage.lock().unwrap().list()?.into_iter().collect();
+        ids.extend(self.spaces.keys().copied());
+        Ok(ids.into_iter().collect())
+    }
+    /// Whether the space fits the caps, given the stored space it replaces.
+    pub fn check_caps(&self, space: &SimpleAthensSpace) -> Result<(), String> {
+        self.caps.check(self.spaces.get(&space.id()), space)
+    }
+    /// Whether the caps leave room for another space.
+    pub fn has_room(&mut self) -> io::Result<bool> {
+        Ok(self.ids()?.len() < self.caps.spaces)
+    }
+    /// Replaces the space with the same id, it will be persisted by the next
+    /// flush. Clients watching the space are told to reload it.
+    pub fn update(&mut self, space: SimpleAthensSpace) {
+        self.publish(&space, None);
+        self.replace(space);
+    }
+    /// Like `update`, for a space changed by a single operation which is
+    /// passed on to the clients watching the space.
+    pub fn apply(&mut self, space: SimpleAthensSpace, op: Operation) {
+        self.publish(&space, Some(op));
+        self.replace(space);
+    }
+    /// The operations that made the versions of the space after `base`, if
+    /// each was a single operation and they are all still kept.
+    pub fn ops_since(&self, id: SpaceId, base: u64) -> Option<Vec<&Operation>> {
+        let version = self.spaces.get(&id).map_or(0, |s| s.version());
+        let since: Vec<&Update> = self
+            .recent
+            .iter()
+            .filter(|(space, update)| *space == id && update.version > base)
+            .map(|(_, update)| update)
+            .collect();
+        if base > version || since.len() as u64 != version - base {
+            return None;
+        }
+        since.into_iter().map(|update| update.op.as_ref()).collect()
+    }
+    fn publish(&mut self, space: &SimpleAthensSpace, op: Option<Operation>) {
+        let update = Update {
+            version: space.version(),
+            op,
+        };
+        if self.recent.len() == RECENT_UPDATES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((space.id(), update.clone()));
+        // Nobody may be watching.
+        let _ = self.updates.send((space.id(), update));
+    }
+    fn replace(&mut self, space: SimpleAthensSpace) {
+        let now = Instant::now();
+        self.unsaved.insert(space.id());
+        self.spaces.insert(space.id(), space);
+        let first = self.changed.as_ref().map_or(now, |c| c.first);
+        self.changed = Some(Changed { first, last: now });
+    }
+}
+
+impl Autosave {
+    /// `current` is the space already in `storage`. `caps` are what
+    /// `Store::check_caps` checks, updates themselves are not checked.
+    pub fn new(
+        storage: Box<dyn Storage>,
+        current: Option<SimpleAthensSpace>,
+        debounce: Duration,
+        caps: Caps,
+    ) -> Arc<Self> {
+        let persisted: BTreeMap<_, _> = current.iter().map(|s| (s.id(), s.version())).collect();
+        let persisted = Arc::new(watch::channel(persisted).0);
+        Arc::new(Self {
+            store: Mutex::new(Store {
+                storage: Arc::new(Mutex::new(storage)),
+                spaces: current.into_iter().map(|s| (s.id(), s)).collect(),
+                unsaved: BTreeSet::new(),
+                changed: None,
+                updates: broadcast::channel(RECENT_UPDATES).0,
+                recent: VecDeque::new(),
+                caps,
+                persisted: persisted.clone(),
+            }),
+            persisted,
+            debounce,
+        })
+    }
+    pub fn lock(&self) -> MutexGuard<'_, Store> {
+        self.store.lock().unwrap()
+    }
+    /// The accepted and persisted versions of a space, 0 for spaces that
+    /// were not loaded.
+    pub fn status(&self, id: SpaceId) -> SaveStatus {
+        let store = self.lock();
+        let persisted_version = self.persisted.borrow().get(&id).copied().unwrap_or(0);
+        SaveStatus {
+            version: store
+                .spaces
+                .get(&id)
+                .map_or(persisted_version, |s| s.version()),
+            persisted_version,
+        }
+    }
+    /// Watches the space with the id. The updates after version `since` that
+    /// are still kept come first, if a version is given. The receiver gets
+    /// updates to every space.
+    pub fn subscribe(
+        &self,
+        id: SpaceId,
+        since: Option<u64>,
+    ) -> (Vec<Update>, broadcast::Receiver<(SpaceId, Update)>) {
+        let store = self.lock();
+        let missed = match since {
+            Some(since) => store
+                .recent
+                .iter()
+                .filter(|(space, update)| *space == id && update.version > since)
+                .map(|(_, update)| update.clone())
+                .collect(),
+            None => Vec::new(),
+        };
+        (missed, store.updates.subscribe())
+    }
+    /// Waits until `version` of the space is persisted or `timeout` passes.
+    pub async fn wait_for(&self, id: SpaceId, version: u64, timeout: Duration) -> SaveStatus {
+        let mut persisted = self.persisted.subscribe();
+        let persisted = async move {
+            while persisted.borrow_and_update().get(&id).copied().unwrap_or(0) < version {
+                if persisted.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        let _ = rocket::tokio::time::timeout(timeout, persisted).await;
+        self.status(id)
+    }
+    /// Writes pending changes if edits stopped for the debounce period, or
+    /// have kept coming for ten of them.
+    pub fn flush_if_due(&self) -> io::Result<()> {
+        let due = self.lock().changed.as_ref().is_some_and(|c| {
+            c.last.elapsed() >= self.debounce || c.first.elapsed() >= self.debounce * 10
+        });
+        if due {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    /// Writes pending changes now. The store is only locked to copy them,
+    /// so requests are not kept waiting for the disk.
+    pub fn flush(&self) -> io::Result<()> {
+        let mut store = self.lock();
+        if store.changed.is_none() {
+            return Ok(());
+        }
+        let unsaved = std::mem::take(&mut store.unsaved);
+        let pending: Vec<SimpleAthensSpace> =
+            unsaved.iter().map(|id| store.spaces[id].clone()).collect();
+        store.changed = None;
+        // Taken before the store is released, so flushes write in order.
+        let storage = store.storage.clone();
+        let mut storage = storage.lock().unwrap();
+        drop(store);
+        let written = |spaces: &[SimpleAthensSpace]| {
+            self.persisted.send_modify(|p| {
+                for space in spaces {
+                    p.insert(space.id(), space.version());
+                }
+            })
+        };
+        for (i, space) in pending.iter().enumerate() {
+            if let Err(e) = storage.save(space) {
+                written(&pending[..i]);
+                drop(storage);
+                // The latest of these spaces are written by the next flush.
+                let mut store = self.lock();
+                store.unsaved.extend(pending[i..].iter().map(|s| s.id()));
+                let now = Instant::now();
+                store.changed.get_or_insert(Changed {
+                    first: now,
+                    last: now,
+                });
+                return Err(e);
+            }
+        }
+        written(&pending);
+        Ok(())
+    }
+    /// Flushes in the background until the server shuts down.
+    pub fn spawn(self: &Arc<Self>) {
+        let autosave = self.clone();
+        let tick = (self.debounce / 4).max(Duration::from_millis(10));
+        rocket::tokio::spawn(async move {
+            loop {
+                rocket::tokio::time::sleep(tick).await;
+                let flushing = autosave.clone();
+                let flushed = rocket::tokio::task::spawn_blocking(move || flushing.flush_if_due());
+                match flushed.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => log::error!("Autosave failed: {:?}", e),
+                    Err(e) => log::error!("Autosave panicked: {:?}", e),
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::SqliteStorage;
+
+    #[test]
+    fn flush_waits_for_debounce() {
+        let dir = std::env::temp_dir().join(format!("athens-autosave-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("db.sqlite3");
+        let storage = Box::new(SqliteStorage::open(&path, Arc::default()).unwrap());
+        let autosave = Autosave::new(storage, None, Duration::from_millis(50), Caps::default());
+        let mut space = SimpleAthensSpace::new();
+        space.set_version(1);
+        autosave.lock().update(space.clone());
+        autosave.flush_if_due().unwrap();
+        assert!(!autosave.status(SpaceId(0)).is_persisted());
+
+        std::thread::sleep(Duration::from_millis(60));
+        autosave.flush_if_due().unwrap();
+        assert!(autosave.status(SpaceId(0)).is_persisted());
+        let mut reopened = SqliteStorage::open(&path, Arc::default()).unwrap();
+        assert_eq!(reopened.load(SpaceId(0)).unwrap(), Some(space));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn versions_are_persisted_per_space() {
+        let storage = crate::storage::MemoryStorage::default();
+        let autosave = Autosave::new(
+            Box::new(storage),
+            None,
+            Duration::from_secs(1),
+            Caps::default(),
+        );
+        let mut first = SimpleAthensSpace::new();
+        first.set_version(3);
+        let mut second = SimpleAthensSpace::with_id(SpaceId(1));
+        second.set_version(1);
+        autosave.lock().update(first.clone());
+        autosave.flush().unwrap();
+        autosave.lock().update(second);
+        let status = |id| {
+            let status = autosave.status(SpaceId(id));
+            (status.version, status.persisted_version)
+        };
+        assert_eq!((status(0), status(1), status(2)), ((3, 3), (1, 0), (0, 0)));
+        first.set_version(4);
+        autosave.lock().update(first);
+        autosave.flush().unwrap();
+        assert_eq!((status(0), status(1)), ((4, 4), (1, 1)));
+    }
+
+    /// Storage whose saves wait to be let through, saying when one starts.
+    struct Gated {
+        started: std::sync::mpsc::Sender<()>,
+        release: std::sync::mpsc::Receiver<()>,
+    }
+
+    impl Storage for Gated {
+        fn load(&mut self, _: SpaceId) -> io::Result<Option<SimpleAthensSpace>> {
+            Ok(None)
+        }
+        fn save(&mut self, _: &SimpleAthensSpace) -> io::Result<()> {
+            self.started.send(()).unwrap();
+            self.release.recv().unwrap();
+            Ok(())
+        }
+        fn list(&mut self) -> io::Result<Vec<SpaceId>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    fn flush_writes_without_the_store_locked() {
+        let (started, started_rx) = std::sync::mpsc::channel();
+        let (release_tx, release) = std::sync::mpsc::channel();
+        let storage = Box::new(Gated { started, release });
+        let autosave = Autosave::new(storage, None, Duration::from_secs(1), Caps::default());
+        let mut space = SimpleAthensSpace::new();
+        space.set_version(1);
+        autosave.lock().update(space.clone());
+        let flushing = autosave.clone();
+        let flush = std::thread::spawn(move || flushing.flush());
+
+        started_rx.recv().unwrap();
+        space.set_version(2);
+        autosave.store.try_lock().unwrap().update(space);
+        release_tx.send(()).unwrap();
+        flush.join().unwrap().unwrap();
+        let status = autosave.status(SpaceId(0));
+        assert_eq!((status.version, status.persisted_version), (2, 1));
+    }
+
+    #[test]
+    fn updates_are_broadcast_and_kept() {
+        let storage = crate::storage::MemoryStorage::default();
+        let autosave = Autosave::new(
+            Box::new(storage),
+            None,
+            Duration::from_secs(1),
+            Caps::default(),
+        );
+        let (missed, mut receiver) = autosave.subscribe(SpaceId(0), None);
+        assert_eq!(missed, vec![]);
+        let mut space = SimpleAthensSpace::new();
+        for version in 1..=3 {
+            space.set_version(version);
+            autosave.lock().update(space.clone());
+        }
+        let (space_id, update) = receiver.try_recv().unwrap();
+        assert_eq!(space_id, SpaceId(0));
+        assert_eq!(update.version, 1);
+
+        let (missed, _) = autosave.subscribe(SpaceId(0), Some(1));
+        let versions: Vec<u64> = missed.iter().map(|u| u.version).collect();
+        assert_eq!(versions, vec![2, 3]);
+        assert_eq!(autosave.subscribe(SpaceId(1), Some(0)).0, vec![]);
+    }
+}
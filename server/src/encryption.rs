@@ -0,0 +1,223 @@
+//! Optional encryption of spaces at rest.
+//!
+//! With `encryption_key` (or `encryption_key_file`) set to the base64 of 32
+//! random bytes, spaces are stored as `enc1:` followed by the base64 of a
+//! random nonce and the XChaCha20-Poly1305 ciphertext of the stored document.
+//! The space's id is authenticated along with it, so a document cannot pass
+//! for another space's. Both can also be set through the environment, as
+//! `ROCKET_ENCRYPTION_KEY` for example.
+//!
+//! To rotate keys, move the old key into `old_encryption_keys` and set a new
+//! `encryption_key`. Documents under old keys are still read and are
+//! re-encrypted under the new key at startup. Plaintext documents are only
+//! read by that startup migration, so once a key is set nobody can slip a
+//! plaintext document into storage. The plaintext `data.txt` of servers
+//! from before storage is removed once its space is stored encrypted.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use model::{SimpleAthensSpace, SpaceId};
+use rocket::figment::Figment;
+use std::io::{self, ErrorKind};
+
+const PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 24;
+
+/// Turns spaces into stored documents and back.
+#[derive(Default)]
+pub struct Codec {
+    // The first key encrypts, all of them decrypt.
+    keys: Vec<XChaCha20Poly1305>,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+/// What is authenticated along with a space's document.
+fn associated_data(id: SpaceId) -> Vec<u8> {
+    format!("space-{}", id.0).into_bytes()
+}
+
+fn parse_key(key: &str) -> io::Result<XChaCha20Poly1305> {
+    let bytes = BASE64
+        .decode(key.trim())
+        .map_err(|e| invalid(format!("Bad encryption key: {}", e)))?;
+    XChaCha20Poly1305::new_from_slice(&bytes)
+        .map_err(|_| invalid("Encryption keys must be 32 bytes"))
+}
+
+impl Codec {
+    /// `keys` are base64, the first one encrypts.
+    pub fn new<'a>(keys: impl IntoIterator<Item = &'a str>) -> io::Result<Self> {
+        let keys = keys.into_iter().map(parse_key).collect::<io::Result<_>>()?;
+        Ok(Self { keys })
+    }
+
+    pub fn from_config(figment: &Figment) -> io::Result<Self> {
+        let key: Option<String> = match figment.extract_inner::<String>("encryption_key_file") {
+            Ok(path) => Some(std::fs::read_to_string(path)?),
+            Err(_) => figment.extract_inner("encryption_key").ok(),
+        };
+        let old: Vec<String> = figment
+            .extract_inner("old_encryption_keys")
+            .unwrap_or_default();
+        match key {
+            Some(key) => Self::new(std::iter::once(&key).chain(old.iter()).map(|k| k.as_str())),
+            None if old.is_empty() => Ok(Self::default()),
+            None => Err(invalid("old_encryption_keys requires an encryption_key")),
+        }
+    }
+
+    pub fn is_encrypting(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    pub fn encode(&self, space: &SimpleAthensSpace) -> String {
+        let stored = space.to_stored();
+        let cipher = match self.keys.first() {
+            Some(cipher) => cipher,
+            None => return stored,
+        };
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: stored.as_bytes(),
+            aad: &associated_data(space.id()),
+        };
+        let mut sealed = nonce.to_vec();
+        sealed.extend(cipher.encrypt(&nonce, payload).unwrap());
+        format!("{}{}", PREFIX, BASE64.encode(sealed))
+    }
+
+    /// Reads the document of space `id`, which must be encrypted if there
+    /// is a key.
+    pub fn decode(&self, id: SpaceId, document: &str) -> io::Result<SimpleAthensSpace> {
+        if self.is_encrypting() && !document.starts_with(PREFIX) {
+            return Err(invalid("Plaintext document where encryption is configured"));
+        }
+        self.decode_migrating(id, document)
+    }
+
+    /// Like `decode`, but also reads plaintext documents. Only for
+    /// re-encoding them at startup.
+    pub fn decode_migrating(&self, id: SpaceId, document: &str) -> io::Result<SimpleAthensSpace> {
+        let stored = match document.strip_prefix(PREFIX) {
+            Some(sealed) => self.open(id, sealed)?.1,
+            None => document.to_string(),
+        };
+        let space =
+            SimpleAthensSpace::from_stored(&stored).map_err(|e| invalid(format!("{:?}", e)))?;
+        if space.id() != id {
+            let message = format!("Document of space {} where {} belongs", space.id().0, id.0);
+            return Err(invalid(message));
+        }
+        Ok(space)
+    }
+
+    /// Whether the document of space `id` is as `encode` would write it now,
+    /// that is encrypted under the current key, or plaintext without a key.
+    pub fn is_current(&self, id: SpaceId, document: &str) -> bool {
+        match document.strip_prefix(PREFIX) {
+            Some(sealed) => matches!(self.open(id, sealed), Ok((0, _))),
+            None => !self.is_encrypting(),
+        }
+    }
+
+    /// Decrypts with whichever key fits, returning its index.
+    fn open(&self, id: SpaceId, sealed: &str) -> io::Result<(usize, String)> {
+        let sealed = BASE64
+            .decode(sealed)
+            .map_err(|e| invalid(format!("Bad encrypted document: {}", e)))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(invalid("Truncated encrypted document"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let aad = associated_data(id);
+        for (i, cipher) in self.keys.iter().enumerate() {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: &aad,
+            };
+            if let Ok(plaintext) = cipher.decrypt(XNonce::from_slice(nonce), payload) {
+                let stored = String::from_utf8(plaintext).map_err(|e| invalid(e.to_string()))?;
+                return Ok((i, stored));
+            }
+        }
+        Err(invalid(
+            "Encrypted document does not decrypt with any configured key",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+    const OTHER_KEY: &str = "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=";
+    const ID: SpaceId = SpaceId(0);
+
+    fn space() -> SimpleAthensSpace {
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "Secret roadmap item".to_string();
+        space
+    }
+
+    #[test]
+    fn round_trips_without_leaking_plaintext() {
+        let codec = Codec::new([KEY]).unwrap();
+        let document = codec.encode(&space());
+        assert!(!document.contains("Secret"));
+        assert!(codec.is_current(ID, &document));
+        assert_eq!(codec.decode(ID, &document).unwrap(), space());
+        // Fresh nonces, so equal spaces do not look equal on disk.
+        assert_ne!(codec.encode(&space()), document);
+    }
+
+    #[test]
+    fn tampering_and_wrong_keys_are_rejected() {
+        let document = Codec::new([KEY]).unwrap().encode(&space());
+        assert!(Codec::new([OTHER_KEY])
+            .unwrap()
+            .decode(ID, &document)
+            .is_err());
+        assert!(Codec::default().decode(ID, &document).is_err());
+
+        let mut sealed = BASE64.decode(&document[PREFIX.len()..]).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        let tampered = format!("{}{}", PREFIX, BASE64.encode(sealed));
+        assert!(Codec::new([KEY]).unwrap().decode(ID, &tampered).is_err());
+    }
+
+    #[test]
+    fn documents_only_decode_as_their_own_space() {
+        let codec = Codec::new([KEY]).unwrap();
+        let document = codec.encode(&space());
+        assert!(codec.decode(SpaceId(1), &document).is_err());
+        assert!(!codec.is_current(SpaceId(1), &document));
+        let plaintext = Codec::default().encode(&space());
+        assert!(Codec::default().decode(SpaceId(1), &plaintext).is_err());
+    }
+
+    #[test]
+    fn rotated_keys_still_decrypt() {
+        let old = Codec::new([KEY]).unwrap().encode(&space());
+        let plaintext = Codec::default().encode(&space());
+        let rotated = Codec::new([OTHER_KEY, KEY]).unwrap();
+        assert_eq!(rotated.decode(ID, &old).unwrap(), space());
+        assert!(rotated.decode(ID, &plaintext).is_err());
+        assert_eq!(rotated.decode_migrating(ID, &plaintext).unwrap(), space());
+        assert!(!rotated.is_current(ID, &old));
+        assert!(!rotated.is_current(ID, &plaintext));
+        assert!(rotated.is_current(ID, &rotated.encode(&space())));
+    }
+
+    #[test]
+    fn keys_must_be_32_bytes() {
+        assert!(Codec::new(["c2hvcnQ="]).is_err());
+        assert!(Codec::new(["not base64!"]).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Crash safe storage for a space.
+//!
+//! Every save is appended to a journal as a checksummed record, so a crash
+//! mid-write can only lose the record being written. Every so often the
+//! latest record is written to a new snapshot and a new, empty, journal is
+//! started. On disk a generation `n` is the pair of files `snapshot-n` and
+//! `journal-n`. The snapshot holds a single record and may be missing for
+//! the very first generation. Snapshots are written to a temporary file and
+//! renamed into place, so a half-written snapshot is never observed.
+//!
+//! A record is `[payload length: u32 LE][crc32 of payload: u32 LE][payload]`.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER_LEN: usize = 8;
+
+pub struct Journal {
+    dir: PathBuf,
+    generation: u64,
+    file: File,
+    // Records appended since the last snapshot.
+    records: usize,
+    compact_every: usize,
+    latest: Option<String>,
+}
+
+impl Journal {
+    /// Opens or creates the journal in `dir`, recovering the latest valid
+    /// record. A torn or corrupt tail is cut off the journal.
+    pub fn open(dir: impl Into<PathBuf>, compact_every: usize) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let mut generations = list_generations(&dir)?;
+        generations.sort_unstable_by(|a, b| b.cmp(a));
+
+        // Use the newest generation with a readable snapshot. Generation 0
+        // never has one.
+        let mut generation = 0;
+        let mut latest = None;
+        for &g in generations.iter() {
+            if g == 0 {
+                break;
+            }
+            let path = dir.join(snapshot_name(g));
+            match std::fs::read(&path) {
+                Ok(bytes) => {
+                    if let Some((record, _)) = read_records(&bytes).pop() {
+                        generation = g;
+                        latest = Some(record);
+                        break;
+                    }
+                    log::error!("Ignoring corrupt snapshot {:?}", path);
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        // Older generations are superseded.
+        for &g in generations.iter().filter(|&&g| g < generation) {
+            remove_if_exists(&dir.join(snapshot_name(g)))?;
+            remove_if_exists(&dir.join(journal_name(g)))?;
+        }
+
+        let path = dir.join(journal_name(generation));
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let records = read_records(&bytes);
+        let valid_len = records.last().map_or(0, |&(_, end)| end);
+        if valid_len < bytes.len() {
+            log::error!(
+                "Truncating {} bytes of torn or corrupt records from {:?}",
+                bytes.len() - valid_len,
+                path
+            );
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        let count = records.len();
+        if let Some((record, _)) = records.into_iter().last() {
+            latest = Some(record);
+        }
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            dir,
+            generation,
+            file,
+            records: count,
+            compact_every: compact_every.max(1),
+            latest,
+        })
+    }
+
+    /// The most recently appended record.
+    pub fn latest(&self) -> Option<&str> {
+        self.latest.as_deref()
+    }
+
+    /// Durably appends a record, compacting if the journal got long. The
+    /// record is saved even if compacting fails, it is retried next time.
+    pub fn append(&mut self, payload: &str) -> io::Result<()> {
+        self.file.write_all(&encode_record(payload))?;
+        self.file.sync_data()?;
+        self.latest = Some(payload.to_string());
+        self.records += 1;
+        if self.records >= self.compact_every {
+            if let Err(e) = self.compact() {
+                log::error!("Failed to compact the journal in {:?}: {}", self.dir, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the latest record to a new snapshot and starts a new journal.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => return Ok(()),
+        };
+        let next = self.generation + 1;
+        write_atomic(&self.dir.join(snapshot_name(next)), &encode_record(latest))?;
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(self.dir.join(journal_name(next)))?;
+        sync_dir(&self.dir)?;
+        // The new generation is durable, the old one can go. If it cannot,
+        // it is superseded anyway and removed by the next `open`.
+        let old = std::mem::replace(&mut self.generation, next);
+        self.file = file;
+        self.records = 0;
+        remove_if_exists(&self.dir.join(snapshot_name(old)))?;
+        remove_if_exists(&self.dir.join(journal_name(old)))
+    }
+}
+
+fn snapshot_name(generation: u64) -> String {
+    format!("snapshot-{:08}", generation)
+}
+
+fn journal_name(generation: u64) -> String {
+    format!("journal-{:08}", generation)
+}
+
+fn list_generations(dir: &Path) -> io::Result<Vec<u64>> {
+    let mut generations = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        let number = name
+            .strip_prefix("snapshot-")
+            .or_else(|| name.strip_prefix("journal-"));
+        if let Some(g) = number.and_then(|n| n.parse().ok()) {
+            generations.push(g);
+        }
+    }
+    generations.sort_unstable();
+    generations.dedup();
+    Ok(generations)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces the file at `path` with `contents` such that readers see either
+/// the old or the new contents, even across crashes.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    sync_dir(path.parent().unwrap_or_else(|| Path::new(".")))
+}
+
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    // Directories can only be opened for syncing on unix.
+    if cfg!(unix) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn encode_record(payload: &str) -> Vec<u8> {
+    let payload = payload.as_bytes();
+    let mut record = Vec::with_capacity(HEADER_LEN + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// Decodes records up to the first torn or corrupt one. Returns each record
+/// with the offset just past its end.
+fn read_records(mut bytes: &[u8]) -> Vec<(String, usize)> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while bytes.len() >= HEADER_LEN {
+        let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+        let payload = match bytes[HEADER_LEN..].get(..len) {
+            Some(payload) if crc32fast::hash(payload) == crc => payload,
+            _ => break,
+        };
+        let payload = match std::str::from_utf8(payload) {
+            Ok(payload) => payload.to_string(),
+            Err(_) => break,
+        };
+        offset += HEADER_LEN + len;
+        records.push((payload, offset));
+        bytes = &bytes[HEADER_LEN + len..];
+    }
+    records
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("athens-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reopening_recovers_latest_record() {
+        let dir = temp_dir("journal-reopen");
+        let mut j = Journal::open(&dir, 100).unwrap();
+        assert_eq!(j.latest(), None);
+        j.append("one").unwrap();
+        j.append("two").unwrap();
+        drop(j);
+        let j = Journal::open(&dir, 100).unwrap();
+        assert_eq!(j.latest(), Some("two"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_record_is_truncated() {
+        let dir = temp_dir("journal-torn");
+        let mut j = Journal::open(&dir, 100).unwrap();
+        j.append("good").unwrap();
+        drop(j);
+        let path = dir.join(journal_name(0));
+        let good_len = std::fs::metadata(&path).unwrap().len();
+        let mut torn = encode_record("torn");
+        torn.truncate(torn.len() - 2);
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&torn)
+            .unwrap();
+
+        let mut j = Journal::open(&dir, 100).unwrap();
+        assert_eq!(j.latest(), Some("good"));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+        j.append("after").unwrap();
+        drop(j);
+        assert_eq!(Journal::open(&dir, 100).unwrap().latest(), Some("after"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_record_stops_recovery() {
+        let record = encode_record("payload");
+        let mut bytes = encode_record("first");
+        bytes.extend_from_slice(&record);
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let records = read_records(&bytes);
+        assert_eq!(records, vec![("first".to_string(), HEADER_LEN + 5)]);
+    }
+
+    #[test]
+    fn compaction_starts_new_generation() {
+        let dir = temp_dir("journal-compact");
+        let mut j = Journal::open(&dir, 2).unwrap();
+        for i in 0..5 {
+            j.append(&i.to_string()).unwrap();
+        }
+        assert_eq!(j.generation, 2);
+        drop(j);
+        assert_eq!(list_generations(&dir).unwrap(), vec![2]);
+        assert_eq!(Journal::open(&dir, 2).unwrap().latest(), Some("4"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_compaction_keeps_the_record() {
+        let dir = temp_dir("journal-compact-failure");
+        let mut j = Journal::open(&dir, 1).unwrap();
+        // A directory in the way of the next snapshot.
+        std::fs::create_dir_all(dir.join(snapshot_name(1)).join("blocked")).unwrap();
+        j.append("saved").unwrap();
+        assert_eq!(j.generation, 0);
+        drop(j);
+        std::fs::remove_dir_all(dir.join(snapshot_name(1))).unwrap();
+        assert_eq!(Journal::open(&dir, 1).unwrap().latest(), Some("saved"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_snapshot_falls_back_to_previous_generation() {
+        let dir = temp_dir("journal-fallback");
+        let mut j = Journal::open(&dir, 100).unwrap();
+        j.append("kept").unwrap();
+        drop(j);
+        // A crash while writing the next snapshot.
+        std::fs::write(dir.join(snapshot_name(1)), b"garbage").unwrap();
+        let j = Journal::open(&dir, 100).unwrap();
+        assert_eq!(j.generation, 0);
+        assert_eq!(j.latest(), Some("kept"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
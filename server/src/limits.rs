@@ -0,0 +1,351 @@
+//! Protection against clients sending too much: body size limits, a rate
+//! limit on changes and caps on the size of spaces.
+//!
+//! Bodies are limited by Rocket's `limits`, which default to sizes fitting
+//! whole spaces. Every change is throttled per client address, refilling
+//! `mutations_per_minute` over a minute, and refused with 429 and a
+//! `Retry-After` once used up. The address is the connection's, or the
+//! `X-Real-IP` header's when `behind_proxy` is set, as anyone may send the
+//! header. Spaces may not grow past `max_tasks` tasks or `max_users` users,
+//! larger changes are refused with 413. There may be at most `max_spaces`
+//! spaces, new ones are refused with 507 as if the server was out of room.
+
+use model::SimpleAthensSpace;
+use rocket::data::{self, Data, FromData, ToByteUnit};
+use rocket::figment::Figment;
+use rocket::http::{Header, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::net::{IpAddr, Ipv4Addr};
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Body limits unless configured otherwise, in bytes. Whole spaces are
+/// posted as strings.
+pub const BODY_LIMITS: [(&str, u64); 2] = [("string", 2 << 20), ("json", 1 << 20)];
+
+/// Beyond this many clients, the one seen longest ago is forgotten.
+const REMEMBERED_CLIENTS: usize = 10_000;
+
+/// How many tasks and users a space may have, and how many spaces there may
+/// be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caps {
+    pub tasks: usize,
+    pub users: usize,
+    pub spaces: usize,
+}
+
+impl Default for Caps {
+    fn default() -> Self {
+        Caps {
+            tasks: 10_000,
+            users: 1_000,
+            spaces: 1_000,
+        }
+    }
+}
+
+impl Caps {
+    pub fn from_config(figment: &Figment) -> Self {
+        let default = Caps::default();
+        Caps {
+            tasks: figment.extract_inner("max_tasks").unwrap_or(default.tasks),
+            users: figment.extract_inner("max_users").unwrap_or(default.users),
+            spaces: figment
+                .extract_inner("max_spaces")
+                .unwrap_or(default.spaces),
+        }
+    }
+
+    /// Whether `space` may replace `stored`. Spaces already over a lowered cap
+    /// may still change, they just may not grow.
+    pub fn check(
+        &self,
+        stored: Option<&SimpleAthensSpace>,
+        space: &SimpleAthensSpace,
+    ) -> Result<(), String> {
+        let counts = |s: &SimpleAthensSpace| (s.task_ids().count(), s.user_ids().count());
+        let (tasks, users) = counts(space);
+        let (stored_tasks, stored_users) = stored.map_or((0, 0), counts);
+        if tasks > self.tasks && tasks > stored_tasks {
+            return Err(format!("Spaces may have at most {} tasks", self.tasks));
+        }
+        if users > self.users && users > stored_users {
+            return Err(format!("Spaces may have at most {} users", self.users));
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket per client address. Each change takes a token, and a
+/// bucket refills at `per_minute` tokens a minute up to `per_minute`.
+pub struct RateLimit {
+    per_minute: u32,
+    /// Whether clients are told apart by the `X-Real-IP` header.
+    behind_proxy: bool,
+    remembered: usize,
+    clients: Mutex<Clients>,
+}
+
+/// The buckets, and the order their clients were last seen in.
+#[derive(Default)]
+struct Clients {
+    buckets: HashMap<IpAddr, Bucket>,
+    seen: BTreeMap<u64, IpAddr>,
+    next: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+    /// The client's key in `Clients::seen`.
+    seen: u64,
+}
+
+impl RateLimit {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimit {
+            per_minute,
+            behind_proxy: false,
+            remembered: REMEMBERED_CLIENTS,
+            clients: Mutex::new(Clients::default()),
+        }
+    }
+
+    pub fn from_config(figment: &Figment) -> Self {
+        RateLimit {
+            behind_proxy: figment.extract_inner("behind_proxy").unwrap_or(false),
+            ..RateLimit::new(figment.extract_inner("mutations_per_minute").unwrap_or(300))
+        }
+    }
+
+    /// Takes a token from the client's bucket, or says how many seconds
+    /// until there is one.
+    pub fn take(&self, client: IpAddr, now: Instant) -> Result<(), u64> {
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let mut clients = self.clients.lock().unwrap();
+        let Clients {
+            buckets,
+            seen,
+            next,
+        } = &mut *clients;
+        *next += 1;
+        match buckets.get(&client) {
+            Some(bucket) => {
+                seen.remove(&bucket.seen);
+            }
+            None if buckets.len() >= self.remembered => {
+                if let Some((_, oldest)) = seen.pop_first() {
+                    buckets.remove(&oldest);
+                }
+            }
+            None => {}
+        }
+        seen.insert(*next, client);
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            at: now,
+            seen: *next,
+        });
+        bucket.tokens = bucket.refilled(now, per_second).min(capacity);
+        bucket.at = now;
+        bucket.seen = *next;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if per_second > 0.0 {
+            Err(((1.0 - bucket.tokens) / per_second).ceil() as u64)
+        } else {
+            Err(60)
+        }
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant, per_second: f64) -> f64 {
+        self.tokens + now.duration_since(self.at).as_secs_f64() * per_second
+    }
+}
+
+/// A text body within the `string` limit. Unlike `&str`, which is 400 when
+/// cut off, longer bodies are 413.
+pub struct Text<'r>(&'r str);
+
+/// The body a `Text` borrows, kept with the request.
+struct Body(String);
+
+impl Deref for Text<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromData<'r> for Text<'r> {
+    type Error = std::io::Error;
+
+    async fn from_data(request: &'r Request<'_>, data: Data<'r>) -> data::Outcome<'r, Self> {
+        let limit = request.limits().get("string").unwrap_or(8.kibibytes());
+        match data.open(limit).into_string().await {
+            Ok(text) if text.is_complete() => {
+                let body = request.local_cache(|| Body(text.into_inner()));
+                data::Outcome::Success(Text(&body.0))
+            }
+            Ok(_) => {
+                let e = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "body too large");
+                data::Outcome::Failure((Status::PayloadTooLarge, e))
+            }
+            Err(e) => data::Outcome::Failure((Status::BadRequest, e)),
+        }
+    }
+}
+
+/// A guard for routes that change something, fails with 429 once the client
+/// sent too many changes.
+pub struct Throttled;
+
+/// Seconds until the throttled client may try again.
+struct RetryAfter(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Throttled {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let limit = match request.guard::<&State<RateLimit>>().await {
+            request::Outcome::Success(limit) => limit,
+            _ => return request::Outcome::Failure((Status::InternalServerError, ())),
+        };
+        let client = if limit.behind_proxy {
+            request.client_ip()
+        } else {
+            request.remote().map(|remote| remote.ip())
+        };
+        // Clients without an address, like local ones, share a bucket.
+        let client = client.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        match limit.take(client, Instant::now()) {
+            Ok(()) => request::Outcome::Success(Throttled),
+            Err(wait) => {
+                request.local_cache(|| RetryAfter(Some(wait)));
+                request::Outcome::Failure((Status::TooManyRequests, ()))
+            }
+        }
+    }
+}
+
+/// A plain text explanation of a refusal.
+pub struct Refusal {
+    status: Status,
+    message: String,
+    retry_after: Option<u64>,
+}
+
+impl<'r> Responder<'r, 'static> for Refusal {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .status(self.status)
+            .sized_body(self.message.len(), Cursor::new(self.message));
+        if let Some(wait) = self.retry_after {
+            response.header(Header::new("Retry-After", wait.to_string()));
+        }
+        Ok(response.finalize())
+    }
+}
+
+#[catch(413)]
+pub fn payload_too_large() -> Refusal {
+    Refusal {
+        status: Status::PayloadTooLarge,
+        message: "Too large: bodies are limited by the `limits` setting, \
+            spaces by `max_tasks` and `max_users`."
+            .to_string(),
+        retry_after: None,
+    }
+}
+
+#[catch(507)]
+pub fn insufficient_storage() -> Refusal {
+    Refusal {
+        status: Status::InsufficientStorage,
+        message: "No room for more spaces, their number is limited by `max_spaces`.".to_string(),
+        retry_after: None,
+    }
+}
+
+#[catch(429)]
+pub fn too_many_requests(request: &Request) -> Refusal {
+    let wait = request.local_cache(|| RetryAfter(None)).0.unwrap_or(60);
+    Refusal {
+        status: Status::TooManyRequests,
+        message: format!("Too many changes, try again in {} seconds.", wait),
+        retry_after: Some(wait),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_refill_per_client() {
+        let limit = RateLimit::new(2);
+        let (ada, bob) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+        assert_eq!(limit.take(ada, start), Ok(()));
+        assert_eq!(limit.take(ada, start), Ok(()));
+        assert_eq!(limit.take(ada, start), Err(30));
+        assert_eq!(limit.take(bob, start), Ok(()));
+        let later = limit.take(ada, start + Duration::from_secs(20));
+        assert!(matches!(later, Err(10..=11)), "{:?}", later);
+        assert_eq!(limit.take(ada, start + Duration::from_secs(30)), Ok(()));
+    }
+
+    #[test]
+    fn only_the_clients_seen_longest_ago_are_forgotten() {
+        let mut limit = RateLimit::new(1);
+        limit.remembered = 2;
+        let [ada, bob, cy]: [IpAddr; 3] =
+            ["10.0.0.1", "10.0.0.2", "10.0.0.3"].map(|a| a.parse().unwrap());
+        let now = Instant::now();
+        assert_eq!(limit.take(ada, now), Ok(()));
+        assert_eq!(limit.take(bob, now), Ok(()));
+        assert!(limit.take(ada, now).is_err());
+        // Bob was seen longest ago, so a new client pushes him out.
+        assert_eq!(limit.take(cy, now), Ok(()));
+        assert_eq!(limit.clients.lock().unwrap().buckets.len(), 2);
+        assert!(limit.take(ada, now).is_err());
+        assert!(limit.take(cy, now).is_err());
+        assert_eq!(limit.take(bob, now), Ok(()));
+    }
+
+    #[test]
+    fn caps_stop_spaces_growing() {
+        let caps = Caps {
+            tasks: 1,
+            users: 1,
+            spaces: 1,
+        };
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_user();
+        assert_eq!(caps.check(None, &space), Ok(()));
+        let stored = space.clone();
+        space.new_task();
+        assert!(caps.check(Some(&stored), &space).is_err());
+        // Lowered caps do not freeze larger spaces.
+        assert_eq!(caps.check(Some(&space), &space), Ok(()));
+        let mut grown = stored.clone();
+        grown.new_user();
+        assert!(caps.check(Some(&stored), &grown).is_err());
+    }
+}
@@ -1,43 +1,61 @@
-#![feature(linked_list_cursors)]
-
 #[macro_use]
 extern crate rocket;
 
+use model::api::{
+    AccountInfo, Credentials, Member, Operation, PasswordChange, Role, SaveStatus, SnapshotInfo,
+    Update,
+};
+use model::interchange::{self, Column, Format, Imported};
+use model::issues;
+use model::{AthensSpace, OrderedTasks, RankPoint, SimpleAthensSpace, SpaceId, TaskId, Timestamp};
+use model::{ParallelSimpleAthensSpace, Task, User, UserId};
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Env, Format as _, Serialized, Toml};
+use rocket::figment::{Figment, Profile};
 use rocket::fs::FileServer;
+use rocket::http::{ContentType, CookieJar, Header, Status};
+use rocket::request::{self, FromRequest, Request};
 use rocket::response::content::RawHtml;
-use rocket::State;
-use std::sync::Arc;
-use std::sync::Mutex;
+use rocket::response::status::Created;
+use rocket::response::stream::{Event, EventStream};
+use rocket::serde::json::Json;
+use rocket::tokio::select;
+use rocket::{Shutdown, State};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
-// TODO: use a persistent database or something.
+mod accounts;
+mod autosave;
+mod encryption;
+mod journal;
+mod limits;
+mod openapi;
+mod orderings;
+// Public so the `uri!` macros Rocket generates for its routes count as used.
+pub mod rest;
+mod snapshots;
+mod storage;
+use accounts::{Accounts, Caller, Session};
+use autosave::{Autosave, Store};
+use encryption::Codec;
+use limits::{Caps, RateLimit, Text, Throttled};
+use snapshots::Snapshots;
 
-// GET  /space/{spaceid} -> Exists or Nah
-// POST /space/{spaceid} -> Create space
-//
-// GET  /space/{spaceid}/user/{userid} -> User { alias, weight }
-// POST /space/{spaceid}/user/{userid} -> update username
-//
-// GET  /space/{spaceid}/task/{taskid} -> Task { text, lifecycle }
-// POST /space/{spaceid}/task/{taskid} -> update task
-//
-// -- If the taskid is not in the list, it is prepended in descending order
-// GET  /space/{spaceid}/importance/{userid} -> Vec<TaskId>;
-// POST /space/{spaceid}/importance/{userid} -> Vec<TaskId>;
+// The routes for any space are in `rest`. Still planned:
 //
 // GET  /space/{spaceid}/blocking/{userid} -> Vec<(TaskId, TaskId)>
 // POST /space/{spaceid}/blocking/{userid} -> Vec<(TaskId, TaskId)>
-//
-// GET  /space -> list of spaces (probably not the best idea for security)
-// GET  /space/{spaceid}/task -> Vec<TaskId>
-// GET  /space/{spaceid}/importance -> Aggregated importance ordering
-// GET  /space/{spaceid}/easiness -> Aggregated easiness ordering
-// GET  /space/{spaceid}/final -> Aggregated ordering considering everything.
 
-//
-//
-type Data = Arc<Mutex<String>>;
+type Data = Arc<Autosave>;
 
-const STORAGE: &str = "data.txt";
+/// Where spaces were stored before `Storage`, imported on first start.
+const LEGACY_STORAGE: &str = "data.txt";
+
+/// The space served by `/tasks`.
+const DEFAULT_SPACE: SpaceId = SpaceId(0);
 
 #[get("/")]
 fn index() -> RawHtml<&'static str> {
@@ -53,32 +71,1516 @@ fn index() -> RawHtml<&'static str> {
     )
 }
 
+/// Describes the routes, see `openapi`.
+#[get("/openapi.json")]
+fn openapi_document(document: &State<openapi::Document>) -> Json<&serde_json::Value> {
+    Json(&document.0)
+}
+
+fn now() -> Timestamp {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Timestamp(secs)
+}
+
+fn parse_time(time: Option<&str>) -> Result<Timestamp, Status> {
+    time.map_or(Ok(now()), |t| {
+        t.parse().map_err(|e| {
+            log::error!("Bad time: {}", e);
+            Status::BadRequest
+        })
+    })
+}
+
+fn parse_space(data: &str) -> Option<SimpleAthensSpace> {
+    SimpleAthensSpace::from_stored(data)
+        .map_err(|e| log::error!("Failed to parse stored tasks: {:?}", e))
+        .ok()
+}
+
+fn load_space(db: &State<Data>) -> Option<SimpleAthensSpace> {
+    db.lock().current().cloned()
+}
+
+#[derive(Responder)]
+enum SaveError {
+    #[response(status = 400)]
+    BadRequest(String),
+    /// The client did not load the latest version, carries the current space.
+    #[response(status = 409, content_type = "json")]
+    Conflict(String),
+    /// The caller's role does not allow the changes.
+    Denied(Status),
+    /// The space has more tasks or users than allowed.
+    #[response(status = 413)]
+    TooLarge(String),
+}
+
+/// The role needed to turn `stored` into `space`, apart from ballots: owners
+/// manage users and their weights, members edit tasks.
+fn needed_role(stored: &SimpleAthensSpace, space: &SimpleAthensSpace) -> Role {
+    let users = |s: &SimpleAthensSpace| -> Vec<User> {
+        s.user_ids().map(|u| s.user(u).user().clone()).collect()
+    };
+    let tasks =
+        |s: &SimpleAthensSpace| -> Vec<Task> { s.task_ids().map(|t| s.task(t).clone()).collect() };
+    if users(stored) != users(space) || stored.alias() != space.alias() {
+        Role::Owner
+    } else if tasks(stored) != tasks(space) {
+        Role::Member
+    } else {
+        Role::Viewer
+    }
+}
+
+/// Accepts the space if it was based on the latest version. It is written to
+/// disk by the autosaver.
 #[post("/tasks", format = "application/json", data = "<tasks>")]
-fn save_tasks(db: &State<Data>, tasks: &str) {
-    *db.lock().unwrap() = tasks.to_string();
-    std::fs::write(STORAGE, tasks).unwrap_or_else(|e| {
-        log::error!("Failed to save tasks: {:?}", e);
-    });
+fn save_tasks(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    tasks: Text<'_>,
+) -> Result<Json<SaveStatus>, SaveError> {
+    let mut space: SimpleAthensSpace = serde_json::from_str(&tasks).map_err(|e| {
+        log::error!("Failed to parse tasks: {:?}", e);
+        SaveError::BadRequest(e.to_string())
+    })?;
+    if space.id() != DEFAULT_SPACE {
+        let message = format!("/tasks only saves space {}", DEFAULT_SPACE.0);
+        return Err(SaveError::BadRequest(message));
+    }
+    space
+        .validate()
+        .map_err(|e| SaveError::BadRequest(e.to_string()))?;
+    let mut store = db.lock();
+    let stored = store.current();
+    let stored_version = stored.map_or(0, |s| s.version());
+    if space.version() != stored_version {
+        // Before the first save the current space is the empty one.
+        let current = stored.cloned().unwrap_or_default();
+        return Err(SaveError::Conflict(
+            serde_json::to_string(&current).unwrap(),
+        ));
+    }
+    let (role, voters) = match stored {
+        Some(stored) => (needed_role(stored, &space), stored.changed_voters(&space)),
+        None => (Role::Owner, Vec::new()),
+    };
+    caller
+        .require(DEFAULT_SPACE, role)
+        .and_then(|()| {
+            voters
+                .iter()
+                .try_for_each(|u| caller.may_vote(DEFAULT_SPACE, *u))
+        })
+        .map_err(SaveError::Denied)?;
+    store.check_caps(&space).map_err(SaveError::TooLarge)?;
+    space.set_version(stored_version + 1);
+    // History is kept by the server, clients cannot rewrite it.
+    space.set_history(stored.map(|s| s.history().clone()).unwrap_or_default());
+    space.record_ballots(now());
+    store.update(space);
+    drop(store);
+    Ok(Json(db.status(DEFAULT_SPACE)))
 }
 
 #[get("/tasks", format = "application/json")]
-fn get_tasks(db: &State<Data>) -> String {
-    let data = db.lock().unwrap().clone();
-    if data.is_empty() {
-        std::fs::read_to_string(STORAGE).unwrap_or_else(|e| {
-            log::error!("Failed to save tasks: {:?}", e);
-            String::new()
-        })
+fn get_tasks(db: &State<Data>, caller: Caller) -> Result<String, Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    let store = db.lock();
+    Ok(store
+        .current()
+        .map_or(String::new(), |s| serde_json::to_string(s).unwrap()))
+}
+
+/// Which versions of the default space are accepted and on disk. Waits up to
+/// 30 seconds for `wait_for` to be persisted, if given.
+#[get("/tasks/status?<wait_for>")]
+async fn save_status(
+    db: &State<Data>,
+    caller: Caller,
+    wait_for: Option<u64>,
+) -> Result<Json<SaveStatus>, Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    Ok(Json(match wait_for {
+        Some(version) => {
+            db.wait_for(DEFAULT_SPACE, version, Duration::from_secs(30))
+                .await
+        }
+        None => db.status(DEFAULT_SPACE),
+    }))
+}
+
+#[get("/history/importance?<at>")]
+fn importance_at(
+    db: &State<Data>,
+    caller: Caller,
+    at: Option<&str>,
+) -> Result<Json<OrderedTasks>, Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    let at = parse_time(at)?;
+    let space = load_space(db).ok_or(Status::NotFound)?;
+    Ok(Json(space.history().importance_at(at)))
+}
+
+#[get("/history/task/<task>?<from>&<to>")]
+fn task_history(
+    db: &State<Data>,
+    caller: Caller,
+    task: usize,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Json<Vec<RankPoint>>, Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    let from = from.map_or(Ok(Timestamp(0)), |f| parse_time(Some(f)))?;
+    let to = parse_time(to)?;
+    let space = load_space(db).ok_or(Status::NotFound)?;
+    let trajectory = space
+        .history()
+        .importance_trajectory(TaskId(task), from, to);
+    Ok(Json(trajectory))
+}
+
+/// A copy of the space with the id, 404 if there is none.
+fn read_space(db: &State<Data>, id: SpaceId) -> Result<ParallelSimpleAthensSpace, Status> {
+    let mut store = db.lock();
+    let space = store.space(id).map_err(internal_error)?;
+    let space = space.cloned().ok_or(Status::NotFound)?;
+    Ok(Arc::new(Mutex::new(space)))
+}
+
+/// Runs `f` on a copy of the space, returning the changed copy.
+fn edit<T>(
+    space: SimpleAthensSpace,
+    f: impl FnOnce(&dyn AthensSpace) -> T,
+) -> (SimpleAthensSpace, T) {
+    let space: ParallelSimpleAthensSpace = Arc::new(Mutex::new(space));
+    let result = f(&space);
+    (
+        Arc::try_unwrap(space).unwrap().into_inner().unwrap(),
+        result,
+    )
+}
+
+/// Makes an edited space the next version, like a save.
+fn next_version(mut space: SimpleAthensSpace) -> SimpleAthensSpace {
+    space.set_version(space.version() + 1);
+    space.record_ballots(now());
+    space
+}
+
+/// 413 if the edited space grew past the caps.
+fn check_caps(store: &Store, space: &SimpleAthensSpace) -> Result<(), Status> {
+    store.check_caps(space).map_err(|e| {
+        log::warn!("Rejected change to space {}: {}", space.id().0, e);
+        Status::PayloadTooLarge
+    })
+}
+
+/// Accepts an edited space as a new version. Returns the version.
+fn accept(store: &mut Store, space: SimpleAthensSpace) -> Result<u64, Status> {
+    check_caps(store, &space)?;
+    let space = next_version(space);
+    let version = space.version();
+    store.update(space);
+    Ok(version)
+}
+
+/// Applies `f` to the current space and accepts the result as a new version,
+/// unless it changed nothing.
+fn modify<T>(db: &State<Data>, f: impl FnOnce(&dyn AthensSpace) -> T) -> Result<T, Status> {
+    let mut store = db.lock();
+    let current = store.current().cloned().unwrap_or_default();
+    let (space, result) = edit(current.clone(), f);
+    if space != current {
+        accept(&mut store, space)?;
+    }
+    Ok(result)
+}
+
+/// Like `modify` for the space with the id, but the result is only accepted
+/// if `f` succeeds and changed something.
+fn modify_space<T>(
+    db: &State<Data>,
+    id: SpaceId,
+    f: impl FnOnce(&dyn AthensSpace) -> Result<T, Status>,
+) -> Result<T, Status> {
+    change(db, id, f).map(|(result, _)| result)
+}
+
+/// Checks that the caller's role allows the operation: members edit tasks,
+/// owners create users and rename others, and users move their own ballots
+/// and rename themselves.
+fn allow_operation(caller: &Caller, id: SpaceId, op: &Operation) -> Result<(), Status> {
+    match op {
+        Operation::CreateTask { .. } | Operation::SetTaskText { .. } => {
+            caller.require(id, Role::Member)
+        }
+        Operation::CreateUser { .. } => caller.require(id, Role::Owner),
+        Operation::RenameUser { user, .. } => caller
+            .may_vote(id, *user)
+            .or_else(|_| caller.require(id, Role::Owner)),
+        Operation::MoveImportance { user, .. } | Operation::MoveEasiness { user, .. } => {
+            caller.may_vote(id, *user)
+        }
+    }
+}
+
+/// Applies one operation to the latest version of the space, returning the
+/// version it made. Operations made against an older `base` version are 409
+/// if what they refer to was changed since, see `Operation::overtaken_by`,
+/// or if the versions since are not all known operations. A member creating
+/// their first user in the space becomes that user.
+fn apply_operation(
+    db: &State<Data>,
+    id: SpaceId,
+    op: &Operation,
+    base: Option<u64>,
+    caller: &Caller,
+) -> Result<u64, Status> {
+    allow_operation(caller, id, op)?;
+    let mut store = db.lock();
+    let current = latest(&mut store, id)?;
+    let creates = matches!(
+        op,
+        Operation::CreateTask { .. } | Operation::CreateUser { .. }
+    );
+    if let Some(base) = base.filter(|base| *base != current.version() && !creates) {
+        let since = store.ops_since(id, base).ok_or(Status::Conflict)?;
+        if since.into_iter().any(|other| op.overtaken_by(other)) {
+            return Err(Status::Conflict);
+        }
+    }
+    let (space, applied) = edit(current, |s| op.apply(s));
+    applied.ok_or(Status::NotFound)?;
+    check_caps(&store, &space)?;
+    if let (Operation::CreateUser { .. }, Some(_)) = (op, caller.account()) {
+        claim_new_user(caller, &space)?;
+    }
+    let space = next_version(space);
+    let version = space.version();
+    store.apply(space, op.clone());
+    Ok(version)
+}
+
+/// Binds the caller to the newest user of the space, unless they already are
+/// someone there or are not a member.
+fn claim_new_user(caller: &Caller, space: &SimpleAthensSpace) -> Result<(), Status> {
+    match (caller.user(space.id()), space.user_ids().last()) {
+        (None, Some(user)) if caller.is_member(space.id()) => caller.bind(space.id(), user),
+        _ => Ok(()),
+    }
+}
+
+/// A copy of the space to change.
+fn latest(store: &mut Store, id: SpaceId) -> Result<SimpleAthensSpace, Status> {
+    match store.space(id).map_err(internal_error)? {
+        Some(space) => Ok(space.clone()),
+        // Like with `/tasks`, the first change creates the default space.
+        None if id == DEFAULT_SPACE => Ok(SimpleAthensSpace::new()),
+        None => Err(Status::NotFound),
+    }
+}
+
+fn change<T>(
+    db: &State<Data>,
+    id: SpaceId,
+    f: impl FnOnce(&dyn AthensSpace) -> Result<T, Status>,
+) -> Result<(T, u64), Status> {
+    let mut store = db.lock();
+    let current = latest(&mut store, id)?;
+    let (space, result) = edit(current.clone(), f);
+    let result = result?;
+    if space == current {
+        return Ok((result, current.version()));
+    }
+    // The operations keep spaces valid, but weights are whatever was posted.
+    space.validate().map_err(|e| {
+        log::error!("Rejected change to space {}: {}", id.0, e);
+        Status::BadRequest
+    })?;
+    Ok((result, accept(&mut store, space)?))
+}
+
+/// Applies a single edit to the latest space, see `Operation`. `base` is the
+/// version the edit was made against.
+#[post("/tasks/op?<base>", format = "application/json", data = "<op>")]
+fn apply_op(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    base: Option<u64>,
+    op: Json<Operation>,
+) -> Result<Json<SaveStatus>, Status> {
+    let version = apply_operation(db, DEFAULT_SPACE, &op, base, &caller)?;
+    let persisted_version = db.status(DEFAULT_SPACE).persisted_version;
+    Ok(Json(SaveStatus {
+        version,
+        persisted_version,
+    }))
+}
+
+/// The `Last-Event-ID` an `EventSource` sends when it reconnects, the last
+/// version it was sent.
+struct LastEventId(Option<u64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Infallible> {
+        let id = request.headers().get_one("Last-Event-ID");
+        request::Outcome::Success(LastEventId(id.and_then(|id| id.parse().ok())))
+    }
+}
+
+/// Server-sent events with an `Update` for every new version of the space.
+/// Reconnecting clients are first sent the updates they missed, if the
+/// server still has them. Clients that see a gap in the versions reload.
+#[get("/tasks/events")]
+fn events(
+    db: &State<Data>,
+    caller: Caller,
+    last: LastEventId,
+    end: Shutdown,
+) -> Result<EventStream![], Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    Ok(updates(db, DEFAULT_SPACE, last, end))
+}
+
+fn updates(db: &State<Data>, id: SpaceId, last: LastEventId, mut end: Shutdown) -> EventStream![] {
+    let (missed, mut receiver) = db.subscribe(id, last.0);
+    let event = |update: &Update| Event::json(update).id(update.version.to_string());
+    EventStream! {
+        for update in missed.iter() {
+            yield event(update);
+        }
+        loop {
+            let update = select! {
+                update = receiver.recv() => match update {
+                    Ok((space, update)) if space == id => update,
+                    Ok(_) => continue,
+                    // Lagging clients reconnect and catch up.
+                    Err(_) => break,
+                },
+                _ = &mut end => break,
+            };
+            yield event(&update);
+        }
+    }
+}
+
+fn parse_format(format: &str) -> Result<Format, Status> {
+    format.parse().map_err(|_| Status::NotFound)
+}
+
+/// Downloads the tasks ranked by importance, either the consensus or one
+/// user's ballot, with their easiness rank as an extra CSV column.
+#[get("/export/<format>?<user>")]
+fn export_tasks(
+    db: &State<Data>,
+    caller: Caller,
+    format: &str,
+    user: Option<usize>,
+) -> Result<Download, Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    let space: ParallelSimpleAthensSpace = Arc::new(Mutex::new(load_space(db).unwrap_or_default()));
+    export(&space, format, user)
+}
+
+fn export(space: &dyn AthensSpace, format: &str, user: Option<usize>) -> Result<Download, Status> {
+    let format = parse_format(format)?;
+    let (importance, easiness) = match user.map(UserId) {
+        Some(user) if !space.users().contains(&user) => return Err(Status::NotFound),
+        Some(user) => (space.user_importance(user), space.user_easiness(user)),
+        None => (space.important_tasks(), space.easy_tasks()),
+    };
+    let easiness = Column {
+        name: "easiness".to_string(),
+        values: easiness
+            .iter()
+            .enumerate()
+            .map(|(rank, id)| (id, (rank + 1).to_string()))
+            .collect(),
+    };
+    let body = interchange::export(space, &importance, format, &[easiness]);
+    let content_type = match format {
+        Format::Csv => ContentType::CSV,
+        Format::Markdown => ContentType::new("text", "markdown"),
+        Format::TodoTxt => ContentType::Plain,
+    };
+    let filename = format!("athens.{}", format.extension());
+    Ok(Download::new(content_type, body, &filename))
+}
+
+/// Creates or updates a task for each open issue in an issue tracker's JSON
+/// export.
+#[post("/import/issues", data = "<input>")]
+fn import_issues(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    input: Text<'_>,
+) -> Result<Json<Imported>, Status> {
+    caller.require(DEFAULT_SPACE, Role::Member)?;
+    let issues = issues::parse(&input).map_err(|_| Status::BadRequest)?;
+    Ok(Json(modify(db, |space| issues::import(space, &issues))?))
+}
+
+/// The consensus ranking as an iCalendar feed of to-dos to subscribe to.
+#[get("/tasks.ics")]
+fn task_feed(db: &State<Data>, caller: Caller) -> Result<(ContentType, String), Status> {
+    caller.require(DEFAULT_SPACE, Role::Viewer)?;
+    let space: ParallelSimpleAthensSpace = Arc::new(Mutex::new(load_space(db).unwrap_or_default()));
+    Ok(ical_feed(&space))
+}
+
+fn ical_feed(space: &dyn AthensSpace) -> (ContentType, String) {
+    let calendar = ContentType::new("text", "calendar").with_params(("charset", "utf-8"));
+    (calendar, interchange::export_ical(space, now()))
+}
+
+/// Creates the tasks in the upload that are not already in the space.
+#[post("/import/<format>", data = "<input>")]
+fn import_tasks(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    format: &str,
+    input: Text<'_>,
+) -> Result<Json<Imported>, Status> {
+    caller.require(DEFAULT_SPACE, Role::Member)?;
+    let format = parse_format(format)?;
+    Ok(Json(modify(db, |space| {
+        interchange::import(space, &input, format)
+    })?))
+}
+
+/// Creates an account and logs in with it.
+#[post(
+    "/account/register",
+    format = "application/json",
+    data = "<credentials>"
+)]
+fn register(
+    accounts: &State<Arc<Accounts>>,
+    cookies: &CookieJar<'_>,
+    _throttled: Throttled,
+    credentials: Json<Credentials>,
+) -> Result<Created<Json<AccountInfo>>, Status> {
+    let info = accounts.register(&credentials)?;
+    Session::start(cookies, accounts, &info.name);
+    Ok(Created::new("/account").body(Json(info)))
+}
+
+#[post("/account/login", format = "application/json", data = "<credentials>")]
+fn login(
+    accounts: &State<Arc<Accounts>>,
+    cookies: &CookieJar<'_>,
+    _throttled: Throttled,
+    credentials: Json<Credentials>,
+) -> Result<Json<AccountInfo>, Status> {
+    let info = accounts.login(&credentials)?;
+    Session::start(cookies, accounts, &info.name);
+    Ok(Json(info))
+}
+
+#[post("/account/logout")]
+fn logout(accounts: &State<Arc<Accounts>>, cookies: &CookieJar<'_>) -> Status {
+    Session::end(cookies, accounts);
+    Status::NoContent
+}
+
+/// Changes the password, logging out every other session of the account.
+#[post("/account/password", format = "application/json", data = "<change>")]
+fn change_password(
+    accounts: &State<Arc<Accounts>>,
+    cookies: &CookieJar<'_>,
+    session: Session,
+    _throttled: Throttled,
+    change: Json<PasswordChange>,
+) -> Status {
+    match accounts.set_password(session.name(), &change) {
+        Ok(()) => {
+            Session::start(cookies, accounts, session.name());
+            Status::NoContent
+        }
+        Err(status) => status,
+    }
+}
+
+/// Joins the invite's space as a new user named `alias`, or after the
+/// account. Their ballots start out as the consensus.
+#[post("/invite/<token>?<alias>")]
+fn join(
+    db: &State<Data>,
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    token: &str,
+    alias: Option<String>,
+) -> Result<Created<Json<User>>, Status> {
+    let space = accounts.invited(session.name(), token, now())?;
+    let alias = alias.unwrap_or_else(|| session.name().to_string());
+    let mut store = db.lock();
+    let (joined, user) = edit(latest(&mut store, space)?, |s| {
+        let (importance, easiness) = (s.important_tasks(), s.easy_tasks());
+        let mut user = s.create_user();
+        user.alias = alias;
+        s.set_user(user.clone());
+        s.set_user_importance(user.id, importance);
+        s.set_user_easiness(user.id, easiness);
+        user
+    });
+    check_caps(&store, &joined)?;
+    // The invite is used up with the store still locked, so the user is only
+    // added once it surely is, and nobody joins as the same user.
+    accounts.redeem(session.name(), token, user.id, now())?;
+    accept(&mut store, joined)?;
+    drop(store);
+    let location = format!("/space/{}/user/{}", space.0, user.id.0);
+    Ok(Created::new(location).body(Json(user)))
+}
+
+/// The logged in account, 401 if there is none.
+#[get("/account")]
+fn account(session: Session) -> Result<Json<AccountInfo>, Status> {
+    session.info().map(Json).ok_or(Status::Unauthorized)
+}
+
+fn internal_error(e: std::io::Error) -> Status {
+    log::error!("{:?}", e);
+    Status::InternalServerError
+}
+
+/// Makes the account an owner of the space, for the configured `admins`. This
+/// is how spaces from before accounts get owners.
+#[post("/admin/space/<space>/owner/<account>")]
+fn make_owner(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    account: &str,
+) -> Result<Json<Member>, Status> {
+    accounts
+        .make_owner(session.name(), SpaceId(space), account)
+        .map(Json)
+}
+
+#[get("/admin/space/<space>/snapshots")]
+fn list_snapshots(
+    snapshots: &State<Arc<Snapshots>>,
+    caller: Caller,
+    space: usize,
+) -> Result<Json<Vec<SnapshotInfo>>, Status> {
+    caller.require_admin(SpaceId(space))?;
+    snapshots
+        .list(SpaceId(space))
+        .map(Json)
+        .map_err(internal_error)
+}
+
+/// Takes a snapshot of the space now.
+#[post("/admin/space/<space>/snapshots")]
+fn take_snapshot(
+    db: &State<Data>,
+    snapshots: &State<Arc<Snapshots>>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+) -> Result<Json<SnapshotInfo>, Status> {
+    caller.require_admin(SpaceId(space))?;
+    let space = db
+        .lock()
+        .space(SpaceId(space))
+        .map_err(internal_error)?
+        .cloned()
+        .ok_or(Status::NotFound)?;
+    snapshots
+        .take(&space, now())
+        .map(Json)
+        .map_err(internal_error)
+}
+
+#[derive(Responder)]
+struct Download {
+    body: (ContentType, String),
+    disposition: Header<'static>,
+}
+
+impl Download {
+    fn new(content_type: ContentType, body: String, filename: &str) -> Self {
+        let disposition = format!("attachment; filename=\"{}\"", filename);
+        Download {
+            body: (content_type, body),
+            disposition: Header::new("Content-Disposition", disposition),
+        }
+    }
+}
+
+#[get("/admin/space/<space>/snapshots/<at>")]
+fn download_snapshot(
+    snapshots: &State<Arc<Snapshots>>,
+    caller: Caller,
+    space: usize,
+    at: u64,
+) -> Result<Download, Status> {
+    caller.require_admin(SpaceId(space))?;
+    let body = snapshots
+        .read(SpaceId(space), Timestamp(at))
+        .map_err(internal_error)?
+        .ok_or(Status::NotFound)?
+        .to_stored();
+    let filename = format!("athens-space-{}-{}.json", space, at);
+    Ok(Download::new(ContentType::JSON, body, &filename))
+}
+
+/// Replaces the space with a snapshot. The replaced space is snapshotted
+/// first so the restore can itself be undone.
+#[post("/admin/space/<space>/snapshots/<at>/restore")]
+fn restore_snapshot(
+    db: &State<Data>,
+    snapshots: &State<Arc<Snapshots>>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    at: u64,
+) -> Result<Json<SaveStatus>, Status> {
+    let id = SpaceId(space);
+    caller.require_admin(id)?;
+    let mut space = snapshots
+        .read(id, Timestamp(at))
+        .map_err(internal_error)?
+        .ok_or(Status::NotFound)?;
+    let mut store = db.lock();
+    let current = store.space(id).map_err(internal_error)?;
+    if let Some(current) = current {
+        snapshots.take(current, now()).map_err(internal_error)?;
+    }
+    // Clients holding the replaced space must reload, and history carries on.
+    space.set_version(current.map_or(0, |s| s.version()) + 1);
+    space.set_history(current.map(|s| s.history().clone()).unwrap_or_default());
+    space.record_ballots(now());
+    store.update(space);
+    drop(store);
+    Ok(Json(db.status(id)))
+}
+
+/// The default space, imported from `legacy` if storage has none yet. With
+/// encryption the plaintext `legacy` file is removed once its space is in
+/// storage, so it does not stay readable on disk.
+fn load_default_space(
+    storage: &mut dyn storage::Storage,
+    encrypting: bool,
+    legacy: &Path,
+) -> std::io::Result<Option<SimpleAthensSpace>> {
+    let mut current = storage.load(DEFAULT_SPACE)?;
+    if current.is_none() {
+        let space = std::fs::read_to_string(legacy).ok();
+        if let Some(space) = space.as_deref().and_then(parse_space) {
+            log::info!("Importing {:?}", legacy);
+            storage.save(&space)?;
+            current = Some(space);
+        }
+    }
+    if encrypting && current.is_some() && legacy.exists() {
+        log::info!("Removing {:?}, its space is stored encrypted", legacy);
+        std::fs::remove_file(legacy)?;
+    }
+    Ok(current)
+}
+
+fn open_autosave(rocket: &rocket::Rocket<rocket::Build>, codec: Arc<Codec>) -> Data {
+    let figment = rocket.figment();
+    let mut storage = storage::open(figment, codec.clone()).expect("Failed to open storage");
+    if codec.is_encrypting() {
+        let count = storage.reencode().expect("Failed to encrypt storage");
+        log::info!("Encrypted {} spaces with the current key", count);
+    }
+    let current = load_default_space(
+        &mut *storage,
+        codec.is_encrypting(),
+        LEGACY_STORAGE.as_ref(),
+    )
+    .expect("Failed to load space");
+    let debounce = figment.extract_inner("autosave_ms").unwrap_or(2000);
+    let caps = Caps::from_config(figment);
+    Autosave::new(storage, current, Duration::from_millis(debounce), caps)
+}
+
+fn open_accounts(rocket: &rocket::Rocket<rocket::Build>) -> Arc<Accounts> {
+    let figment = rocket.figment();
+    let iterations = figment
+        .extract_inner("password_iterations")
+        .unwrap_or(100_000);
+    let days: u64 = figment.extract_inner("session_days").unwrap_or(30);
+    let lifetime = Duration::from_secs(days * 24 * 3600);
+    let admins: Vec<String> = figment.extract_inner("admins").unwrap_or_default();
+    let storage: String = figment.extract_inner("storage").unwrap_or_default();
+    let accounts = if storage == "memory" {
+        Accounts::in_memory(iterations)
     } else {
-        data
+        let path: String = figment
+            .extract_inner("accounts_path")
+            .unwrap_or_else(|_| "data/accounts.json".to_string());
+        Accounts::open(path, iterations).expect("Failed to open accounts")
+    };
+    Arc::new(accounts.with_session_lifetime(lifetime).with_admins(admins))
+}
+
+fn open_snapshots(rocket: &rocket::Rocket<rocket::Build>, codec: Arc<Codec>) -> Arc<Snapshots> {
+    let figment = rocket.figment();
+    let dir: String = figment
+        .extract_inner("snapshot_dir")
+        .unwrap_or_else(|_| "data/snapshots".to_string());
+    let keep_daily = figment.extract_inner("snapshot_keep_daily").unwrap_or(7);
+    let keep_weekly = figment.extract_inner("snapshot_keep_weekly").unwrap_or(8);
+    let snapshots = Snapshots::open(dir, keep_daily, keep_weekly, codec.clone())
+        .expect("Failed to open snapshots");
+    if codec.is_encrypting() {
+        let count = snapshots.reencode().expect("Failed to encrypt snapshots");
+        log::info!("Encrypted {} snapshots with the current key", count);
     }
+    Arc::new(snapshots)
+}
+
+/// Rocket's configuration, with `limits::BODY_LIMITS` in place of Rocket's
+/// own unless `Rocket.toml` or the environment set others.
+fn config() -> Figment {
+    let limits: BTreeMap<&str, u64> = limits::BODY_LIMITS.into_iter().collect();
+    Figment::from(rocket::Config::default())
+        .merge(Serialized::default("limits", limits))
+        .merge(Toml::file(Env::var_or("ROCKET_CONFIG", "Rocket.toml")).nested())
+        .merge(Env::prefixed("ROCKET_").ignore(&["PROFILE"]).global())
+        .select(Profile::from_env_or(
+            "ROCKET_PROFILE",
+            rocket::Config::DEFAULT_PROFILE,
+        ))
 }
 
 #[launch]
 fn build() -> rocket::Rocket<rocket::Build> {
-    let data = Data::default();
-    rocket::build()
-        .mount("/", routes![index, save_tasks, get_tasks])
+    app(rocket::custom(config()))
+}
+
+fn app(rocket: rocket::Rocket<rocket::Build>) -> rocket::Rocket<rocket::Build> {
+    let codec = Codec::from_config(rocket.figment()).expect("Bad encryption config");
+    let codec = Arc::new(codec);
+    let data = open_autosave(&rocket, codec.clone());
+    let snapshots = open_snapshots(&rocket, codec);
+    let accounts = open_accounts(&rocket);
+    let rate_limit = RateLimit::from_config(rocket.figment());
+    let snapshot_every = rocket
+        .figment()
+        .extract_inner("snapshot_every_mins")
+        .unwrap_or(60);
+    rocket
+        .mount(
+            "/",
+            routes![
+                index,
+                openapi_document,
+                save_tasks,
+                apply_op,
+                events,
+                get_tasks,
+                save_status,
+                importance_at,
+                task_history,
+                export_tasks,
+                task_feed,
+                import_tasks,
+                import_issues,
+                register,
+                login,
+                logout,
+                change_password,
+                account,
+                join,
+                make_owner,
+                list_snapshots,
+                take_snapshot,
+                download_snapshot,
+                restore_snapshot
+            ],
+        )
+        .mount("/space", rest::routes())
         .mount("/public", FileServer::from("./static"))
+        .register(
+            "/",
+            catchers![
+                limits::payload_too_large,
+                limits::too_many_requests,
+                limits::insufficient_storage
+            ],
+        )
+        .attach(AdHoc::on_ignite("OpenAPI", |rocket| async {
+            let document = openapi::document(rocket.routes());
+            rocket.manage(openapi::Document(document))
+        }))
+        .attach(AdHoc::on_liftoff("Autosave", |rocket| {
+            Box::pin(async move {
+                rocket.state::<Data>().unwrap().spawn();
+            })
+        }))
+        .attach(AdHoc::on_liftoff("Snapshots", move |rocket| {
+            Box::pin(async move {
+                let data = rocket.state::<Data>().unwrap().clone();
+                let every = Duration::from_secs(snapshot_every * 60);
+                rocket.state::<Arc<Snapshots>>().unwrap().spawn(data, every);
+            })
+        }))
+        .attach(AdHoc::on_shutdown("Flush autosave", |rocket| {
+            Box::pin(async move {
+                if let Err(e) = rocket.state::<Data>().unwrap().flush() {
+                    log::error!("Failed to save on shutdown: {:?}", e);
+                }
+            })
+        }))
         .manage(data)
+        .manage(snapshots)
+        .manage(accounts)
+        .manage(orderings::Orderings::default())
+        .manage(rate_limit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use accounts::SESSION_COOKIE;
+    use model::api::Invite;
+    use rocket::http::{ContentType, Cookie, Header};
+    use rocket::local::blocking::Client;
+
+    pub fn figment() -> rocket::figment::Figment {
+        let dir = std::env::temp_dir().join(format!("athens-server-{}", std::process::id()));
+        config()
+            .merge(("storage", "memory"))
+            .merge(("password_iterations", 10))
+            .merge(("snapshot_dir", dir))
+            .merge(("admins", ["ada"]))
+    }
+
+    pub fn client() -> Client {
+        Client::tracked(app(rocket::custom(figment()))).unwrap()
+    }
+
+    /// Logs in as `ada`, registering her, and makes her owner of the space.
+    pub fn own_space(client: &Client, space: SpaceId) {
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        let json = |uri: &str| client.post(uri.to_string()).header(ContentType::JSON);
+        if json("/account/register").body(ada).dispatch().status() != Status::Created {
+            json("/account/login").body(ada).dispatch();
+        }
+        let owner = format!("/admin/space/{}/owner/ada", space.0);
+        assert_eq!(client.post(owner).dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn legacy_storage_is_removed_once_stored_encrypted() {
+        let dir = std::env::temp_dir().join(format!("athens-legacy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy = dir.join("data.txt");
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "Secret".to_string();
+        let import = |key: Option<&str>| {
+            std::fs::write(&legacy, space.to_stored()).unwrap();
+            let codec = Codec::new(key).unwrap();
+            let encrypting = codec.is_encrypting();
+            let mut storage = storage::SqliteStorage::open_in_memory(Arc::new(codec)).unwrap();
+            load_default_space(&mut storage, encrypting, &legacy).unwrap()
+        };
+        assert_eq!(import(None), Some(space.clone()));
+        assert!(legacy.exists());
+        let key = "YXRoZW5zLWxlZ2FjeS10ZXN0LWtleS0zMi1ieXRlcyE=";
+        assert_eq!(import(Some(key)), Some(space.clone()));
+        assert!(!legacy.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn save(client: &Client, space: &SimpleAthensSpace) -> Status {
+        client
+            .post("/tasks")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(space).unwrap())
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn saves_are_versioned() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let get = || {
+            let response = client.get("/tasks").header(ContentType::JSON).dispatch();
+            response.into_string().unwrap()
+        };
+        assert_eq!(get(), "");
+        let mut ahead = SimpleAthensSpace::new();
+        ahead.set_version(3);
+        let response = client
+            .post("/tasks")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&ahead).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let current: SimpleAthensSpace = response.into_json().unwrap();
+        assert_eq!(current, SimpleAthensSpace::new());
+
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "first".to_string();
+        assert_eq!(save(&client, &space), Status::Ok);
+        let saved: SimpleAthensSpace = serde_json::from_str(&get()).unwrap();
+        assert_eq!(saved.version(), 1);
+        assert_eq!(saved.task(TaskId(0)).text, "first");
+
+        // Still based on version 0.
+        space.new_task();
+        assert_eq!(save(&client, &space), Status::Conflict);
+    }
+
+    #[test]
+    fn operations_apply_to_the_latest_space() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let apply = |op: &str| {
+            let request = client.post("/tasks/op").header(ContentType::JSON);
+            request.body(op).dispatch()
+        };
+        let status: SaveStatus = apply(r#"{"op": "create_task", "text": "first"}"#)
+            .into_json()
+            .unwrap();
+        assert_eq!(status.version, 1);
+        let op = r#"{"op": "set_task_text", "task": 0, "text": "First"}"#;
+        assert_eq!(apply(op).status(), Status::Ok);
+        let op = r#"{"op": "rename_user", "user": 0, "alias": "Ada"}"#;
+        assert_eq!(apply(op).status(), Status::NotFound);
+
+        let space: SimpleAthensSpace = client.get("/tasks").dispatch().into_json().unwrap();
+        assert_eq!(space.version(), 2);
+        assert_eq!(space.task(TaskId(0)).text, "First");
+
+        // Made against version 1, before the text was set.
+        let stale = |op: &str| {
+            let request = client.post("/tasks/op?base=1").header(ContentType::JSON);
+            request.body(op).dispatch().status()
+        };
+        let op = r#"{"op": "set_task_text", "task": 0, "text": "Mine"}"#;
+        assert_eq!(stale(op), Status::Conflict);
+        assert_eq!(
+            stale(r#"{"op": "create_task", "text": "second"}"#),
+            Status::Ok
+        );
+        let request = client.post("/tasks/op?base=3").header(ContentType::JSON);
+        assert_eq!(request.body(op).dispatch().status(), Status::Ok);
+        // Edits to other tasks since do not matter.
+        let op = r#"{"op": "set_task_text", "task": 1, "text": "Second"}"#;
+        let request = client.post("/tasks/op?base=3").header(ContentType::JSON);
+        assert_eq!(request.body(op).dispatch().status(), Status::Ok);
+        // A whole save since may have changed anything.
+        let space: SimpleAthensSpace = client.get("/tasks").dispatch().into_json().unwrap();
+        let body = serde_json::to_string(&space).unwrap();
+        let save = client.post("/tasks").header(ContentType::JSON).body(body);
+        assert_eq!(save.dispatch().status(), Status::Ok);
+        let op = r#"{"op": "set_task_text", "task": 1, "text": "Later"}"#;
+        let request = client.post("/tasks/op?base=5").header(ContentType::JSON);
+        assert_eq!(request.body(op).dispatch().status(), Status::Conflict);
+    }
+
+    #[test]
+    fn events_catch_up_from_the_last_version() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let apply = |op: &str| {
+            let request = client.post("/tasks/op").header(ContentType::JSON);
+            request.body(op).dispatch().status()
+        };
+        apply(r#"{"op": "create_task", "text": "first"}"#);
+        apply(r#"{"op": "create_user", "alias": "Ada"}"#);
+        client.post("/import/md").body("- [ ] second\n").dispatch();
+
+        // Ends the stream after the missed updates.
+        client.rocket().shutdown().notify();
+        let response = client
+            .get("/tasks/events")
+            .header(Header::new("Last-Event-ID", "1"))
+            .dispatch();
+        let events = response.into_string().unwrap();
+        let updates: Vec<Update> = events
+            .lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect();
+        let created = Operation::CreateUser {
+            alias: "Ada".to_string(),
+        };
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].version, 2);
+        assert_eq!(updates[0].op, Some(created));
+        assert_eq!(updates[1].version, 3);
+        assert_eq!(updates[1].op, None);
+        assert!(events.contains("id:3"), "{}", events);
+    }
+
+    #[test]
+    fn ballots_belong_to_their_users() {
+        let client = client();
+        let post = |uri: &str, body: &str| {
+            let request = client.post(uri).header(ContentType::JSON).body(body);
+            request.dispatch().status()
+        };
+        let moved = r#"{"op": "move_importance", "user": 0, "from": 0, "to": 1}"#;
+        let first = r#"{"op": "create_task", "text": "first"}"#;
+        assert_eq!(post("/tasks/op", first), Status::Unauthorized);
+        own_space(&client, DEFAULT_SPACE);
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        assert_eq!(post("/account/register", ada), Status::Conflict);
+        post("/tasks/op", first);
+        post("/tasks/op", r#"{"op": "create_task", "text": "second"}"#);
+        post("/tasks/op", r#"{"op": "create_user", "alias": "Ada"}"#);
+        let account: AccountInfo = client.get("/account").dispatch().into_json().unwrap();
+        assert_eq!(account.user(DEFAULT_SPACE), Some(UserId(0)));
+        assert_eq!(post("/tasks/op", moved), Status::Ok);
+        let mut space: SimpleAthensSpace = client.get("/tasks").dispatch().into_json().unwrap();
+
+        assert_eq!(post("/account/logout", ""), Status::NoContent);
+        assert_eq!(
+            client.get("/account").dispatch().status(),
+            Status::Unauthorized
+        );
+        assert_eq!(post("/tasks/op", moved), Status::Unauthorized);
+        assert_eq!(
+            client.get("/tasks").dispatch().status(),
+            Status::Unauthorized
+        );
+        space.mut_user(UserId(0)).unwrap().move_importance(0, 1);
+        let body = serde_json::to_string(&space).unwrap();
+        assert_eq!(post("/tasks", &body), Status::Unauthorized);
+
+        let bob = r#"{"name": "bob", "password": "battery staple"}"#;
+        post("/account/register", bob);
+        assert_eq!(post("/tasks", &body), Status::Forbidden);
+        let wrong = r#"{"name": "ada", "password": "wrong horse"}"#;
+        assert_eq!(post("/account/login", wrong), Status::Unauthorized);
+        assert_eq!(post("/account/login", ada), Status::Ok);
+        assert_eq!(post("/tasks", &body), Status::Ok);
+    }
+
+    #[test]
+    fn sessions_end_on_logout_and_password_change() {
+        let client = client();
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        let json = |uri: &'static str| client.post(uri).header(ContentType::JSON);
+        let register = json("/account/register").body(ada).dispatch();
+        let first = register.cookies().get(SESSION_COOKIE).unwrap().clone();
+        let account_with = |cookie: &Cookie<'static>| {
+            let request = client.get("/account").cookie(cookie.clone());
+            request.dispatch().status()
+        };
+        assert_eq!(account_with(&first), Status::Ok);
+        client.post("/account/logout").dispatch();
+        assert_eq!(account_with(&first), Status::Unauthorized);
+
+        let login = json("/account/login").body(ada).dispatch();
+        let second = login.cookies().get(SESSION_COOKIE).unwrap().clone();
+        let login = json("/account/login").body(ada).dispatch();
+        let third = login.cookies().get(SESSION_COOKIE).unwrap().clone();
+        let change = |body: &str| {
+            let request = json("/account/password").cookie(third.clone());
+            request.body(body).dispatch()
+        };
+        let wrong = r#"{"password": "wrong horse", "new_password": "battery staple"}"#;
+        assert_eq!(change(wrong).status(), Status::Unauthorized);
+        let right = r#"{"password": "correct horse", "new_password": "battery staple"}"#;
+        let changed = change(right);
+        assert_eq!(changed.status(), Status::NoContent);
+        let fourth = changed.cookies().get(SESSION_COOKIE).unwrap().clone();
+        assert_eq!(account_with(&second), Status::Unauthorized);
+        assert_eq!(account_with(&third), Status::Unauthorized);
+        assert_eq!(account_with(&fourth), Status::Ok);
+        let login = json("/account/login").body(ada).dispatch();
+        assert_eq!(login.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn joins_with_an_invite() {
+        let client = client();
+        let post = |uri: &str, body: &str| {
+            let request = client
+                .post(uri.to_string())
+                .header(ContentType::JSON)
+                .body(body);
+            request.dispatch()
+        };
+        let account =
+            |name: &str| format!(r#"{{"name": "{}", "password": "correct horse"}}"#, name);
+        own_space(&client, DEFAULT_SPACE);
+        post("/tasks/op", r#"{"op": "create_user", "alias": "Ada"}"#);
+        post("/tasks/op", r#"{"op": "create_task", "text": "first"}"#);
+        post("/tasks/op", r#"{"op": "create_task", "text": "second"}"#);
+        post(
+            "/tasks/op",
+            r#"{"op": "move_importance", "user": 0, "from": 0, "to": 1}"#,
+        );
+        let response = post("/space/0/invite?uses=1", "");
+        assert_eq!(response.status(), Status::Created);
+        let invite: Invite = response.into_json().unwrap();
+
+        post("/account/register", &account("bob"));
+        let status = post("/space/0/invite", "").status();
+        assert_eq!(status, Status::Forbidden);
+        let join = format!("/invite/{}?alias=Bob", invite.token);
+        let user: User = post(&join, "").into_json().unwrap();
+        assert_eq!((user.id, user.alias.as_str()), (UserId(1), "Bob"));
+        let consensus: OrderedTasks = client
+            .get("/space/0/importance")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        let ballot: OrderedTasks = client
+            .get("/space/0/importance/1")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(ballot, consensus);
+        assert_eq!(ballot, [TaskId(0), TaskId(1)]);
+
+        post("/account/register", &account("cy"));
+        assert_eq!(post(&join, "").status(), Status::Gone);
+        post("/account/login", &account("ada"));
+        let invites: Vec<Invite> = client
+            .get("/space/0/invite")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(invites[0].redemptions[0].account, "bob");
+        let revoke = format!("/space/0/invite/{}/revoke", invite.token);
+        assert_eq!(post(&revoke, "").status(), Status::Ok);
+    }
+
+    #[test]
+    fn joining_a_full_space_leaves_the_invite_unused() {
+        let client =
+            Client::tracked(app(rocket::custom(figment().merge(("max_users", 1))))).unwrap();
+        let post = |uri: &str, body: &str| {
+            let request = client.post(uri.to_string()).header(ContentType::JSON);
+            request.body(body).dispatch()
+        };
+        let account =
+            |name: &str| format!(r#"{{"name": "{}", "password": "correct horse"}}"#, name);
+        own_space(&client, DEFAULT_SPACE);
+        post("/tasks/op", r#"{"op": "create_user", "alias": "Ada"}"#);
+        let invite: Invite = post("/space/0/invite", "").into_json().unwrap();
+
+        post("/account/register", &account("bob"));
+        let join = format!("/invite/{}", invite.token);
+        assert_eq!(post(&join, "").status(), Status::PayloadTooLarge);
+        let bob: AccountInfo = client.get("/account").dispatch().into_json().unwrap();
+        assert_eq!(bob.spaces, vec![]);
+        post("/account/login", &account("ada"));
+        let invites: Vec<Invite> = client
+            .get("/space/0/invite")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(invites[0].redemptions, vec![]);
+    }
+
+    #[test]
+    fn rejects_invalid_spaces() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let post = |body: &str| {
+            let request = client.post("/tasks").header(ContentType::JSON).body(body);
+            let response = request.dispatch();
+            (response.status(), response.into_string().unwrap())
+        };
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_user();
+        let valid = serde_json::to_string(&space).unwrap();
+
+        let weighty = valid.replace(r#""weight":1"#, r#""weight":4000000000"#);
+        let (status, message) = post(&weighty);
+        assert_eq!(status, Status::BadRequest);
+        assert!(message.contains("weight"), "{}", message);
+        let unranked = valid.replace(r#""importance":[0]"#, r#""importance":[]"#);
+        assert_eq!(post(&unranked).0, Status::BadRequest);
+        let elsewhere = valid.replace(
+            r#""id":0,"alias":"My space""#,
+            r#""id":3,"alias":"My space""#,
+        );
+        assert_eq!(post(&elsewhere).0, Status::BadRequest);
+        assert_eq!(post("{}").0, Status::BadRequest);
+        assert_eq!(post(&valid).0, Status::Ok);
+    }
+
+    #[test]
+    fn reimports_issues() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let import = |title: &str| -> Imported {
+            let body = format!(
+                r#"[{{"number": 4, "html_url": "https://x/4", "title": "{}"}}]"#,
+                title
+            );
+            let response = client.post("/import/issues").body(body).dispatch();
+            response.into_json().unwrap()
+        };
+        assert_eq!(import("Old title").created, vec![TaskId(0)]);
+        assert_eq!(import("New title").updated, vec![TaskId(0)]);
+        let space: SimpleAthensSpace = client.get("/tasks").dispatch().into_json().unwrap();
+        assert_eq!(space.task(TaskId(0)).text, "New title");
+        // Nothing new is no new version.
+        import("New title");
+        let status: SaveStatus = client.get("/tasks/status").dispatch().into_json().unwrap();
+        assert_eq!(status.version, space.version());
+        assert_eq!(
+            client
+                .post("/import/issues")
+                .body("nope")
+                .dispatch()
+                .status(),
+            Status::BadRequest
+        );
+    }
+
+    #[test]
+    fn import_then_export() {
+        let client = client();
+        own_space(&client, DEFAULT_SPACE);
+        let imported: Imported = client
+            .post("/import/md")
+            .body("- [ ] first\n- [ ] second\n- [ ] first\n")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(imported.created, vec![TaskId(0), TaskId(1)]);
+        assert_eq!(imported.duplicates, vec!["first"]);
+
+        let response = client.get("/export/csv").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        let csv = response.into_string().unwrap();
+        assert_eq!(csv.lines().next(), Some("rank,id,text,easiness"));
+        assert_eq!(csv.lines().count(), 3);
+        assert_eq!(
+            client.get("/export/pdf").dispatch().status(),
+            Status::NotFound
+        );
+
+        let response = client.get("/tasks.ics").dispatch();
+        assert_eq!(
+            response.content_type().map(|c| c.to_string()),
+            Some("text/calendar; charset=utf-8".to_string())
+        );
+        let feed = response.into_string().unwrap();
+        assert!(feed.contains("UID:space-0-task-1@athens\r\nDTSTAMP:"));
+        assert_eq!(feed.matches("BEGIN:VTODO").count(), 2);
+    }
+
+    /// Sends a typed call to the local server, like `client::native` would.
+    fn call<T>(client: &Client, call: ::client::Call<T>) -> Result<T, ::client::Error> {
+        let method = match call.method {
+            ::client::Method::Get => rocket::http::Method::Get,
+            ::client::Method::Post => rocket::http::Method::Post,
+        };
+        let mut request = client.req(method, call.path.clone());
+        if let Some(body) = &call.body {
+            let content_type = ContentType::parse_flexible(body.content_type).unwrap();
+            request = request.header(content_type).body(&body.text);
+        }
+        let response = request.dispatch();
+        let status = response.status().code;
+        call.response(status, &response.into_string().unwrap_or_default())
+    }
+
+    #[test]
+    fn typed_client_calls_the_routes() {
+        let client = client();
+        let ada = Credentials {
+            name: "ada".to_string(),
+            password: "correct horse".to_string(),
+        };
+        let account = call(&client, ::client::register(&ada)).unwrap();
+        assert_eq!(account.name, "ada");
+        let space = ::client::space(SpaceId(6));
+        let info = call(&client, space.create(Some("Tools"))).unwrap();
+        assert_eq!(info.alias, "Tools");
+        let user = call(&client, space.create_user()).unwrap();
+        let mut task = call(&client, space.create_task()).unwrap();
+        task.text = "Write a client".to_string();
+        call(&client, space.set_task(&task)).unwrap();
+        call(&client, space.create_task()).unwrap();
+        let ballot = call(&client, space.set_user_importance(user.id, &[task.id]));
+        assert_eq!(ballot.unwrap(), [TaskId(1), TaskId(0)]);
+        let role = call(&client, space.role()).unwrap();
+        assert_eq!(role, Role::Owner);
+        let invite = call(
+            &client,
+            space.create_invite(None, Some(1), Some(Role::Voter)),
+        );
+        assert_eq!(invite.unwrap().role, Role::Voter);
+        let op = Operation::CreateTask {
+            text: "Typed".to_string(),
+        };
+        // The ballot was already so, it made no version.
+        assert_eq!(call(&client, space.apply(&op, 4)), Ok(5));
+        let csv = call(&client, space.export(Format::Csv, None)).unwrap();
+        assert!(csv.contains("Write a client"));
+
+        call(&client, ::client::logout()).unwrap();
+        let denied = call(&client, space.create_task()).unwrap_err();
+        assert_eq!(denied.status(), Some(401));
+        let denied = call(&client, ::client::load_tasks()).unwrap_err();
+        assert_eq!(denied.status(), Some(401));
+
+        let document: serde_json::Value =
+            client.get("/openapi.json").dispatch().into_json().unwrap();
+        assert_eq!(document["openapi"], "3.0.3");
+        let paths = document["paths"].as_object().unwrap();
+        assert!(paths.contains_key("/space/{space}/task/{task}"));
+    }
+
+    #[test]
+    fn large_and_frequent_changes_are_refused() {
+        let figment = figment()
+            .merge(("limits.string", 512))
+            .merge(("max_tasks", 2))
+            .merge(("mutations_per_minute", 6));
+        let client = Client::tracked(app(rocket::custom(figment))).unwrap();
+        own_space(&client, DEFAULT_SPACE);
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "x".repeat(1024);
+        let response = client
+            .post("/tasks")
+            .header(ContentType::JSON)
+            .body(serde_json::to_string(&space).unwrap())
+            .dispatch();
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        assert!(response.into_string().unwrap().contains("`limits`"));
+
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_task();
+        space.new_task();
+        assert_eq!(save(&client, &space), Status::PayloadTooLarge);
+        let mut space = SimpleAthensSpace::new();
+        space.new_task();
+        space.new_task();
+        assert_eq!(save(&client, &space), Status::Ok);
+        let op = Operation::CreateTask {
+            text: "third".to_string(),
+        };
+        let op = serde_json::to_string(&op).unwrap();
+        let apply = || {
+            let request = client.post("/tasks/op").header(ContentType::JSON);
+            request.body(&op).dispatch()
+        };
+        assert_eq!(apply().status(), Status::PayloadTooLarge);
+
+        // Six changes a minute were used up, two to log in and own the space.
+        let response = apply();
+        assert_eq!(response.status(), Status::TooManyRequests);
+        let wait: u64 = response
+            .headers()
+            .get_one("Retry-After")
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=15).contains(&wait));
+        assert_eq!(client.get("/tasks/status").dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_their_address() {
+        let throttled = |behind_proxy: bool| {
+            let figment = figment()
+                .merge(("mutations_per_minute", 1))
+                .merge(("behind_proxy", behind_proxy));
+            let client = Client::tracked(app(rocket::custom(figment))).unwrap();
+            let mut statuses = Vec::new();
+            for (remote, real_ip) in [("10.0.0.1:1", "10.0.1.1"), ("10.0.0.1:2", "10.0.1.2")] {
+                let response = client
+                    .post("/tasks/op")
+                    .remote(remote.parse().unwrap())
+                    .header(Header::new("X-Real-IP", real_ip))
+                    .header(ContentType::JSON)
+                    .body(r#"{"op": "create_task", "text": ""}"#)
+                    .dispatch();
+                statuses.push(response.status());
+            }
+            statuses
+        };
+        // Anonymous changes are refused after taking from the bucket.
+        let (refused, throttled_status) = (Status::Unauthorized, Status::TooManyRequests);
+        assert_eq!(throttled(false), [refused, throttled_status]);
+        assert_eq!(throttled(true), [refused, refused]);
+    }
+
+    #[test]
+    fn snapshots_are_per_space_and_for_owners() {
+        let dir =
+            std::env::temp_dir().join(format!("athens-route-snapshots-{}", std::process::id()));
+        let figment = figment().merge(("snapshot_dir", &dir));
+        let client = Client::tracked(app(rocket::custom(figment))).unwrap();
+        assert_eq!(
+            client.get("/admin/space/1/snapshots").dispatch().status(),
+            Status::Unauthorized
+        );
+        let bob = r#"{"name": "bob", "password": "correct horse"}"#;
+        let register = client.post("/account/register").header(ContentType::JSON);
+        register.body(bob).dispatch();
+        assert_eq!(client.post("/space/1").dispatch().status(), Status::Created);
+        let op = r#"{"op": "create_task", "text": "Bob's"}"#;
+        let request = client.post("/space/1/op").header(ContentType::JSON);
+        request.body(op).dispatch();
+        let response = client.post("/admin/space/1/snapshots").dispatch();
+        let taken: SnapshotInfo = response.into_json().unwrap();
+        let download = format!("/admin/space/1/snapshots/{}", taken.at.0);
+        let response = client.get(download.clone()).dispatch();
+        assert!(response.into_string().unwrap().contains("Bob's"));
+        assert_eq!(
+            client.get("/admin/space/0/snapshots").dispatch().status(),
+            Status::Forbidden
+        );
+
+        // Admins look after every space, and each space has its own snapshots.
+        own_space(&client, DEFAULT_SPACE);
+        let snapshots: Vec<SnapshotInfo> = client
+            .get("/admin/space/1/snapshots")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(snapshots, [taken]);
+        let snapshots: Vec<SnapshotInfo> = client
+            .get("/admin/space/0/snapshots")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(snapshots, []);
+        let restore = format!("{}/restore", download);
+        assert_eq!(client.post(restore).dispatch().status(), Status::Ok);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn spaces_are_capped() {
+        let figment = figment().merge(("max_spaces", 2));
+        let client = Client::tracked(app(rocket::custom(figment))).unwrap();
+        // Logs in, creating spaces needs an account.
+        own_space(&client, DEFAULT_SPACE);
+        for space in ["/space/1", "/space/2"] {
+            assert_eq!(client.post(space).dispatch().status(), Status::Created);
+        }
+        let response = client.post("/space/3").dispatch();
+        assert_eq!(response.status(), Status::InsufficientStorage);
+        assert!(response.into_string().unwrap().contains("`max_spaces`"));
+        assert_eq!(
+            client.get("/space").dispatch().into_json(),
+            Some(vec![1, 2])
+        );
+    }
 }
@@ -0,0 +1,806 @@
+//! The OpenAPI description of the server, served at `/openapi.json`.
+//!
+//! Paths, methods and parameters are read off the mounted routes. What each
+//! route takes and returns is listed in `ENDPOINTS`, and the schemas of those
+//! `model` types are inferred from examples serialized with serde. Which
+//! fields are required or nullable is asked of each type's `Deserialize`,
+//! the rest is only as good as the example: values nested in a field are
+//! described as they are in it. Every route has to be listed and every field
+//! needs a value in its example, which the tests check.
+
+use model::api::{
+    AccountInfo, Credentials, Invite, Member, Membership, Operation, PasswordChange, Redemption,
+    Role, SaveStatus, SnapshotInfo, SpaceInfo, Update,
+};
+use model::interchange::Imported;
+use model::{ExternalRef, OrderedTasks, RankPoint, SimpleAthensSpace, SpaceId, Task, TaskId};
+use model::{Timestamp, User, UserId};
+use rocket::http::Method;
+use rocket::Route;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+/// What a route takes or returns.
+#[derive(Debug, Clone, Copy)]
+enum Body {
+    None,
+    /// JSON of a type in `schemas`, `[Type]` for a list of them.
+    Json(&'static str),
+    /// Anything of the media type.
+    Text(&'static str),
+    /// Server-sent events, each an `Update`.
+    Events,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Endpoint {
+    method: Method,
+    path: &'static str,
+    summary: &'static str,
+    request: Body,
+    status: u16,
+    response: Body,
+    /// Query parameters the route cannot do without.
+    required: &'static [&'static str],
+}
+
+const fn get(path: &'static str, summary: &'static str, response: Body) -> Endpoint {
+    Endpoint {
+        method: Method::Get,
+        path,
+        summary,
+        request: Body::None,
+        status: 200,
+        response,
+        required: &[],
+    }
+}
+
+const fn post(
+    path: &'static str,
+    summary: &'static str,
+    request: Body,
+    response: Body,
+) -> Endpoint {
+    Endpoint {
+        method: Method::Post,
+        request,
+        ..get(path, summary, response)
+    }
+}
+
+impl Endpoint {
+    const fn answering(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    const fn requiring(mut self, query: &'static [&'static str]) -> Self {
+        self.required = query;
+        self
+    }
+}
+
+use Body::{Events, Json, Text};
+
+const ENDPOINTS: &[Endpoint] = &[
+    get("/", "The frontend", Text("text/html")),
+    get("/openapi.json", "This document", Text("application/json")),
+    get(
+        "/tasks",
+        "The default space, empty before the first save",
+        Json("SimpleAthensSpace"),
+    ),
+    post(
+        "/tasks",
+        "Replace the default space, 409 with the latest unless based on it",
+        Json("SimpleAthensSpace"),
+        Json("SaveStatus"),
+    ),
+    post(
+        "/tasks/op",
+        "Apply one change to the default space, 409 if what it refers to changed since version `base`",
+        Json("Operation"),
+        Json("SaveStatus"),
+    ),
+    get(
+        "/tasks/events",
+        "A new version of the default space, as it happens",
+        Events,
+    ),
+    get(
+        "/tasks/status",
+        "Which versions of the default space are accepted and on disk, waiting up to 30 seconds for `wait_for`",
+        Json("SaveStatus"),
+    ),
+    get(
+        "/tasks.ics",
+        "The consensus ranking as an iCalendar feed",
+        Text("text/calendar"),
+    ),
+    get(
+        "/history/importance",
+        "The consensus importance at a time",
+        Json("OrderedTasks"),
+    ),
+    get(
+        "/history/task/{task}",
+        "The task's consensus rank over time",
+        Json("[RankPoint]"),
+    ),
+    get(
+        "/export/{format}",
+        "Download the ranking as csv, md or txt, the consensus or the user's",
+        Text("text/plain"),
+    ),
+    post(
+        "/import/{format}",
+        "Create the tasks of a csv, md or txt upload",
+        Text("text/plain"),
+        Json("Imported"),
+    ),
+    post(
+        "/import/issues",
+        "Create or update a task for each open issue of a tracker's export",
+        Text("application/json"),
+        Json("Imported"),
+    ),
+    post(
+        "/account/register",
+        "Create an account and log in",
+        Json("Credentials"),
+        Json("AccountInfo"),
+    )
+    .answering(201),
+    post(
+        "/account/login",
+        "Log in",
+        Json("Credentials"),
+        Json("AccountInfo"),
+    ),
+    post("/account/logout", "Log out", Body::None, Body::None).answering(204),
+    post(
+        "/account/password",
+        "Change the password, logging out the account's other sessions",
+        Json("PasswordChange"),
+        Body::None,
+    )
+    .answering(204),
+    get("/account", "The logged in account", Json("AccountInfo")),
+    post(
+        "/invite/{token}",
+        "Join the invite's space as a new user",
+        Body::None,
+        Json("User"),
+    )
+    .answering(201),
+    post(
+        "/admin/space/{space}/owner/{account}",
+        "Make the account an owner of the space, for admins",
+        Body::None,
+        Json("Member"),
+    ),
+    get(
+        "/admin/space/{space}/snapshots",
+        "The snapshots of the space, for its owners and admins",
+        Json("[SnapshotInfo]"),
+    ),
+    post(
+        "/admin/space/{space}/snapshots",
+        "Take a snapshot of the space now",
+        Body::None,
+        Json("SnapshotInfo"),
+    ),
+    get(
+        "/admin/space/{space}/snapshots/{at}",
+        "Download a snapshot",
+        Text("application/json"),
+    ),
+    post(
+        "/admin/space/{space}/snapshots/{at}/restore",
+        "Replace the space with one of its snapshots",
+        Body::None,
+        Json("SaveStatus"),
+    ),
+    get(
+        "/space",
+        "The spaces the caller is a member of, all of them for admins",
+        Json("[SpaceId]"),
+    ),
+    get("/space/{space}", "About a space", Json("SpaceInfo")),
+    post(
+        "/space/{space}",
+        "Create an empty space, 409 if the id is taken and 507 if there are `max_spaces` already",
+        Body::None,
+        Json("SpaceInfo"),
+    )
+    .answering(201),
+    get(
+        "/space/{space}/role",
+        "What the caller may do in the space",
+        Json("Role"),
+    ),
+    post(
+        "/space/{space}/invite",
+        "Invite people, as members or with `role`",
+        Body::None,
+        Json("Invite"),
+    )
+    .answering(201),
+    get(
+        "/space/{space}/invite",
+        "The space's invites",
+        Json("[Invite]"),
+    ),
+    post(
+        "/space/{space}/invite/{token}/revoke",
+        "Stop an invite from being used",
+        Body::None,
+        Json("Invite"),
+    ),
+    get(
+        "/space/{space}/member",
+        "The space's members",
+        Json("[Member]"),
+    ),
+    post(
+        "/space/{space}/member/{account}",
+        "Change what a member may do",
+        Body::None,
+        Json("Member"),
+    )
+    .requiring(&["role"]),
+    post(
+        "/space/{space}/member/{account}/user/{user}",
+        "Make a member the user in the space",
+        Body::None,
+        Json("Member"),
+    ),
+    get("/space/{space}/user", "The space's users", Json("[UserId]")),
+    post(
+        "/space/{space}/user",
+        "Create a user",
+        Body::None,
+        Json("User"),
+    )
+    .answering(201),
+    get("/space/{space}/user/{user}", "A user", Json("User")),
+    post(
+        "/space/{space}/user/{user}",
+        "Rename or reweigh a user",
+        Json("User"),
+        Json("User"),
+    ),
+    post(
+        "/space/{space}/user/{user}/claim",
+        "Log in as the user in a space the caller owns",
+        Body::None,
+        Json("AccountInfo"),
+    ),
+    get("/space/{space}/task", "The space's tasks", Json("[TaskId]")),
+    post(
+        "/space/{space}/task",
+        "Create an empty task",
+        Body::None,
+        Json("Task"),
+    )
+    .answering(201),
+    get("/space/{space}/task/{task}", "A task", Json("Task")),
+    post(
+        "/space/{space}/task/{task}",
+        "Edit a task",
+        Json("Task"),
+        Json("Task"),
+    ),
+    get(
+        "/space/{space}/task/{task}/history",
+        "The task's consensus rank over time",
+        Json("[RankPoint]"),
+    ),
+    get(
+        "/space/{space}/importance/{user}",
+        "The user's importance ballot",
+        Json("OrderedTasks"),
+    ),
+    post(
+        "/space/{space}/importance/{user}",
+        "Replace the user's importance ballot, missing tasks go in front",
+        Json("[TaskId]"),
+        Json("OrderedTasks"),
+    ),
+    get(
+        "/space/{space}/easiness/{user}",
+        "The user's easiness ballot",
+        Json("OrderedTasks"),
+    ),
+    post(
+        "/space/{space}/easiness/{user}",
+        "Replace the user's easiness ballot, missing tasks go in front",
+        Json("[TaskId]"),
+        Json("OrderedTasks"),
+    ),
+    post(
+        "/space/{space}/op",
+        "Apply one change, returns the version it made. 409 like `/tasks/op` for a stale `base`",
+        Json("Operation"),
+        Json("u64"),
+    ),
+    get(
+        "/space/{space}/events",
+        "A new version of the space, as it happens",
+        Events,
+    ),
+    get(
+        "/space/{space}/status",
+        "Which versions of the space are accepted and on disk, waiting up to 30 seconds for `wait_for`",
+        Json("SaveStatus"),
+    ),
+    get(
+        "/space/{space}/importance",
+        "The consensus importance, now or at `at`. Tagged, 304 for a matching If-None-Match",
+        Json("OrderedTasks"),
+    ),
+    get(
+        "/space/{space}/easiness",
+        "The consensus easiness. Tagged, 304 for a matching If-None-Match",
+        Json("OrderedTasks"),
+    ),
+    get(
+        "/space/{space}/final",
+        "The consensus of importance and easiness. Tagged, 304 for a matching If-None-Match",
+        Json("OrderedTasks"),
+    ),
+    get(
+        "/space/{space}/export/{format}",
+        "Download the ranking as csv, md or txt, the consensus or the user's",
+        Text("text/plain"),
+    ),
+    post(
+        "/space/{space}/import/{format}",
+        "Create the tasks of a csv, md or txt upload",
+        Text("text/plain"),
+        Json("Imported"),
+    ),
+    post(
+        "/space/{space}/import/issues",
+        "Create or update a task for each open issue of a tracker's export",
+        Text("application/json"),
+        Json("Imported"),
+    ),
+    get(
+        "/space/{space}/tasks.ics",
+        "The consensus ranking as an iCalendar feed",
+        Text("text/calendar"),
+    ),
+];
+
+/// The document for the mounted routes, made when the server starts.
+pub struct Document(pub Value);
+
+/// The document for the routes. Routes serving files are left out.
+pub fn document<'a>(routes: impl Iterator<Item = &'a Route>) -> Value {
+    let mut paths = Map::new();
+    for route in routes {
+        let path = openapi_path(route.uri.path());
+        if path.contains("..") {
+            continue;
+        }
+        let endpoint = match endpoint(route.method, &path) {
+            Some(endpoint) => endpoint,
+            None => {
+                log::error!("{} {} is not in the OpenAPI document", route.method, path);
+                continue;
+            }
+        };
+        let operation = operation(route, endpoint);
+        let methods = paths.entry(path).or_insert_with(|| json!({}));
+        methods[route.method.as_str().to_lowercase()] = operation;
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Athens",
+            "description": "Group task ranking by ranked pairs voting.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "session": {"type": "apiKey", "in": "cookie", "name": crate::accounts::SESSION_COOKIE},
+            },
+        },
+        "security": [{}, {"session": []}],
+    })
+}
+
+fn endpoint(method: Method, path: &str) -> Option<&'static Endpoint> {
+    ENDPOINTS
+        .iter()
+        .find(|e| e.method == method && e.path == path)
+}
+
+/// `/space/<space>/` as `/space/{space}`.
+fn openapi_path(path: &str) -> String {
+    let path = path.replace('<', "{").replace('>', "}");
+    match path.trim_end_matches('/') {
+        "" => "/".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+fn operation(route: &Route, endpoint: &Endpoint) -> Value {
+    let path_params = route
+        .uri
+        .path()
+        .split('/')
+        .filter_map(|s| s.strip_prefix('<')?.strip_suffix('>'))
+        .map(|name| parameter(name, "path", true));
+    let query_params = route
+        .uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|s| s.strip_prefix('<')?.strip_suffix('>'))
+        .map(|name| parameter(name, "query", endpoint.required.contains(&name)));
+    let mut operation = json!({
+        "operationId": format!("{}_{}", route.method.as_str().to_lowercase(), operation_id(endpoint.path)),
+        "summary": endpoint.summary,
+        "parameters": path_params.chain(query_params).collect::<Vec<_>>(),
+        "responses": {
+            endpoint.status.to_string(): response(endpoint.response),
+            "default": {"description": "Refused, see the status. 401 and 403 when the caller may not, 404 for unknown spaces, users and tasks, 413 for bodies or spaces over the limits, 507 for spaces past `max_spaces` and 429 with a Retry-After when changing too often."},
+        },
+    });
+    let media = route.format.as_ref().map(|f| f.to_string());
+    if let Some(content) = content(endpoint.request, media) {
+        operation["requestBody"] = json!({"required": true, "content": content});
+    }
+    operation
+}
+
+fn operation_id(path: &str) -> String {
+    let words = path
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty());
+    let id = words.collect::<Vec<_>>().join("_");
+    match id.as_str() {
+        "" => "index".to_string(),
+        _ => id,
+    }
+}
+
+fn parameter(name: &str, location: &str, required: bool) -> Value {
+    // Ids, times and counts are numbers, and times may also be dates.
+    let schema = match name {
+        "space" | "user" | "task" | "uses" | "wait_for" => json!({"type": "integer"}),
+        "at" | "from" | "to" | "expires" => {
+            json!({"type": "string", "description": "Unix seconds or YYYY-MM-DD"})
+        }
+        "role" => reference("Role"),
+        _ => json!({"type": "string"}),
+    };
+    json!({"name": name, "in": location, "required": required, "schema": schema})
+}
+
+fn response(body: Body) -> Value {
+    let description = match body {
+        Body::None => "Done",
+        Body::Events => "An `Update` per version, with the version as its id",
+        _ => "OK",
+    };
+    match content(body, None) {
+        Some(content) => json!({"description": description, "content": content}),
+        None => json!({"description": description}),
+    }
+}
+
+fn content(body: Body, media: Option<String>) -> Option<Value> {
+    let (media, schema) = match body {
+        Body::None => return None,
+        Body::Json(ty) => (
+            media.unwrap_or_else(|| "application/json".to_string()),
+            type_schema(ty),
+        ),
+        Body::Text(media) => (media.to_string(), json!({"type": "string"})),
+        Body::Events => ("text/event-stream".to_string(), json!({"type": "string"})),
+    };
+    let mut content = Map::new();
+    content.insert(media, json!({ "schema": schema }));
+    Some(Value::Object(content))
+}
+
+fn type_schema(ty: &str) -> Value {
+    match ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        Some(item) => json!({"type": "array", "items": type_schema(item)}),
+        None if ty == "u64" => json!({"type": "integer"}),
+        None => reference(ty),
+    }
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// The schema of a serialized value.
+fn infer(value: &Value) -> Value {
+    match value {
+        Value::Null => json!({"nullable": true}),
+        Value::Bool(_) => json!({"type": "boolean"}),
+        Value::Number(n) if n.is_f64() => json!({"type": "number"}),
+        Value::Number(_) => json!({"type": "integer"}),
+        Value::String(_) => json!({"type": "string"}),
+        Value::Array(items) => {
+            let items = items.first().map_or(json!({}), infer);
+            json!({"type": "array", "items": items})
+        }
+        Value::Object(fields) => {
+            let properties: Map<String, Value> =
+                fields.iter().map(|(k, v)| (k.clone(), infer(v))).collect();
+            json!({"type": "object", "properties": properties, "required": fields.keys().collect::<Vec<_>>()})
+        }
+    }
+}
+
+/// The schema of `T` from an example with every field set. A field is
+/// required if the example no longer parses as `T` without it, and nullable
+/// if it still parses with the field null.
+fn example<T: Serialize + DeserializeOwned>(example: &T) -> Value {
+    let value = serde_json::to_value(example).expect("Failed to serialize example");
+    let parses = |value: Value| serde_json::from_value::<T>(value).is_ok();
+    assert!(parses(value.clone()), "Example does not parse: {}", value);
+    let mut schema = infer(&value);
+    if let Value::Object(fields) = &value {
+        let mut required = Vec::new();
+        for name in fields.keys() {
+            let mut without = fields.clone();
+            without.remove(name);
+            if !parses(Value::Object(without)) {
+                required.push(name.clone());
+            }
+            let mut null = fields.clone();
+            null.insert(name.clone(), Value::Null);
+            if parses(Value::Object(null)) {
+                schema["properties"][name]["nullable"] = json!(true);
+            }
+        }
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+/// A tagged enum's schema from an example of each variant.
+fn variants(examples: &[Value], tag: &str) -> Value {
+    let variants = examples.iter().map(|variant| {
+        let mut schema = infer(variant);
+        schema["properties"][tag]["enum"] = json!([variant[tag]]);
+        schema
+    });
+    json!({"oneOf": variants.collect::<Vec<_>>(), "discriminator": {"propertyName": tag}})
+}
+
+fn schemas() -> Map<String, Value> {
+    let (space, user, task, at) = (SpaceId(1), UserId(2), TaskId(3), Timestamp(1_700_000_000));
+    let mut stored = SimpleAthensSpace::with_id(space);
+    stored.new_task().text = "Write the docs".to_string();
+    stored.new_user().move_importance(0, 0);
+    stored.record_ballots(at);
+    let example_task = Task {
+        id: task,
+        text: "Fix the login".to_string(),
+        external: Some(ExternalRef {
+            number: 42,
+            url: "https://example.com/issues/42".to_string(),
+        }),
+    };
+    let membership = Membership {
+        space,
+        role: Role::Voter,
+        user: Some(user),
+    };
+    let member = Member {
+        account: "ada".to_string(),
+        role: Role::Owner,
+        user: Some(user),
+    };
+    let invite = Invite {
+        token: "MM0YcNKa8iS9Fu5eS7BqOQ".to_string(),
+        space,
+        created_by: "ada".to_string(),
+        created: at,
+        expires: Some(Timestamp(at.0 + 3600)),
+        max_uses: Some(5),
+        role: Role::Member,
+        revoked: false,
+        redemptions: vec![Redemption {
+            account: "bob".to_string(),
+            user,
+            at,
+        }],
+    };
+    let operations = [
+        Operation::CreateTask {
+            text: "Write the docs".to_string(),
+        },
+        Operation::SetTaskText {
+            task,
+            text: "Write the docs".to_string(),
+        },
+        Operation::CreateUser {
+            alias: "Ada".to_string(),
+        },
+        Operation::RenameUser {
+            user,
+            alias: "Ada".to_string(),
+        },
+        Operation::MoveImportance {
+            user,
+            from: 2,
+            to: 0,
+        },
+        Operation::MoveEasiness {
+            user,
+            from: 0,
+            to: 2,
+        },
+    ];
+    let operations: Vec<Value> = operations
+        .iter()
+        .map(|op| serde_json::to_value(op).unwrap())
+        .collect();
+    let mut update = example(&Update {
+        version: 8,
+        op: Some(Operation::CreateUser {
+            alias: "Bob".to_string(),
+        }),
+    });
+    update["properties"]["op"] = reference("Operation");
+    let roles = [Role::Viewer, Role::Voter, Role::Member, Role::Owner];
+
+    let mut schemas = Map::new();
+    let mut add = |name: &str, schema: Value| schemas.insert(name.to_string(), schema);
+    add("SpaceId", example(&space));
+    add("UserId", example(&user));
+    add("TaskId", example(&task));
+    add("Timestamp", example(&at));
+    add("OrderedTasks", example(&OrderedTasks::from_vec(vec![task])));
+    add(
+        "User",
+        example(&User {
+            id: user,
+            alias: "Ada".to_string(),
+            weight: 1,
+        }),
+    );
+    add("Task", example(&example_task));
+    add("SimpleAthensSpace", example(&stored));
+    add(
+        "SaveStatus",
+        example(&SaveStatus {
+            version: 8,
+            persisted_version: 7,
+        }),
+    );
+    add("SnapshotInfo", example(&SnapshotInfo { at, bytes: 2048 }));
+    add(
+        "SpaceInfo",
+        example(&SpaceInfo {
+            id: space,
+            alias: "Team".to_string(),
+            version: 8,
+            tasks: 1,
+            users: 1,
+        }),
+    );
+    add("RankPoint", example(&RankPoint { at, rank: Some(0) }));
+    add(
+        "Imported",
+        example(&Imported {
+            created: vec![task],
+            updated: vec![task],
+            duplicates: vec!["Write the docs".to_string()],
+        }),
+    );
+    add("Operation", variants(&operations, "op"));
+    add("Update", update);
+    add(
+        "Credentials",
+        example(&Credentials {
+            name: "ada".to_string(),
+            password: "correct horse".to_string(),
+        }),
+    );
+    add(
+        "PasswordChange",
+        example(&PasswordChange {
+            password: "correct horse".to_string(),
+            new_password: "battery staple".to_string(),
+        }),
+    );
+    add(
+        "Role",
+        json!({"type": "string", "enum": roles, "description": "Each role may do everything the ones before it may"}),
+    );
+    add("Membership", example(&membership));
+    add("Member", example(&member));
+    add(
+        "AccountInfo",
+        example(&AccountInfo {
+            name: "ada".to_string(),
+            spaces: vec![membership],
+        }),
+    );
+    add("Invite", example(&invite));
+    schemas
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_route_is_described() {
+        let rocket = crate::app(rocket::custom(crate::test::figment()));
+        let mut described = Vec::new();
+        for route in rocket.routes() {
+            let path = openapi_path(route.uri.path());
+            if path.contains("..") {
+                continue;
+            }
+            let endpoint = endpoint(route.method, &path);
+            assert!(
+                endpoint.is_some(),
+                "{} {} is not in ENDPOINTS",
+                route.method,
+                path
+            );
+            described.push((route.method, path));
+        }
+        assert_eq!(
+            described.len(),
+            ENDPOINTS.len(),
+            "ENDPOINTS has unmounted routes"
+        );
+
+        let document = document(rocket.routes());
+        let schemas = &document["components"]["schemas"];
+        let text = document.to_string();
+        for name in text.split("#/components/schemas/").skip(1) {
+            let name = name.split('"').next().unwrap();
+            assert!(schemas.get(name).is_some(), "{} has no schema", name);
+        }
+        let invite = &document["paths"]["/space/{space}/invite"]["post"];
+        let names: Vec<&str> = invite["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["space", "expires", "uses", "role"]);
+        let task = &schemas["Task"];
+        assert_eq!(task["required"], json!(["id", "text"]));
+        assert_eq!(schemas["Invite"]["properties"]["expires"]["nullable"], true);
+        assert_eq!(
+            schemas["Invite"]["properties"]["role"].get("nullable"),
+            None
+        );
+        assert_eq!(schemas["Update"]["required"], json!(["version"]));
+    }
+
+    /// A field left empty in its example would have no type.
+    #[test]
+    fn every_field_has_a_type() {
+        for (name, schema) in schemas() {
+            let properties = schema["properties"].as_object().into_iter().flatten();
+            for (field, property) in properties {
+                assert!(
+                    property.get("type").is_some() || property.get("$ref").is_some(),
+                    "{}.{} has no type, give it a value in the example",
+                    name,
+                    field
+                );
+            }
+        }
+    }
+}
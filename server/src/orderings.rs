@@ -0,0 +1,175 @@
+//! Consensus orderings kept per version of a space, and the conditional
+//! requests that let pollers skip them altogether.
+//!
+//! Ranked pairs over every ballot is the expensive part of reading a space.
+//! The orderings only change with the space's version, so each is computed
+//! once per version and tagged with a strong ETag naming the space, the
+//! version and the ordering. A request whose `If-None-Match` has the tag is
+//! answered 304 without reading the space.
+
+use model::{AthensSpace, OrderedTasks, SpaceId, Timestamp};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::serde::json::Json;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Mutex;
+
+/// An ordering of the tasks by everyone's ballots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Consensus {
+    Importance,
+    /// The importance as it was at the time.
+    ImportanceAt(Timestamp),
+    Easiness,
+    Final,
+}
+
+impl Consensus {
+    /// The tag of the ordering for the version of the space.
+    pub fn etag(&self, space: SpaceId, version: u64) -> String {
+        let name = match self {
+            Consensus::Importance => "importance".to_string(),
+            Consensus::ImportanceAt(at) => format!("importance-at-{}", at.0),
+            Consensus::Easiness => "easiness".to_string(),
+            Consensus::Final => "final".to_string(),
+        };
+        format!("\"{}-{}-{}\"", space.0, version, name)
+    }
+
+    fn compute(&self, space: &model::ParallelSimpleAthensSpace) -> OrderedTasks {
+        match self {
+            Consensus::Importance => space.important_tasks(),
+            Consensus::ImportanceAt(at) => space.lock().unwrap().history().importance_at(*at),
+            Consensus::Easiness => space.easy_tasks(),
+            Consensus::Final => space.important_and_easy_tasks(),
+        }
+    }
+}
+
+/// The latest orderings computed for each space, with the version they are
+/// of. Orderings of past times are not kept, there are too many of them.
+#[derive(Default)]
+pub struct Orderings {
+    cached: Mutex<BTreeMap<(SpaceId, Consensus), (u64, OrderedTasks)>>,
+}
+
+impl Orderings {
+    /// The ordering of the copy of the space, computed unless it already was
+    /// for its version.
+    pub fn get(&self, space: &model::ParallelSimpleAthensSpace, of: Consensus) -> OrderedTasks {
+        let (id, version) = {
+            let space = space.lock().unwrap();
+            (space.id(), space.version())
+        };
+        let key = (id, of);
+        if let Some((cached, ordering)) = self.cached.lock().unwrap().get(&key) {
+            if *cached == version {
+                return ordering.clone();
+            }
+        }
+        let ordering = of.compute(space);
+        if !matches!(of, Consensus::ImportanceAt(_)) {
+            let mut cached = self.cached.lock().unwrap();
+            let newer = cached.get(&key).is_some_and(|(v, _)| *v > version);
+            if !newer {
+                cached.insert(key, (version, ordering.clone()));
+            }
+        }
+        ordering
+    }
+}
+
+/// The tags in a request's `If-None-Match`, if it has one.
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    /// Whether the client already has the version tagged `etag`. Weak tags
+    /// match too, as they should for `If-None-Match`.
+    pub fn matches(&self, etag: &str) -> bool {
+        let tags = match &self.0 {
+            Some(tags) => tags,
+            None => return false,
+        };
+        tags.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Infallible> {
+        let tags = request.headers().get_one("If-None-Match");
+        request::Outcome::Success(IfNoneMatch(tags.map(str::to_string)))
+    }
+}
+
+/// JSON tagged with an ETag, or 304 if the client has it already.
+pub struct Tagged<T> {
+    pub etag: String,
+    pub body: Option<T>,
+}
+
+impl<T> Tagged<T> {
+    pub fn not_modified(etag: String) -> Self {
+        Tagged { etag, body: None }
+    }
+}
+
+impl<'r, T: Serialize> Responder<'r, 'static> for Tagged<T> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = match self.body {
+            Some(body) => Json(body).respond_to(request)?,
+            None => Response::build().status(Status::NotModified).finalize(),
+        };
+        response.set_raw_header("ETag", self.etag);
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use model::SimpleAthensSpace;
+    use std::sync::Arc;
+
+    #[test]
+    fn orderings_are_kept_per_version() {
+        let mut space = SimpleAthensSpace::with_id(SpaceId(2));
+        space.new_task();
+        space.new_task();
+        space.new_user().move_importance(0, 1);
+        space.set_version(4);
+        let orderings = Orderings::default();
+        let copy = Arc::new(Mutex::new(space.clone()));
+        let first = orderings.get(&copy, Consensus::Importance);
+        assert_eq!(first, space.importance());
+
+        // Only a new version is ranked again.
+        space
+            .mut_user(model::UserId(0))
+            .unwrap()
+            .move_importance(0, 1);
+        let changed = Arc::new(Mutex::new(space.clone()));
+        assert_eq!(orderings.get(&changed, Consensus::Importance), first);
+        space.set_version(5);
+        let newer = Arc::new(Mutex::new(space.clone()));
+        assert_eq!(
+            orderings.get(&newer, Consensus::Importance),
+            space.importance()
+        );
+        assert_ne!(space.importance(), first);
+
+        let etag = Consensus::Final.etag(SpaceId(2), 5);
+        assert_eq!(etag, "\"2-5-final\"");
+        let tags = IfNoneMatch(Some(format!("\"2-4-final\", W/{}", etag)));
+        assert!(tags.matches(&etag));
+        assert!(!IfNoneMatch(None).matches(&etag));
+        assert!(IfNoneMatch(Some("*".to_string())).matches(&etag));
+    }
+}
@@ -0,0 +1,989 @@
+//! The resource oriented API under `/space`, so clients can read and change
+//! parts of a space without loading and saving all of it.
+//!
+//! Every change is accepted as a new version of the space, like a save to
+//! `/tasks`. Unknown spaces, users and tasks are 404, malformed bodies 400.
+//! Changes are rate limited per client and refused with 429 once used up,
+//! bodies and spaces over their limits with 413, see `limits`.
+//!
+//! ```text
+//! GET  /space -> Vec<SpaceId> of the caller's spaces, all of them for admins
+//! GET  /space/{spaceid} -> SpaceInfo
+//! POST /space/{spaceid}?alias={alias} -> Create space, SpaceInfo (logged in)
+//!
+//! -- Reading a space needs a login and, once somebody owns it, to be a
+//! -- member or admin. Changes need a role in the space, see `Role`. Spaces
+//! -- without an owner are only changed by admins. It is 401 if the caller
+//! -- is not logged in and 403 if they may not read or change the space.
+//! GET  /space/{spaceid}/role -> The caller's Role
+//!
+//! -- Only the space's owner may invite and manage members, see `accounts`
+//! POST /space/{spaceid}/invite?expires={time}&uses={n}&role={role} -> Create invite, Invite
+//! GET  /space/{spaceid}/invite -> Vec<Invite>
+//! POST /space/{spaceid}/invite/{token}/revoke -> Invite
+//! GET  /space/{spaceid}/member -> Vec<Member>
+//! POST /space/{spaceid}/member/{account}?role={role} -> Member
+//! POST /space/{spaceid}/member/{account}/user/{userid} -> Member
+//!
+//! GET  /space/{spaceid}/user -> Vec<UserId>
+//! POST /space/{spaceid}/user -> Create user, User (owner)
+//! GET  /space/{spaceid}/user/{userid} -> User
+//! POST /space/{spaceid}/user/{userid} <- User -> User (owner, or renaming oneself)
+//! POST /space/{spaceid}/user/{userid}/claim -> Log in as the user, AccountInfo (owner)
+//!
+//! GET  /space/{spaceid}/task -> Vec<TaskId>
+//! POST /space/{spaceid}/task -> Create task, Task (member)
+//! GET  /space/{spaceid}/task/{taskid} -> Task
+//! POST /space/{spaceid}/task/{taskid} <- Task -> Task (member)
+//! GET  /space/{spaceid}/task/{taskid}/history?from={time}&to={time} -> Vec<RankPoint>
+//!
+//! -- Only the logged in user may post their ballots or move their tasks,
+//! -- and only if they are at least a voter
+//! -- Tasks missing from a posted ballot are prepended in descending order
+//! GET  /space/{spaceid}/importance/{userid} -> OrderedTasks
+//! POST /space/{spaceid}/importance/{userid} <- Vec<TaskId> -> OrderedTasks
+//! GET  /space/{spaceid}/easiness/{userid} -> OrderedTasks
+//! POST /space/{spaceid}/easiness/{userid} <- Vec<TaskId> -> OrderedTasks
+//!
+//! -- 409 if what it refers to changed since version `base`
+//! POST /space/{spaceid}/op?base={version} <- Operation -> The version it made
+//! GET  /space/{spaceid}/events -> Server-sent events, an Update per version
+//! GET  /space/{spaceid}/status?wait_for={version} -> SaveStatus
+//!
+//! -- Tagged with an ETag of the space's version, 304 for a matching If-None-Match
+//! GET  /space/{spaceid}/importance -> Aggregated importance ordering
+//! GET  /space/{spaceid}/importance?at={time} -> Aggregated importance in the past
+//! GET  /space/{spaceid}/easiness -> Aggregated easiness ordering
+//! GET  /space/{spaceid}/final -> Aggregated ordering considering everything
+//!
+//! GET  /space/{spaceid}/export/{csv,md,txt}?user={userid} -> Download the ranking
+//! POST /space/{spaceid}/import/{csv,md,txt} -> Imported (member)
+//! POST /space/{spaceid}/import/issues -> Imported, updating already imported issues (member)
+//! GET  /space/{spaceid}/tasks.ics -> iCalendar feed of VTODOs by importance
+//! ```
+
+use crate::accounts::{Accounts, Caller, Session};
+use crate::limits::{Text, Throttled};
+use crate::orderings::{Consensus, IfNoneMatch, Orderings, Tagged};
+use crate::{apply_operation, internal_error, modify_space, parse_format, parse_time};
+use crate::{now, read_space, updates, Data, Download, LastEventId};
+use model::api::{AccountInfo, Invite, Member, Operation, Role, SaveStatus, SpaceInfo};
+use model::interchange::{self, Imported};
+use model::{issues, AthensSpace, OrderedTasks, RankPoint, SimpleAthensSpace, SpaceId};
+use model::{Task, TaskId, Timestamp, User, UserId};
+use rocket::http::{ContentType, Status};
+use rocket::response::status::Created;
+use rocket::response::stream::EventStream;
+use rocket::serde::json::Json;
+use rocket::{Route, Shutdown, State};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        list_spaces,
+        get_space,
+        create_space,
+        caller_role,
+        create_invite,
+        list_invites,
+        revoke_invite,
+        list_members,
+        set_member_role,
+        assign_user,
+        list_users,
+        create_user,
+        get_user,
+        set_user,
+        claim_user,
+        list_tasks,
+        create_task,
+        get_task,
+        set_task,
+        task_history,
+        user_importance,
+        set_user_importance,
+        user_easiness,
+        set_user_easiness,
+        apply_op,
+        events,
+        save_status,
+        importance,
+        easiness,
+        final_ordering,
+        export_tasks,
+        import_tasks,
+        import_issues,
+        task_feed,
+    ]
+}
+
+fn info(space: &SimpleAthensSpace) -> SpaceInfo {
+    SpaceInfo {
+        id: space.id(),
+        alias: space.alias().to_string(),
+        version: space.version(),
+        tasks: space.task_ids().count(),
+        users: space.user_ids().count(),
+    }
+}
+
+/// The spaces the caller is a member of, or all of them for admins.
+#[get("/")]
+fn list_spaces(
+    db: &State<Data>,
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+) -> Result<Json<Vec<SpaceId>>, Status> {
+    let mut ids = db.lock().ids().map_err(internal_error)?;
+    if !accounts.is_admin(session.name()) {
+        ids.retain(|id| session.is_member(*id));
+    }
+    Ok(Json(ids))
+}
+
+#[get("/<space>")]
+fn get_space(db: &State<Data>, caller: Caller, space: usize) -> Result<Json<SpaceInfo>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let space = read_space(db, SpaceId(space))?;
+    let space = space.lock().unwrap();
+    Ok(Json(info(&space)))
+}
+
+/// Creates an empty space owned by the caller, 409 if the id is taken and
+/// 507 if there are `max_spaces` spaces already.
+#[post("/<space>?<alias>")]
+fn create_space(
+    db: &State<Data>,
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    alias: Option<String>,
+) -> Result<Created<Json<SpaceInfo>>, Status> {
+    let id = SpaceId(space);
+    let mut store = db.lock();
+    if store.space(id).map_err(internal_error)?.is_some() {
+        return Err(Status::Conflict);
+    }
+    if !store.has_room().map_err(internal_error)? {
+        log::warn!("Refused to create space {}: too many spaces", id.0);
+        return Err(Status::InsufficientStorage);
+    }
+    let mut space = SimpleAthensSpace::with_id(id);
+    if let Some(alias) = alias {
+        space.set_alias(alias);
+    }
+    let created = info(&space);
+    accounts.adopt(session.name(), id)?;
+    store.update(space);
+    drop(store);
+    Ok(Created::new(format!("/space/{}", id.0)).body(Json(created)))
+}
+
+/// What the caller may do in the space.
+#[get("/<space>/role")]
+fn caller_role(caller: Caller, space: usize) -> Json<Role> {
+    Json(caller.role(SpaceId(space)))
+}
+
+/// A role as it is written in JSON, like `voter`.
+fn parse_role(role: &str) -> Result<Role, Status> {
+    serde_json::from_value(role.into()).map_err(|_| Status::BadRequest)
+}
+
+/// An invite that expires at `expires` and can be used `uses` times, if
+/// given. People joining with it become members, or have `role`.
+#[post("/<space>/invite?<expires>&<uses>&<role>")]
+fn create_invite(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    expires: Option<&str>,
+    uses: Option<u32>,
+    role: Option<&str>,
+) -> Result<Created<Json<Invite>>, Status> {
+    let expires = expires.map(|e| parse_time(Some(e))).transpose()?;
+    let role = role.map(parse_role).transpose()?.unwrap_or_default();
+    let space = SpaceId(space);
+    let invite = accounts.invite(session.name(), space, now(), expires, uses, role)?;
+    let location = format!("/?invite={}", invite.token);
+    Ok(Created::new(location).body(Json(invite)))
+}
+
+#[get("/<space>/invite")]
+fn list_invites(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    space: usize,
+) -> Result<Json<Vec<Invite>>, Status> {
+    accounts.invites(session.name(), SpaceId(space)).map(Json)
+}
+
+#[post("/<space>/invite/<token>/revoke")]
+fn revoke_invite(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    token: &str,
+) -> Result<Json<Invite>, Status> {
+    accounts
+        .revoke(session.name(), SpaceId(space), token)
+        .map(Json)
+}
+
+#[get("/<space>/member")]
+fn list_members(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    space: usize,
+) -> Result<Json<Vec<Member>>, Status> {
+    accounts.members(session.name(), SpaceId(space)).map(Json)
+}
+
+/// Changes what a member may do, 409 if it would leave the space without
+/// an owner.
+#[post("/<space>/member/<account>?<role>")]
+fn set_member_role(
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    account: &str,
+    role: &str,
+) -> Result<Json<Member>, Status> {
+    let role = parse_role(role)?;
+    accounts
+        .set_role(session.name(), SpaceId(space), account, role)
+        .map(Json)
+}
+
+/// Makes the member the user, 403 if another account already is and 409 if
+/// the member is already someone else there.
+#[post("/<space>/member/<account>/user/<user>")]
+fn assign_user(
+    db: &State<Data>,
+    accounts: &State<Arc<Accounts>>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    account: &str,
+    user: usize,
+) -> Result<Json<Member>, Status> {
+    user_exists(&read_space(db, SpaceId(space))?, UserId(user))?;
+    accounts
+        .assign(session.name(), SpaceId(space), account, UserId(user))
+        .map(Json)
+}
+
+#[get("/<space>/user")]
+fn list_users(db: &State<Data>, caller: Caller, space: usize) -> Result<Json<Vec<UserId>>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    Ok(Json(read_space(db, SpaceId(space))?.users()))
+}
+
+#[post("/<space>/user")]
+/// A member who is nobody in the space yet becomes the new user.
+fn create_user(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+) -> Result<Created<Json<User>>, Status> {
+    caller.require(SpaceId(space), Role::Owner)?;
+    let user = modify_space(db, SpaceId(space), |s| Ok(s.create_user()))?;
+    if caller.is_member(SpaceId(space)) && caller.user(SpaceId(space)).is_none() {
+        caller.bind(SpaceId(space), user.id)?;
+    }
+    let location = format!("/space/{}/user/{}", space, user.id.0);
+    Ok(Created::new(location).body(Json(user)))
+}
+
+#[get("/<space>/user/<user>")]
+fn get_user(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    user: usize,
+) -> Result<Json<User>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let space = read_space(db, SpaceId(space))?;
+    space
+        .get_user(UserId(user))
+        .map(Json)
+        .ok_or(Status::NotFound)
+}
+
+/// Renames or reweighs a user. The id in the body must match the path.
+/// Only owners change weights, users may rename themselves.
+#[post("/<space>/user/<user>", data = "<body>")]
+fn set_user(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    user: usize,
+    body: Json<User>,
+) -> Result<Json<User>, Status> {
+    if body.id != UserId(user) {
+        return Err(Status::BadRequest);
+    }
+    modify_space(db, SpaceId(space), |s| {
+        let current = s.get_user(UserId(user)).ok_or(Status::NotFound)?;
+        match caller.require(SpaceId(space), Role::Owner) {
+            Err(_) if current.weight == body.weight => {
+                caller.may_vote(SpaceId(space), UserId(user))
+            }
+            owner => owner,
+        }?;
+        s.set_user(body.into_inner()).ok_or(Status::NotFound)
+    })
+    .map(Json)
+}
+
+/// Logs the caller in as the user in the space, if they own it. 403 if
+/// another account already is, 409 if the caller is already someone else
+/// there. Other members are made users with `/member/<account>/user/<user>`.
+#[post("/<space>/user/<user>/claim")]
+fn claim_user(
+    db: &State<Data>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    user: usize,
+) -> Result<Json<AccountInfo>, Status> {
+    user_exists(&read_space(db, SpaceId(space))?, UserId(user))?;
+    session.bind(SpaceId(space), UserId(user))?;
+    session.info().map(Json).ok_or(Status::Unauthorized)
+}
+
+#[get("/<space>/task")]
+fn list_tasks(db: &State<Data>, caller: Caller, space: usize) -> Result<Json<Vec<TaskId>>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    Ok(Json(read_space(db, SpaceId(space))?.tasks()))
+}
+
+/// Creates an empty task, at the top of every ballot.
+#[post("/<space>/task")]
+fn create_task(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+) -> Result<Created<Json<Task>>, Status> {
+    caller.require(SpaceId(space), Role::Member)?;
+    let task = modify_space(db, SpaceId(space), |s| Ok(s.create_task()))?;
+    let location = format!("/space/{}/task/{}", space, task.id.0);
+    Ok(Created::new(location).body(Json(task)))
+}
+
+#[get("/<space>/task/<task>")]
+fn get_task(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    task: usize,
+) -> Result<Json<Task>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let space = read_space(db, SpaceId(space))?;
+    space
+        .get_task(TaskId(task))
+        .map(Json)
+        .ok_or(Status::NotFound)
+}
+
+/// Edits a task. The id in the body must match the path.
+#[post("/<space>/task/<task>", data = "<body>")]
+fn set_task(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    task: usize,
+    body: Json<Task>,
+) -> Result<Json<Task>, Status> {
+    caller.require(SpaceId(space), Role::Member)?;
+    if body.id != TaskId(task) {
+        return Err(Status::BadRequest);
+    }
+    modify_space(db, SpaceId(space), |s| {
+        s.set_task(body.into_inner()).ok_or(Status::NotFound)
+    })
+    .map(Json)
+}
+
+#[get("/<space>/task/<task>/history?<from>&<to>")]
+fn task_history(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    task: usize,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Json<Vec<RankPoint>>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let from = from.map_or(Ok(Timestamp(0)), |f| parse_time(Some(f)))?;
+    let to = parse_time(to)?;
+    let space = read_space(db, SpaceId(space))?;
+    let space = space.lock().unwrap();
+    if space.task_ids().all(|t| t != TaskId(task)) {
+        return Err(Status::NotFound);
+    }
+    let trajectory = space
+        .history()
+        .importance_trajectory(TaskId(task), from, to);
+    Ok(Json(trajectory))
+}
+
+/// Makes a full ballot out of `ids`. Tasks missing from it are put in front,
+/// newest first, like new tasks are. `None` if `ids` has unknown or repeated
+/// tasks.
+fn complete_ballot(space: &dyn AthensSpace, ids: Vec<TaskId>) -> Option<OrderedTasks> {
+    let all: BTreeSet<TaskId> = space.tasks().into_iter().collect();
+    let given: BTreeSet<TaskId> = ids.iter().copied().collect();
+    if given.len() != ids.len() || !given.is_subset(&all) {
+        return None;
+    }
+    let missing = all.iter().rev().filter(|id| !given.contains(id)).copied();
+    Some(OrderedTasks::from_vec(
+        missing.chain(ids).collect::<Vec<_>>(),
+    ))
+}
+
+fn user_exists(space: &dyn AthensSpace, user: UserId) -> Result<(), Status> {
+    match space.users().contains(&user) {
+        true => Ok(()),
+        false => Err(Status::NotFound),
+    }
+}
+
+#[get("/<space>/importance/<user>")]
+fn user_importance(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    user: usize,
+) -> Result<Json<OrderedTasks>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let space = read_space(db, SpaceId(space))?;
+    user_exists(&space, UserId(user))?;
+    Ok(Json(space.user_importance(UserId(user))))
+}
+
+/// Replaces a user's importance ballot, see `complete_ballot`.
+#[post("/<space>/importance/<user>", data = "<ballot>")]
+fn set_user_importance(
+    db: &State<Data>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    user: usize,
+    ballot: Json<Vec<TaskId>>,
+) -> Result<Json<OrderedTasks>, Status> {
+    modify_space(db, SpaceId(space), |s| {
+        user_exists(s, UserId(user))?;
+        session.may_vote(SpaceId(space), UserId(user))?;
+        let ballot = complete_ballot(s, ballot.into_inner()).ok_or(Status::BadRequest)?;
+        s.set_user_importance(UserId(user), ballot)
+            .ok_or(Status::NotFound)
+    })
+    .map(Json)
+}
+
+#[get("/<space>/easiness/<user>")]
+fn user_easiness(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    user: usize,
+) -> Result<Json<OrderedTasks>, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    let space = read_space(db, SpaceId(space))?;
+    user_exists(&space, UserId(user))?;
+    Ok(Json(space.user_easiness(UserId(user))))
+}
+
+/// Replaces a user's easiness ballot, see `complete_ballot`.
+#[post("/<space>/easiness/<user>", data = "<ballot>")]
+fn set_user_easiness(
+    db: &State<Data>,
+    session: Session,
+    _throttled: Throttled,
+    space: usize,
+    user: usize,
+    ballot: Json<Vec<TaskId>>,
+) -> Result<Json<OrderedTasks>, Status> {
+    modify_space(db, SpaceId(space), |s| {
+        user_exists(s, UserId(user))?;
+        session.may_vote(SpaceId(space), UserId(user))?;
+        let ballot = complete_ballot(s, ballot.into_inner()).ok_or(Status::BadRequest)?;
+        s.set_user_easiness(UserId(user), ballot)
+            .ok_or(Status::NotFound)
+    })
+    .map(Json)
+}
+
+#[post("/<space>/op?<base>", format = "application/json", data = "<op>")]
+fn apply_op(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    base: Option<u64>,
+    op: Json<Operation>,
+) -> Result<Json<u64>, Status> {
+    apply_operation(db, SpaceId(space), &op, base, &caller).map(Json)
+}
+
+/// Like `/tasks/events`. Spaces that do not exist yet can be watched for
+/// when they are created.
+#[get("/<space>/events")]
+fn events(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    last: LastEventId,
+    end: Shutdown,
+) -> Result<EventStream![], Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    Ok(updates(db, SpaceId(space), last, end))
+}
+
+/// Like `/tasks/status`: which versions of the space are accepted and on
+/// disk, waiting up to 30 seconds for `wait_for` to be persisted.
+#[get("/<space>/status?<wait_for>")]
+async fn save_status(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    wait_for: Option<u64>,
+) -> Result<Json<SaveStatus>, Status> {
+    let id = SpaceId(space);
+    caller.require(id, Role::Viewer)?;
+    let exists = db.lock().space(id).map_err(internal_error)?.is_some();
+    if !exists {
+        return Err(Status::NotFound);
+    }
+    Ok(Json(match wait_for {
+        Some(version) => db.wait_for(id, version, Duration::from_secs(30)).await,
+        None => db.status(id),
+    }))
+}
+
+/// The consensus ordering, or 304 if the caller has the one of the
+/// latest version, see `orderings`.
+fn consensus(
+    db: &State<Data>,
+    orderings: &Orderings,
+    caller: Caller,
+    id: SpaceId,
+    of: Consensus,
+    cached: IfNoneMatch,
+) -> Result<Tagged<OrderedTasks>, Status> {
+    caller.require(id, Role::Viewer)?;
+    let version = {
+        let mut store = db.lock();
+        let space = store.space(id).map_err(internal_error)?;
+        space.ok_or(Status::NotFound)?.version()
+    };
+    if cached.matches(&of.etag(id, version)) {
+        return Ok(Tagged::not_modified(of.etag(id, version)));
+    }
+    // The space may have changed since, the tag is of the copy.
+    let space = read_space(db, id)?;
+    let version = space.lock().unwrap().version();
+    Ok(Tagged {
+        etag: of.etag(id, version),
+        body: Some(orderings.get(&space, of)),
+    })
+}
+
+/// The consensus importance, now or at `at`.
+#[get("/<space>/importance?<at>")]
+fn importance(
+    db: &State<Data>,
+    orderings: &State<Orderings>,
+    caller: Caller,
+    space: usize,
+    at: Option<&str>,
+    cached: IfNoneMatch,
+) -> Result<Tagged<OrderedTasks>, Status> {
+    let of = match at {
+        Some(_) => Consensus::ImportanceAt(parse_time(at)?),
+        None => Consensus::Importance,
+    };
+    consensus(db, orderings, caller, SpaceId(space), of, cached)
+}
+
+#[get("/<space>/easiness")]
+fn easiness(
+    db: &State<Data>,
+    orderings: &State<Orderings>,
+    caller: Caller,
+    space: usize,
+    cached: IfNoneMatch,
+) -> Result<Tagged<OrderedTasks>, Status> {
+    consensus(
+        db,
+        orderings,
+        caller,
+        SpaceId(space),
+        Consensus::Easiness,
+        cached,
+    )
+}
+
+/// The consensus considering both importance and easiness.
+#[get("/<space>/final")]
+fn final_ordering(
+    db: &State<Data>,
+    orderings: &State<Orderings>,
+    caller: Caller,
+    space: usize,
+    cached: IfNoneMatch,
+) -> Result<Tagged<OrderedTasks>, Status> {
+    consensus(
+        db,
+        orderings,
+        caller,
+        SpaceId(space),
+        Consensus::Final,
+        cached,
+    )
+}
+
+#[get("/<space>/export/<format>?<user>")]
+fn export_tasks(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+    format: &str,
+    user: Option<usize>,
+) -> Result<Download, Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    crate::export(&read_space(db, SpaceId(space))?, format, user)
+}
+
+// Ranked after `import_issues`, which would otherwise collide.
+#[post("/<space>/import/<format>", data = "<input>", rank = 2)]
+fn import_tasks(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    format: &str,
+    input: Text<'_>,
+) -> Result<Json<Imported>, Status> {
+    caller.require(SpaceId(space), Role::Member)?;
+    let format = parse_format(format)?;
+    modify_space(db, SpaceId(space), |s| {
+        Ok(interchange::import(s, &input, format))
+    })
+    .map(Json)
+}
+
+#[post("/<space>/import/issues", data = "<input>")]
+fn import_issues(
+    db: &State<Data>,
+    caller: Caller,
+    _throttled: Throttled,
+    space: usize,
+    input: Text<'_>,
+) -> Result<Json<Imported>, Status> {
+    caller.require(SpaceId(space), Role::Member)?;
+    let issues = issues::parse(&input).map_err(|_| Status::BadRequest)?;
+    modify_space(db, SpaceId(space), |s| Ok(issues::import(s, &issues))).map(Json)
+}
+
+#[get("/<space>/tasks.ics")]
+fn task_feed(
+    db: &State<Data>,
+    caller: Caller,
+    space: usize,
+) -> Result<(ContentType, String), Status> {
+    caller.require(SpaceId(space), Role::Viewer)?;
+    Ok(crate::ical_feed(&read_space(db, SpaceId(space))?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::client;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    fn post(client: &Client, uri: &str, body: &str) -> Status {
+        let response = client.post(uri).header(ContentType::JSON).body(body);
+        response.dispatch().status()
+    }
+
+    #[test]
+    fn spaces_users_and_tasks() {
+        let client = client();
+        let status = client.get("/space/1").dispatch().status();
+        assert_eq!(status, Status::Unauthorized);
+        assert_eq!(post(&client, "/space/1", ""), Status::Unauthorized);
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        post(&client, "/account/register", ada);
+        assert_eq!(post(&client, "/space/1?alias=Team", ""), Status::Created);
+        let status: SaveStatus = client
+            .get("/space/1/status")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(status.version, 0);
+        let unknown = client.get("/space/9/status").dispatch();
+        assert_eq!(unknown.status(), Status::NotFound);
+        assert_eq!(post(&client, "/space/1", ""), Status::Conflict);
+        let spaces: Vec<SpaceId> = client.get("/space").dispatch().into_json().unwrap();
+        assert_eq!(spaces, vec![SpaceId(1)]);
+
+        assert_eq!(post(&client, "/space/1/task", ""), Status::Created);
+        let user: User = client.post("/space/1/user").dispatch().into_json().unwrap();
+        assert_eq!(user.id, UserId(0));
+        let task = r#"{"id": 0, "text": "Write the API"}"#;
+        assert_eq!(post(&client, "/space/1/task/0", task), Status::Ok);
+        assert_eq!(post(&client, "/space/1/task/1", task), Status::BadRequest);
+        assert_eq!(post(&client, "/space/1/task/0", "nope"), Status::BadRequest);
+        let task: Task = client
+            .get("/space/1/task/0")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(task.text, "Write the API");
+        assert_eq!(
+            client.get("/space/1/task/1").dispatch().status(),
+            Status::NotFound
+        );
+
+        let info: SpaceInfo = client.get("/space/1").dispatch().into_json().unwrap();
+        assert_eq!(info.alias, "Team");
+        assert_eq!((info.version, info.tasks, info.users), (3, 1, 1));
+        assert_eq!(client.get("/space/9").dispatch().status(), Status::NotFound);
+    }
+
+    #[test]
+    fn only_members_read_owned_spaces() {
+        let client = client();
+        let account = |name| format!(r#"{{"name": "{}", "password": "correct horse"}}"#, name);
+        post(&client, "/account/register", &account("ada"));
+        post(&client, "/space/3", "");
+        post(&client, "/space/3/task", "");
+        let invite: Invite = client
+            .post("/space/3/invite?role=viewer")
+            .dispatch()
+            .into_json()
+            .unwrap();
+
+        post(&client, "/account/register", &account("bob"));
+        post(&client, "/space/4", "");
+        let spaces: Vec<SpaceId> = client.get("/space").dispatch().into_json().unwrap();
+        assert_eq!(spaces, [SpaceId(4)]);
+        for uri in [
+            "/space/3",
+            "/space/3/task/0",
+            "/space/3/final",
+            "/space/3/events",
+        ] {
+            assert_eq!(client.get(uri).dispatch().status(), Status::Forbidden);
+        }
+        let join = format!("/invite/{}", invite.token);
+        assert_eq!(post(&client, &join, ""), Status::Created);
+        let spaces: Vec<SpaceId> = client.get("/space").dispatch().into_json().unwrap();
+        assert_eq!(spaces, [SpaceId(3), SpaceId(4)]);
+        for uri in ["/space/3", "/space/3/task/0", "/space/3/final"] {
+            assert_eq!(client.get(uri).dispatch().status(), Status::Ok);
+        }
+
+        // Admins read every space, members or not.
+        post(&client, "/account/login", &account("ada"));
+        assert_eq!(client.get("/space/4").dispatch().status(), Status::Ok);
+        let spaces: Vec<SpaceId> = client.get("/space").dispatch().into_json().unwrap();
+        assert_eq!(spaces, [SpaceId(3), SpaceId(4)]);
+    }
+
+    #[test]
+    fn ballots() {
+        let client = client();
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        assert_eq!(post(&client, "/account/register", ada), Status::Created);
+        post(&client, "/space/2", "");
+        for _ in 0..3 {
+            post(&client, "/space/2/task", "");
+        }
+        // The owner is the first user they create.
+        post(&client, "/space/2/user", "");
+        post(&client, "/space/2/user", "");
+        assert_eq!(post(&client, "/space/2/user/0/claim", ""), Status::Ok);
+        assert_eq!(post(&client, "/space/2/user/1/claim", ""), Status::Conflict);
+        post(&client, "/account/logout", "");
+        assert_eq!(
+            post(&client, "/space/2/importance/0", "[0]"),
+            Status::Unauthorized
+        );
+        post(&client, "/account/login", ada);
+        assert_eq!(
+            post(&client, "/space/2/importance/1", "[0]"),
+            Status::Forbidden
+        );
+
+        // The missing tasks go in front, newest first.
+        assert_eq!(post(&client, "/space/2/importance/0", "[0]"), Status::Ok);
+        let ballot: OrderedTasks = client
+            .get("/space/2/importance/0")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(ballot, [TaskId(2), TaskId(1), TaskId(0)]);
+        let consensus: OrderedTasks = client
+            .get("/space/2/importance")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(consensus, ballot);
+
+        assert_eq!(
+            post(&client, "/space/2/easiness/0", "[0, 0]"),
+            Status::BadRequest
+        );
+        assert_eq!(
+            post(&client, "/space/2/easiness/0", "[7]"),
+            Status::BadRequest
+        );
+        assert_eq!(
+            post(&client, "/space/2/easiness/2", "[0]"),
+            Status::NotFound
+        );
+        assert_eq!(client.get("/space/2/final").dispatch().status(), Status::Ok);
+
+        let op = r#"{"op": "move_easiness", "user": 0, "from": 2, "to": 0}"#;
+        let version: u64 = client
+            .post("/space/2/op")
+            .header(ContentType::JSON)
+            .body(op)
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(version, 7);
+        let ballot: OrderedTasks = client
+            .get("/space/2/easiness/0")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(ballot, [TaskId(2), TaskId(0), TaskId(1)]);
+        let op = r#"{"op": "move_easiness", "user": 0, "from": 3, "to": 0}"#;
+        assert_eq!(post(&client, "/space/2/op", op), Status::NotFound);
+    }
+
+    #[test]
+    fn roles_limit_changes() {
+        let client = client();
+        let account =
+            |name: &str| format!(r#"{{"name": "{}", "password": "correct horse"}}"#, name);
+        post(&client, "/account/register", &account("ada"));
+        post(&client, "/space/5", "");
+        post(&client, "/space/5/task", "");
+        post(&client, "/space/5/user", "");
+        let invite = |role: &str| -> Invite {
+            let uri = format!("/space/5/invite?role={}", role);
+            client.post(uri).dispatch().into_json().unwrap()
+        };
+        let (voter, viewer) = (invite("voter"), invite("viewer"));
+        assert_eq!(
+            post(&client, "/space/5/invite?role=admin", ""),
+            Status::BadRequest
+        );
+
+        post(&client, "/account/logout", "");
+        let task = r#"{"id": 0, "text": "Anonymous"}"#;
+        assert_eq!(post(&client, "/space/5/task/0", task), Status::Unauthorized);
+        let role: Role = client.get("/space/5/role").dispatch().into_json().unwrap();
+        assert_eq!(role, Role::Viewer);
+
+        post(&client, "/account/register", &account("bob"));
+        let join = format!("/invite/{}?alias=Bob", voter.token);
+        assert_eq!(post(&client, &join, ""), Status::Created);
+        assert_eq!(post(&client, "/space/5/task", ""), Status::Forbidden);
+        assert_eq!(post(&client, "/space/5/user", ""), Status::Forbidden);
+        let heavier = r#"{"id": 1, "alias": "Bob", "weight": 5}"#;
+        assert_eq!(post(&client, "/space/5/user/1", heavier), Status::Forbidden);
+        let renamed = r#"{"id": 1, "alias": "Robert", "weight": 1}"#;
+        assert_eq!(post(&client, "/space/5/user/1", renamed), Status::Ok);
+        let other = r#"{"id": 0, "alias": "Bob", "weight": 1}"#;
+        assert_eq!(post(&client, "/space/5/user/0", other), Status::Forbidden);
+        assert_eq!(post(&client, "/space/5/importance/1", "[0]"), Status::Ok);
+
+        post(&client, "/account/register", &account("cy"));
+        let join = format!("/invite/{}", viewer.token);
+        assert_eq!(post(&client, &join, ""), Status::Created);
+        assert_eq!(
+            post(&client, "/space/5/importance/2", "[0]"),
+            Status::Forbidden
+        );
+
+        post(&client, "/account/login", &account("ada"));
+        assert_eq!(post(&client, "/space/5/user/1", heavier), Status::Ok);
+        assert_eq!(
+            post(&client, "/space/5/member/bob?role=member", ""),
+            Status::Ok
+        );
+        let members: Vec<Member> = client
+            .get("/space/5/member")
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(members.len(), 3);
+        assert!(members
+            .iter()
+            .any(|m| m.account == "bob" && m.role == Role::Member));
+
+        // Only owners say who is an existing user.
+        assert_eq!(post(&client, "/space/5/user", ""), Status::Created);
+        let assign = |member: &str| format!("/space/5/member/{}/user/3", member);
+        assert_eq!(post(&client, &assign("cy"), ""), Status::Conflict);
+        assert_eq!(post(&client, &assign("dan"), ""), Status::NotFound);
+        post(&client, "/account/login", &account("cy"));
+        assert_eq!(
+            post(&client, "/space/5/user/3/claim", ""),
+            Status::Forbidden
+        );
+        assert_eq!(post(&client, &assign("cy"), ""), Status::Forbidden);
+    }
+
+    #[test]
+    fn consensus_is_tagged_with_the_version() {
+        let client = client();
+        let ada = r#"{"name": "ada", "password": "correct horse"}"#;
+        post(&client, "/account/register", ada);
+        post(&client, "/space/7", "");
+        post(&client, "/space/7/task", "");
+        post(&client, "/space/7/task", "");
+        let response = client.get("/space/7/final").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(etag, "\"7-2-final\"");
+
+        let cached = |uri: &'static str, etag: &str| {
+            let request = client
+                .get(uri)
+                .header(Header::new("If-None-Match", etag.to_string()));
+            request.dispatch().status()
+        };
+        assert_eq!(cached("/space/7/final", &etag), Status::NotModified);
+        assert_eq!(cached("/space/7/easiness", &etag), Status::Ok);
+        post(&client, "/space/7/task", "");
+        assert_eq!(cached("/space/7/final", &etag), Status::Ok);
+        assert_eq!(cached("/space/8/final", &etag), Status::NotFound);
+
+        let past = client.get("/space/7/importance?at=10").dispatch();
+        let past = past.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(past, "\"7-3-importance-at-10\"");
+        assert_eq!(
+            cached("/space/7/importance?at=10", &past),
+            Status::NotModified
+        );
+    }
+}
@@ -0,0 +1,348 @@
+//! Timestamped copies of each space, so a bad save can be undone.
+//!
+//! Each snapshot is a file in the space's `space-{id}` directory, named
+//! after the unix time it was taken, or the next second free if there
+//! already is one from that second. Old snapshots of a space are rotated
+//! away, keeping its newest snapshot of each of the last few days and weeks.
+//! Snapshots are encoded with the same `Codec` as the spaces' storage.
+
+use crate::autosave::Autosave;
+use crate::encryption::Codec;
+use crate::journal::write_atomic;
+use model::api::SnapshotInfo;
+use model::{SimpleAthensSpace, SpaceId, Timestamp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const EXTENSION: &str = "json";
+
+pub struct Snapshots {
+    dir: PathBuf,
+    keep_daily: usize,
+    keep_weekly: usize,
+    codec: Arc<Codec>,
+    /// Held while picking the name of a new snapshot and rotating old ones.
+    taking: Mutex<()>,
+}
+
+impl Snapshots {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        keep_daily: usize,
+        keep_weekly: usize,
+        codec: Arc<Codec>,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            keep_daily,
+            keep_weekly,
+            codec,
+            taking: Mutex::new(()),
+        })
+    }
+
+    fn space_dir(&self, id: SpaceId) -> PathBuf {
+        self.dir.join(format!("space-{}", id.0))
+    }
+
+    fn path(&self, id: SpaceId, at: Timestamp) -> PathBuf {
+        self.space_dir(id).join(format!("{}.{}", at.0, EXTENSION))
+    }
+
+    /// Writes a snapshot of the space and rotates out its ones no longer
+    /// needed. Other snapshots are never overwritten, like the one a restore
+    /// is from.
+    pub fn take(&self, space: &SimpleAthensSpace, at: Timestamp) -> io::Result<SnapshotInfo> {
+        let id = space.id();
+        let document = self.codec.encode(space);
+        let _taking = self.taking.lock().unwrap();
+        std::fs::create_dir_all(self.space_dir(id))?;
+        let mut at = at;
+        while self.path(id, at).exists() {
+            at = Timestamp(at.0 + 1);
+        }
+        write_atomic(&self.path(id, at), document.as_bytes())?;
+        let taken: Vec<Timestamp> = self.list(id)?.iter().map(|s| s.at).collect();
+        let keep = to_keep(&taken, self.keep_daily, self.keep_weekly);
+        for at in taken.into_iter().filter(|at| !keep.contains(at)) {
+            std::fs::remove_file(self.path(id, at))?;
+        }
+        Ok(SnapshotInfo {
+            at,
+            bytes: document.len() as u64,
+        })
+    }
+
+    /// The spaces that have snapshots.
+    fn spaces(&self) -> io::Result<Vec<SpaceId>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let id = name.to_str().and_then(|n| n.strip_prefix("space-"));
+            if let Some(id) = id.and_then(|id| id.parse().ok()) {
+                ids.push(SpaceId(id));
+            }
+        }
+        Ok(ids)
+    }
+
+    /// All snapshots of the space, newest first.
+    pub fn list(&self, id: SpaceId) -> io::Result<Vec<SnapshotInfo>> {
+        let entries = match std::fs::read_dir(self.space_dir(id)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut snapshots = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension() != Some(EXTENSION.as_ref()) {
+                continue;
+            }
+            let at = path.file_stem().and_then(|s| s.to_str()?.parse().ok());
+            if let Some(at) = at {
+                snapshots.push(SnapshotInfo {
+                    at: Timestamp(at),
+                    bytes: entry.metadata()?.len(),
+                });
+            }
+        }
+        snapshots.sort_unstable_by_key(|s| std::cmp::Reverse(s.at));
+        Ok(snapshots)
+    }
+
+    /// The snapshot of the space taken at `at`, if there is one.
+    pub fn read(&self, id: SpaceId, at: Timestamp) -> io::Result<Option<SimpleAthensSpace>> {
+        match std::fs::read_to_string(self.path(id, at)) {
+            Ok(document) => self.codec.decode(id, &document).map(Some),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Rewrites snapshots not encoded as the codec would now, e.g. after a
+    /// key rotation. Returns how many were rewritten.
+    pub fn reencode(&self) -> io::Result<usize> {
+        let mut count = 0;
+        for id in self.spaces()? {
+            for snapshot in self.list(id)? {
+                let path = self.path(id, snapshot.at);
+                let document = std::fs::read_to_string(&path)?;
+                if !self.codec.is_current(id, &document) {
+                    let space = self.codec.decode_migrating(id, &document)?;
+                    write_atomic(&path, self.codec.encode(&space).as_bytes())?;
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Snapshots the spaces every `every` until the server shuts down,
+    /// skipping spaces when nothing was saved to them since their last one.
+    pub fn spawn(self: &Arc<Self>, autosave: Arc<Autosave>, every: Duration) {
+        let snapshots = self.clone();
+        rocket::tokio::spawn(async move {
+            let mut last_versions = BTreeMap::new();
+            loop {
+                rocket::tokio::time::sleep(every).await;
+                let changed: Vec<SimpleAthensSpace> = autosave
+                    .lock()
+                    .loaded()
+                    .filter(|s| last_versions.get(&s.id()) != Some(&s.version()))
+                    .cloned()
+                    .collect();
+                for space in changed {
+                    match snapshots.take(&space, crate::now()) {
+                        Ok(_) => {
+                            last_versions.insert(space.id(), space.version());
+                        }
+                        Err(e) => log::error!("Snapshot of space {} failed: {:?}", space.id().0, e),
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// The snapshots to keep: the newest overall, and the newest of each of the
+/// last `daily` days and `weekly` weeks that have any.
+fn to_keep(taken: &[Timestamp], daily: usize, weekly: usize) -> BTreeSet<Timestamp> {
+    let mut newest_first = taken.to_vec();
+    newest_first.sort_unstable_by(|a, b| b.cmp(a));
+    let mut keep: BTreeSet<_> = newest_first.first().copied().into_iter().collect();
+    for (count, days) in [(daily, 1), (weekly, 7)] {
+        // The epoch was a Thursday, shift it so weeks start on Monday.
+        let period = |t: &Timestamp| (t.0 / SECONDS_PER_DAY + 3) / days;
+        let mut periods = BTreeSet::new();
+        for t in newest_first.iter() {
+            if periods.len() == count && !periods.contains(&period(t)) {
+                break;
+            }
+            if periods.insert(period(t)) {
+                keep.insert(*t);
+            }
+        }
+    }
+    keep
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("athens-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn keeps_newest_per_day_and_week() {
+        let hour = 60 * 60;
+        let day = 24 * hour;
+        // 2026-09-07 was a Monday.
+        let monday = Timestamp::from_date(2026, 9, 7).unwrap().0;
+        let taken: Vec<Timestamp> = [
+            monday - 8 * day,
+            monday - 7 * day,
+            monday - day,
+            monday,
+            monday + hour,
+            monday + day,
+            monday + day + hour,
+            monday + 2 * day,
+        ]
+        .into_iter()
+        .map(Timestamp)
+        .collect();
+        let keep = |daily, weekly| -> Vec<u64> {
+            let keep = to_keep(&taken, daily, weekly);
+            keep.into_iter().map(|t| t.0).collect()
+        };
+        assert_eq!(keep(0, 0), vec![monday + 2 * day]);
+        assert_eq!(keep(2, 0), vec![monday + day + hour, monday + 2 * day]);
+        assert_eq!(
+            keep(0, 3),
+            vec![monday - 8 * day, monday - day, monday + 2 * day]
+        );
+        assert_eq!(keep(100, 100).len(), 6);
+    }
+
+    const ID: SpaceId = SpaceId(0);
+
+    fn space(alias: &str) -> SimpleAthensSpace {
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = alias.to_string();
+        space
+    }
+
+    #[test]
+    fn take_list_and_read() {
+        let dir = temp_dir("snapshots");
+        let snapshots = Snapshots::open(&dir, 2, 0, Arc::default()).unwrap();
+        snapshots.take(&space("one"), Timestamp(10)).unwrap();
+        snapshots.take(&space("two"), Timestamp(20)).unwrap();
+        // Another day, so the first day keeps only its newest.
+        snapshots
+            .take(&space("three"), Timestamp(SECONDS_PER_DAY))
+            .unwrap();
+        let taken: Vec<Timestamp> = snapshots.list(ID).unwrap().iter().map(|s| s.at).collect();
+        assert_eq!(taken, vec![Timestamp(SECONDS_PER_DAY), Timestamp(20)]);
+        assert_eq!(
+            snapshots.read(ID, Timestamp(20)).unwrap(),
+            Some(space("two"))
+        );
+        assert_eq!(snapshots.read(ID, Timestamp(10)).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_in_the_same_second_are_kept() {
+        let dir = temp_dir("snapshots-same-second");
+        let snapshots = Snapshots::open(&dir, 2, 0, Arc::default()).unwrap();
+        snapshots.take(&space("one"), Timestamp(10)).unwrap();
+        let info = snapshots.take(&space("two"), Timestamp(10)).unwrap();
+        assert_eq!(info.at, Timestamp(11));
+        assert_eq!(
+            snapshots.read(ID, Timestamp(11)).unwrap(),
+            Some(space("two"))
+        );
+        // The first was only rotated out, as the day has a newer one.
+        assert_eq!(snapshots.read(ID, Timestamp(10)).unwrap(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn spaces_have_their_own_snapshots() {
+        let dir = temp_dir("snapshots-per-space");
+        let snapshots = Snapshots::open(&dir, 0, 0, Arc::default()).unwrap();
+        let mut other = SimpleAthensSpace::with_id(SpaceId(1));
+        other.new_task().text = "other".to_string();
+        snapshots.take(&space("one"), Timestamp(10)).unwrap();
+        snapshots.take(&other, Timestamp(20)).unwrap();
+        // Rotating the other space's snapshots leaves these alone.
+        snapshots.take(&other, Timestamp(30)).unwrap();
+        assert_eq!(
+            snapshots.read(ID, Timestamp(10)).unwrap(),
+            Some(space("one"))
+        );
+        assert_eq!(snapshots.read(ID, Timestamp(30)).unwrap(), None);
+        let taken: Vec<Timestamp> = snapshots
+            .list(SpaceId(1))
+            .unwrap()
+            .iter()
+            .map(|s| s.at)
+            .collect();
+        assert_eq!(taken, [Timestamp(30)]);
+        assert_eq!(snapshots.list(SpaceId(2)).unwrap(), []);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_snapshots_all_succeed() {
+        let dir = temp_dir("snapshots-concurrent");
+        let snapshots = Arc::new(Snapshots::open(&dir, 0, 0, Arc::default()).unwrap());
+        let takes: Vec<_> = (0..8)
+            .map(|i| {
+                let snapshots = snapshots.clone();
+                std::thread::spawn(move || snapshots.take(&space("one"), Timestamp(i)))
+            })
+            .collect();
+        for take in takes {
+            take.join().unwrap().unwrap();
+        }
+        assert_eq!(snapshots.list(ID).unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reencode_rotates_keys() {
+        let dir = temp_dir("snapshots-rotate");
+        let old_key = "b2xkLWtleS1vbGQta2V5LW9sZC1rZXktb2xkLWtleSE=";
+        let new_key = "bmV3LWtleS1uZXcta2V5LW5ldy1rZXktbmV3LWtleSE=";
+        let old = Arc::new(Codec::new([old_key]).unwrap());
+        let snapshots = Snapshots::open(&dir, 2, 0, old).unwrap();
+        snapshots.take(&space("secret"), Timestamp(10)).unwrap();
+
+        let rotated = Arc::new(Codec::new([new_key, old_key]).unwrap());
+        let snapshots = Snapshots::open(&dir, 2, 0, rotated).unwrap();
+        assert_eq!(snapshots.reencode().unwrap(), 1);
+        assert_eq!(snapshots.reencode().unwrap(), 0);
+
+        let new = Arc::new(Codec::new([new_key]).unwrap());
+        let snapshots = Snapshots::open(&dir, 2, 0, new).unwrap();
+        assert_eq!(
+            snapshots.read(ID, Timestamp(10)).unwrap(),
+            Some(space("secret"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+//! Where spaces are kept between restarts.
+//!
+//! The implementation is chosen by the `storage` config value:
+//!
+//! - `"fs"`, the default, keeps a crash safe journal per space under
+//!   `journal_dir`.
+//! - `"sqlite"` keeps every space in the database at `sqlite_path`.
+//! - `"memory"` forgets everything on shutdown, for tests.
+//!
+//! Durable storage encodes spaces with the configured `Codec`.
+
+use crate::encryption::Codec;
+use crate::journal::Journal;
+use model::{SimpleAthensSpace, SpaceId};
+use rocket::figment::Figment;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub trait Storage: Send {
+    fn load(&mut self, id: SpaceId) -> io::Result<Option<SimpleAthensSpace>>;
+    /// Durably stores the space under its id, replacing the previous one.
+    fn save(&mut self, space: &SimpleAthensSpace) -> io::Result<()>;
+    fn list(&mut self) -> io::Result<Vec<SpaceId>>;
+    /// Rewrites spaces not encoded as the codec would now, e.g. after a key
+    /// rotation. Returns how many were rewritten.
+    fn reencode(&mut self) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Opens the storage selected in the config.
+pub fn open(figment: &Figment, codec: Arc<Codec>) -> io::Result<Box<dyn Storage>> {
+    let kind: String = figment
+        .extract_inner("storage")
+        .unwrap_or_else(|_| "fs".to_string());
+    match kind.as_str() {
+        "fs" => {
+            let dir: String = figment
+                .extract_inner("journal_dir")
+                .unwrap_or_else(|_| "data".to_string());
+            let compact_every = figment.extract_inner("compact_every").unwrap_or(100);
+            Ok(Box::new(FsStorage::open(dir, compact_every, codec)?))
+        }
+        "sqlite" => {
+            let path: String = figment
+                .extract_inner("sqlite_path")
+                .unwrap_or_else(|_| "data/athens.sqlite3".to_string());
+            Ok(Box::new(SqliteStorage::open(path, codec)?))
+        }
+        "memory" => Ok(Box::new(MemoryStorage::default())),
+        _ => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown storage {:?}", kind),
+        )),
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    spaces: BTreeMap<SpaceId, SimpleAthensSpace>,
+}
+
+impl Storage for MemoryStorage {
+    fn load(&mut self, id: SpaceId) -> io::Result<Option<SimpleAthensSpace>> {
+        Ok(self.spaces.get(&id).cloned())
+    }
+    fn save(&mut self, space: &SimpleAthensSpace) -> io::Result<()> {
+        self.spaces.insert(space.id(), space.clone());
+        Ok(())
+    }
+    fn list(&mut self) -> io::Result<Vec<SpaceId>> {
+        Ok(self.spaces.keys().copied().collect())
+    }
+}
+
+/// A journal per space, each in a directory named `space-{id}`.
+pub struct FsStorage {
+    dir: PathBuf,
+    compact_every: usize,
+    journals: BTreeMap<SpaceId, Journal>,
+    codec: Arc<Codec>,
+}
+
+impl FsStorage {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        compact_every: usize,
+        codec: Arc<Codec>,
+    ) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let mut storage = Self {
+            dir,
+            compact_every,
+            journals: BTreeMap::new(),
+            codec,
+        };
+        storage.migrate_single_journal()?;
+        Ok(storage)
+    }
+    /// Before each space had a journal, the only space's journal was in
+    /// `dir` itself. It becomes the default space's, unless that already
+    /// has saves, in which case it is set aside in `single-journal`.
+    fn migrate_single_journal(&mut self) -> io::Result<()> {
+        let dir = self.dir.clone();
+        let old_files = || -> io::Result<Vec<PathBuf>> {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                let journaled = name.starts_with("journal-") || name.starts_with("snapshot-");
+                if journaled && entry.file_type()?.is_file() {
+                    files.push(entry.path());
+                }
+            }
+            Ok(files)
+        };
+        if old_files()?.is_empty() {
+            return Ok(());
+        }
+        let latest = Journal::open(&dir, self.compact_every)?
+            .latest()
+            .map(str::to_string);
+        let journal = self.journal(crate::DEFAULT_SPACE)?;
+        match latest {
+            Some(latest) if journal.latest().is_none() => {
+                log::info!("Moving the journal in {:?} to the default space", dir);
+                // Encoded as it was, `reencode` encrypts it if need be.
+                journal.append(&latest)?;
+            }
+            Some(_) => {
+                let aside = dir.join("single-journal");
+                log::warn!(
+                    "The default space has saves, moving the old journal to {:?}",
+                    aside
+                );
+                std::fs::create_dir_all(&aside)?;
+                for path in old_files()? {
+                    std::fs::rename(&path, aside.join(path.file_name().unwrap()))?;
+                }
+                return Ok(());
+            }
+            None => {}
+        }
+        for path in old_files()? {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+    fn journal(&mut self, id: SpaceId) -> io::Result<&mut Journal> {
+        if !self.journals.contains_key(&id) {
+            let dir = self.dir.join(format!("space-{}", id.0));
+            let journal = Journal::open(dir, self.compact_every)?;
+            self.journals.insert(id, journal);
+        }
+        Ok(self.journals.get_mut(&id).unwrap())
+    }
+}
+
+impl Storage for FsStorage {
+    fn load(&mut self, id: SpaceId) -> io::Result<Option<SimpleAthensSpace>> {
+        if !self.list()?.contains(&id) {
+            return Ok(None);
+        }
+        let codec = self.codec.clone();
+        self.journal(id)?
+            .latest()
+            .map(|d| codec.decode(id, d))
+            .transpose()
+    }
+    fn save(&mut self, space: &SimpleAthensSpace) -> io::Result<()> {
+        let document = self.codec.encode(space);
+        self.journal(space.id())?.append(&document)
+    }
+    fn list(&mut self) -> io::Result<Vec<SpaceId>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let id = name.to_str().and_then(|n| n.strip_prefix("space-"));
+            if let Some(id) = id.and_then(|id| id.parse().ok()) {
+                ids.push(SpaceId(id));
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+    fn reencode(&mut self) -> io::Result<usize> {
+        let mut count = 0;
+        for id in self.list()? {
+            let codec = self.codec.clone();
+            let journal = self.journal(id)?;
+            if let Some(latest) = journal.latest().filter(|d| !codec.is_current(id, d)) {
+                let space = codec.decode_migrating(id, latest)?;
+                journal.append(&codec.encode(&space))?;
+                // Older records are gone once the journal compacts.
+                journal.compact()?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+pub struct SqliteStorage {
+    conn: Connection,
+    codec: Arc<Codec>,
+}
+
+fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+impl SqliteStorage {
+    pub fn open(path: impl Into<PathBuf>, codec: Arc<Codec>) -> io::Result<Self> {
+        let path = path.into();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::from_connection(Connection::open(path).map_err(sqlite_error)?, codec)
+    }
+    #[cfg(test)]
+    pub fn open_in_memory(codec: Arc<Codec>) -> io::Result<Self> {
+        Self::from_connection(Connection::open_in_memory().map_err(sqlite_error)?, codec)
+    }
+    fn from_connection(conn: Connection, codec: Arc<Codec>) -> io::Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS spaces (
+                id INTEGER PRIMARY KEY,
+                document TEXT NOT NULL
+            );",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self { conn, codec })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self, id: SpaceId) -> io::Result<Option<SimpleAthensSpace>> {
+        let document: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT document FROM spaces WHERE id = ?",
+                params![id.0 as i64],
+                |r| r.get(0),
+            )
+            .optional()
+            .map_err(sqlite_error)?;
+        document.map(|d| self.codec.decode(id, &d)).transpose()
+    }
+    fn save(&mut self, space: &SimpleAthensSpace) -> io::Result<()> {
+        self.conn
+            .execute(
+                "INSERT OR REPLACE INTO spaces (id, document) VALUES (?, ?)",
+                params![space.id().0 as i64, self.codec.encode(space)],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+    fn list(&mut self) -> io::Result<Vec<SpaceId>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id FROM spaces ORDER BY id")
+            .map_err(sqlite_error)?;
+        let ids = stmt
+            .query_map([], |r| Ok(SpaceId(r.get::<_, i64>(0)? as usize)))
+            .map_err(sqlite_error)?;
+        ids.collect::<Result<_, _>>().map_err(sqlite_error)
+    }
+    fn reencode(&mut self) -> io::Result<usize> {
+        let mut stale = Vec::new();
+        for id in self.list()? {
+            let document: String = self
+                .conn
+                .query_row(
+                    "SELECT document FROM spaces WHERE id = ?",
+                    params![id.0 as i64],
+                    |r| r.get(0),
+                )
+                .map_err(sqlite_error)?;
+            if !self.codec.is_current(id, &document) {
+                stale.push(self.codec.decode_migrating(id, &document)?);
+            }
+        }
+        for space in stale.iter() {
+            self.save(space)?;
+        }
+        Ok(stale.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "YXRoZW5zLXN0b3JhZ2UtdGVzdC1rZXktMzItYnl0ZXM=";
+
+    fn round_trips(storage: &mut dyn Storage) {
+        assert_eq!(storage.list().unwrap(), vec![]);
+        assert_eq!(storage.load(SpaceId(0)).unwrap(), None);
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "first".to_string();
+        storage.save(&space).unwrap();
+        space.new_task().text = "second".to_string();
+        storage.save(&space).unwrap();
+        assert_eq!(storage.list().unwrap(), vec![SpaceId(0)]);
+        assert_eq!(storage.load(SpaceId(0)).unwrap(), Some(space));
+        assert_eq!(storage.load(SpaceId(1)).unwrap(), None);
+    }
+
+    #[test]
+    fn memory_storage() {
+        round_trips(&mut MemoryStorage::default());
+    }
+
+    #[test]
+    fn sqlite_storage() {
+        let codec = Arc::new(Codec::default());
+        round_trips(&mut SqliteStorage::open_in_memory(codec).unwrap());
+    }
+
+    #[test]
+    fn encrypted_sqlite_storage() {
+        let codec = Arc::new(Codec::new([KEY]).unwrap());
+        round_trips(&mut SqliteStorage::open_in_memory(codec).unwrap());
+    }
+
+    #[test]
+    fn fs_storage() {
+        let dir = std::env::temp_dir().join(format!("athens-storage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let codec = Arc::new(Codec::default());
+        round_trips(&mut FsStorage::open(&dir, 100, codec.clone()).unwrap());
+        let mut reopened = FsStorage::open(&dir, 100, codec).unwrap();
+        assert_eq!(reopened.list().unwrap(), vec![SpaceId(0)]);
+        assert_eq!(
+            reopened
+                .load(SpaceId(0))
+                .unwrap()
+                .unwrap()
+                .task_ids()
+                .count(),
+            2
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fs_storage_moves_the_single_journal() {
+        let dir = std::env::temp_dir().join(format!("athens-single-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "journaled".to_string();
+        let mut single = Journal::open(&dir, 100).unwrap();
+        single
+            .append(&serde_json::to_string(&space).unwrap())
+            .unwrap();
+        drop(single);
+
+        let codec = Arc::new(Codec::default());
+        let mut storage = FsStorage::open(&dir, 100, codec.clone()).unwrap();
+        assert_eq!(storage.load(SpaceId(0)).unwrap(), Some(space.clone()));
+        assert!(!dir.join("journal-00000000").exists());
+        drop(storage);
+        let mut reopened = FsStorage::open(&dir, 100, codec).unwrap();
+        assert_eq!(reopened.load(SpaceId(0)).unwrap(), Some(space));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn documents_cannot_be_swapped_between_spaces() {
+        let codec = Arc::new(Codec::new([KEY]).unwrap());
+        let mut storage = SqliteStorage::open_in_memory(codec).unwrap();
+        storage
+            .save(&SimpleAthensSpace::with_id(SpaceId(1)))
+            .unwrap();
+        storage
+            .conn
+            .execute(
+                "INSERT INTO spaces (id, document) SELECT 2, document FROM spaces WHERE id = 1",
+                [],
+            )
+            .unwrap();
+        assert!(storage.load(SpaceId(1)).unwrap().is_some());
+        assert!(storage.load(SpaceId(2)).is_err());
+    }
+
+    #[test]
+    fn reencode_encrypts_plaintext_journals() {
+        let dir = std::env::temp_dir().join(format!("athens-reencode-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut space = SimpleAthensSpace::new();
+        space.new_task().text = "plaintext".to_string();
+        let plain = Arc::new(Codec::default());
+        FsStorage::open(&dir, 100, plain)
+            .unwrap()
+            .save(&space)
+            .unwrap();
+
+        let codec = Arc::new(Codec::new([KEY]).unwrap());
+        let mut storage = FsStorage::open(&dir, 100, codec.clone()).unwrap();
+        assert_eq!(storage.reencode().unwrap(), 1);
+        assert_eq!(storage.reencode().unwrap(), 0);
+        drop(storage);
+
+        let journal = Journal::open(dir.join("space-0"), 100).unwrap();
+        assert!(codec.is_current(SpaceId(0), journal.latest().unwrap()));
+        let mut storage = FsStorage::open(&dir, 100, codec).unwrap();
+        assert_eq!(storage.load(SpaceId(0)).unwrap(), Some(space));
+        // Nothing readable is left behind in any file.
+        for entry in std::fs::read_dir(dir.join("space-0")).unwrap() {
+            let bytes = std::fs::read(entry.unwrap().path()).unwrap();
+            assert!(!String::from_utf8_lossy(&bytes).contains("plaintext"));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}